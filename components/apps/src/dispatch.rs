@@ -68,7 +68,16 @@ pub struct DispatchContext {
     se050: Se050Context,
 }
 
+/// Written by the provisioner app once the provisioning is finalized, see
+/// `provisioner_app::LOCK_FILE`.
+const PROVISIONING_LOCK_FILE: &Path = path!("/attn/lock");
+
 fn should_preserve_file(file: &Path) -> bool {
+    // Only test devices can be provisioned again after a factory reset
+    if file == PROVISIONING_LOCK_FILE {
+        return !cfg!(feature = "nk3-test");
+    }
+
     // We save all "special" objects, with an ID that is representable by a `u8`
 
     const DIRS: &[&Path] = &[path!("x5c"), path!("ctr"), path!("sec"), path!("pub")];
@@ -501,9 +510,17 @@ mod tests {
         assert!(should_preserve_file(path!("/attn/sec/01")));
         assert!(should_preserve_file(path!("/attn/sec/02")));
         assert!(should_preserve_file(path!("/attn/sec/03")));
+        assert!(should_preserve_file(path!("/attn/sec/04")));
+        assert!(should_preserve_file(path!("/attn/sec/05")));
         assert!(should_preserve_file(path!("/attn/x5c/01")));
         assert!(should_preserve_file(path!("/attn/x5c/02")));
         assert!(should_preserve_file(path!("/attn/x5c/03")));
+        assert!(should_preserve_file(path!("/attn/x5c/04")));
+        assert!(should_preserve_file(path!("/attn/x5c/05")));
         assert!(!should_preserve_file(path!("/fido/dat/sec/00")));
+        assert_eq!(
+            should_preserve_file(path!("/attn/lock")),
+            !cfg!(feature = "nk3-test")
+        );
     }
 }
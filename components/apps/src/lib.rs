@@ -67,6 +67,9 @@ pub struct Config {
     #[cfg(feature = "piv-authenticator")]
     #[serde(default, rename = "p", skip_serializing_if = "is_default")]
     piv: PivConfig,
+    #[cfg(feature = "ndef-app")]
+    #[serde(default, rename = "n", skip_serializing_if = "is_default")]
+    ndef: NdefConfig,
     #[serde(default, rename = "v", skip_serializing_if = "is_default")]
     fs_version: u32,
     #[cfg(feature = "se050")]
@@ -83,6 +86,8 @@ impl admin_app::Config for Config {
             "opcard" => self.opcard.field(key),
             #[cfg(feature = "piv-authenticator")]
             "piv" => self.piv.field(key),
+            #[cfg(feature = "ndef-app")]
+            "ndef" => self.ndef.field(key),
             _ => None,
         }
     }
@@ -122,6 +127,22 @@ impl admin_app::Config for Config {
                 destructive: false,
                 ty: FieldType::Bool,
             },
+            #[cfg(feature = "ndef-app")]
+            ConfigField {
+                name: "ndef.writable",
+                requires_touch_confirmation: true,
+                requires_reboot: true,
+                destructive: false,
+                ty: FieldType::Bool,
+            },
+            #[cfg(feature = "ndef-app")]
+            ConfigField {
+                name: "ndef.pin",
+                requires_touch_confirmation: true,
+                requires_reboot: true,
+                destructive: false,
+                ty: FieldType::Bool,
+            },
         ]
     }
 
@@ -296,6 +317,30 @@ impl OpcardConfig {
     }
 }
 
+/// Write access to the NDEF file served over NFC, see `ndef_app::Options`.
+///
+/// The URL and the texts of the message are too large for the config file.  They are stored by
+/// the NDEF app itself, see `ndef_app::template`.
+#[cfg(feature = "ndef-app")]
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct NdefConfig {
+    #[serde(default, rename = "w", skip_serializing_if = "is_default")]
+    writable: bool,
+    #[serde(default, rename = "p", skip_serializing_if = "is_default")]
+    pin: bool,
+}
+
+#[cfg(feature = "ndef-app")]
+impl NdefConfig {
+    fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
+        match key {
+            "writable" => Some(ConfigValueMut::Bool(&mut self.writable)),
+            "pin" => Some(ConfigValueMut::Bool(&mut self.pin)),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(feature = "piv-authenticator")]
 impl PivConfig {
     fn field(&mut self, key: &str) -> Option<ConfigValueMut<'_>> {
@@ -358,7 +403,7 @@ type AdminApp<R> = admin_app::App<Client<R>, <R as Runner>::Reboot, AdminStatus,
 #[cfg(feature = "fido-authenticator")]
 type FidoApp<R> = fido_authenticator::Authenticator<fido_authenticator::Conforming, Client<R>>;
 #[cfg(feature = "ndef-app")]
-type NdefApp = ndef_app::App<'static>;
+type NdefApp<R> = ndef_app::App<Client<R>>;
 #[cfg(feature = "secrets-app")]
 type SecretsApp<R> = secrets_app::Authenticator<Client<R>>;
 #[cfg(feature = "opcard")]
@@ -372,6 +417,8 @@ type ProvisionerApp<R> = provisioner_app::Provisioner<<R as Runner>::Store, Clie
 pub enum CustomStatus {
     ReverseHotpSuccess = 0,
     ReverseHotpError = 1,
+    SelfTestLed = 2,
+    SelfTestButton = 3,
 }
 
 impl From<CustomStatus> for u8 {
@@ -387,6 +434,8 @@ impl TryFrom<u8> for CustomStatus {
         match value {
             0 => Ok(Self::ReverseHotpSuccess),
             1 => Ok(Self::ReverseHotpError),
+            2 => Ok(Self::SelfTestLed),
+            3 => Ok(Self::SelfTestButton),
             _ => Err(UnknownStatusError(value)),
         }
     }
@@ -400,7 +449,7 @@ pub struct Apps<R: Runner> {
     #[cfg(feature = "fido-authenticator")]
     fido: Option<FidoApp<R>>,
     #[cfg(feature = "ndef-app")]
-    ndef: NdefApp,
+    ndef: NdefApp<R>,
     #[cfg(feature = "secrets-app")]
     oath: Option<SecretsApp<R>>,
     #[cfg(feature = "opcard")]
@@ -412,9 +461,9 @@ pub struct Apps<R: Runner> {
 }
 
 const CLIENT_COUNT: usize = const {
-    // ndef is not listed here because it does not need a client
     let clients = [
         cfg!(feature = "fido-authenticator"),
+        cfg!(feature = "ndef-app"),
         cfg!(feature = "opcard"),
         cfg!(feature = "piv-authenticator"),
         cfg!(feature = "provisioner-app"),
@@ -569,14 +618,26 @@ impl<R: Runner> Apps<R> {
         #[cfg(feature = "piv-authenticator")]
         let piv = migrated_successfully.then(|| App::new(runner, client_builder, (), &()));
 
+        // the self tests report the status including the errors of the admin app
         #[cfg(feature = "provisioner-app")]
-        let provisioner = App::new(runner, client_builder, provisioner, &());
+        let provisioner = App::new(
+            runner,
+            client_builder,
+            ProvisionerData {
+                init_status,
+                ..provisioner
+            },
+            &(),
+        );
+
+        #[cfg(feature = "ndef-app")]
+        let ndef = App::new(runner, client_builder, (), &admin.config().ndef);
 
         Self {
             #[cfg(feature = "fido-authenticator")]
             fido,
             #[cfg(feature = "ndef-app")]
-            ndef: NdefApp::new(),
+            ndef,
             #[cfg(feature = "secrets-app")]
             oath,
             #[cfg(feature = "opcard")]
@@ -589,6 +650,12 @@ impl<R: Runner> Apps<R> {
         }
     }
 
+    /// Reports the lock state of the provisioner app in the admin status.
+    #[cfg(feature = "provisioner-app")]
+    fn update_provisioning_status(&mut self) {
+        self.admin.status_mut().provisioning_locked = self.provisioner.is_locked();
+    }
+
     fn admin_app<P: Platform>(
         runner: &R,
         trussed_service: &mut Service<P, Dispatch<R::Twi, R::Se050Timer>>,
@@ -712,6 +779,9 @@ impl<R: Runner> Apps<R> {
     where
         F: FnOnce(&mut [&mut dyn ApduApp]) -> T,
     {
+        #[cfg(feature = "provisioner-app")]
+        self.update_provisioning_status();
+
         let mut apps: Vec<&mut dyn ApduApp, 7> = Default::default();
 
         // App 1: ndef
@@ -756,6 +826,9 @@ impl<R: Runner> Apps<R> {
     where
         F: FnOnce(&mut [&mut dyn CtaphidApp<'static>]) -> T,
     {
+        #[cfg(feature = "provisioner-app")]
+        self.update_provisioning_status();
+
         let mut apps: Vec<&mut dyn CtaphidApp<'static>, 4> = Default::default();
 
         #[cfg(feature = "fido-authenticator")]
@@ -906,15 +979,23 @@ impl<R: Runner> AdminData<R> {
     }
 }
 
+#[cfg(not(feature = "provisioner-app"))]
+const ADMIN_STATUS_LEN: usize = 5;
+// provisioner builds append the provisioning lock state
+#[cfg(feature = "provisioner-app")]
+const ADMIN_STATUS_LEN: usize = 6;
+
 pub struct AdminStatus {
     init_status: InitStatus,
     ifs_blocks: u8,
     efs_blocks: u16,
     variant: Variant,
+    #[cfg(feature = "provisioner-app")]
+    provisioning_locked: bool,
 }
 
 impl admin_app::StatusBytes for AdminStatus {
-    type Serialized = [u8; 5];
+    type Serialized = [u8; ADMIN_STATUS_LEN];
     fn set_random_error(&mut self, value: bool) {
         self.init_status.set(InitStatus::RNG_ERROR, value);
     }
@@ -922,15 +1003,21 @@ impl admin_app::StatusBytes for AdminStatus {
         self.init_status.contains(InitStatus::RNG_ERROR)
     }
 
-    fn serialize(&self) -> [u8; 5] {
+    fn serialize(&self) -> [u8; ADMIN_STATUS_LEN] {
         let efs_blocks = self.efs_blocks.to_be_bytes();
-        [
+        let mut status = [0; ADMIN_STATUS_LEN];
+        status[..5].copy_from_slice(&[
             self.init_status.bits(),
             self.ifs_blocks,
             efs_blocks[0],
             efs_blocks[1],
             self.variant.into(),
-        ]
+        ]);
+        #[cfg(feature = "provisioner-app")]
+        {
+            status[5] = self.provisioning_locked.into();
+        }
+        status
     }
 }
 
@@ -941,6 +1028,8 @@ impl<R: Runner> AdminData<R> {
             ifs_blocks: self.ifs_blocks,
             efs_blocks: self.efs_blocks,
             variant: self.variant,
+            #[cfg(feature = "provisioner-app")]
+            provisioning_locked: false,
         }
     }
 }
@@ -1043,6 +1132,35 @@ impl<R: Runner> App<R> for FidoApp<R> {
     }
 }
 
+#[cfg(feature = "ndef-app")]
+impl<R: Runner> App<R> for NdefApp<R> {
+    const CLIENT_ID: &'static Path = path!("ndef");
+
+    type Data = ();
+    type Config = NdefConfig;
+
+    fn with_client(runner: &R, trussed: Client<R>, _: (), config: &NdefConfig) -> Self {
+        let options = ndef_app::Options {
+            uuid: runner.uuid(),
+            writable: config.writable,
+            pin_required: config.pin,
+        };
+        Self::new(trussed, options)
+    }
+
+    fn channel() -> &'static TrussedChannel {
+        static CHANNEL: TrussedChannel = TrussedChannel::new();
+        &CHANNEL
+    }
+
+    fn backends(runner: &R, _: &NdefConfig) -> &'static [BackendId<Backend>] {
+        const BACKENDS_NDEF: &[BackendId<Backend>] =
+            &[BackendId::Custom(Backend::Auth), BackendId::Core];
+        let _ = runner;
+        BACKENDS_NDEF
+    }
+}
+
 #[cfg(feature = "secrets-app")]
 impl<R: Runner> App<R> for SecretsApp<R> {
     const CLIENT_ID: &'static Path = path!("secrets");
@@ -1196,6 +1314,14 @@ impl<R: Runner> App<R> for PivApp<R> {
 pub struct ProvisionerData<R: Runner> {
     pub store: R::Store,
     pub rebooter: fn() -> !,
+    pub variant: Variant,
+    pub version: Version,
+    pub init_status: InitStatus,
+    /// JEDEC ID of the external flash, if it was queried during the initialization
+    pub ext_flash_jedec_id: Option<[u8; 3]>,
+    pub has_nfc: bool,
+    /// registers of the NFC chip after the initialization, if it was detected
+    pub nfc_registers: Option<[u8; 13]>,
 }
 
 #[cfg(feature = "provisioner-app")]
@@ -1207,7 +1333,36 @@ impl<R: Runner> App<R> for ProvisionerApp<R> {
 
     fn with_client(runner: &R, trussed: Client<R>, data: Self::Data, _: &()) -> Self {
         let uuid = runner.uuid();
-        Self::new(trussed, data.store.clone(), uuid, data.rebooter)
+        let device = provisioner_app::DeviceInfo {
+            version: [
+                data.version.major(),
+                data.version.minor(),
+                data.version.patch(),
+            ],
+            variant: data.variant.into(),
+            is_efs_available: runner.is_efs_available(),
+        };
+        let self_test = provisioner_app::SelfTestOptions {
+            hardware: provisioner_app::HardwareStatus {
+                init_status: data.init_status.bits(),
+                ext_flash_jedec_id: data.ext_flash_jedec_id,
+                ext_flash_ok: !data.init_status.intersects(
+                    InitStatus::EXTERNAL_FLASH_ERROR | InitStatus::EXT_FLASH_NEED_REFORMAT,
+                ),
+                has_nfc: data.has_nfc,
+                nfc_registers: data.nfc_registers,
+            },
+            led_status: CustomStatus::SelfTestLed.into(),
+            button_status: CustomStatus::SelfTestButton.into(),
+        };
+        Self::new(
+            trussed,
+            data.store.clone(),
+            uuid,
+            data.rebooter,
+            device,
+            self_test,
+        )
     }
 
     fn channel() -> &'static TrussedChannel {
@@ -1223,6 +1378,8 @@ impl<R: Runner> App<R> for ProvisionerApp<R> {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "ndef-app")]
+    use super::NdefConfig;
     #[cfg(feature = "opcard")]
     use super::OpcardConfig;
     #[cfg(feature = "piv-authenticator")]
@@ -1244,6 +1401,11 @@ mod tests {
             },
             #[cfg(feature = "piv-authenticator")]
             piv: PivConfig { disabled: true },
+            #[cfg(feature = "ndef-app")]
+            ndef: NdefConfig {
+                writable: true,
+                pin: true,
+            },
             fs_version: 1,
             #[cfg(feature = "se050")]
             se050_backend_configured_version: 1,
@@ -4,6 +4,8 @@ use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
 use littlefs2::{driver::Storage, io::Error};
 use spi_memory::{BlockDevice, Read};
 
+use crate::self_test;
+
 struct FlashProperties {
     size: usize,
     jedec: [u8; 3],
@@ -132,7 +134,11 @@ where
             };
         }
 
-        doraw!([0x9f, 0, 0, 0], 4, "JEDEC {}");
+        let mut jedec = [0x9f, 0, 0, 0];
+        Self::raw_command(spim, cs, &mut jedec);
+        trace!("JEDEC {}", delog::hex_str!(&jedec[1..]));
+        self_test::record_ext_flash_jedec_id([jedec[1], jedec[2], jedec[3]]);
+
         doraw!([0x05, 0], 2, "RDSRl {}");
         doraw!([0x35, 0], 2, "RDSRh {}");
     }
@@ -219,7 +219,16 @@ pub fn init_apps<B: Board>(
         let store = store.clone();
         let rebooter: fn() -> ! = B::Soc::reboot_to_firmware_update;
 
-        apps::ProvisionerData { store, rebooter }
+        apps::ProvisionerData {
+            store,
+            rebooter,
+            variant: B::Soc::VARIANT,
+            version,
+            init_status,
+            ext_flash_jedec_id: crate::self_test::ext_flash_jedec_id(),
+            has_nfc: B::HAS_NFC,
+            nfc_registers: crate::self_test::nfc_registers(),
+        }
     };
 
     let runner = Runner {
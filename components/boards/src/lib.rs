@@ -8,6 +8,7 @@ use cortex_m_rt::ExceptionFrame;
 pub mod flash;
 pub mod init;
 pub mod runtime;
+pub mod self_test;
 pub mod soc;
 pub mod store;
 pub mod ui;
@@ -33,7 +34,7 @@ use trussed::platform::{Platform, Syscall};
 
 use crate::{
     soc::{Soc, Uuid},
-    store::RunnerStore,
+    store::{snapshot::Secret, RunnerStore},
     ui::{buttons::UserPresence, rgb_led::RgbLed, UserInterface},
 };
 
@@ -74,9 +75,38 @@ pub trait Board {
         ifs_alloc: &mut Allocation<Self::InternalStorage>,
         efs_storage: &mut Self::ExternalStorage,
     ) -> LfsResult<()> {
-        let _ = (ifs_alloc, efs_storage);
+        if store::restore_ifs_snapshot::<Self>(ifs_storage, ifs_alloc, efs_storage) {
+            return Ok(());
+        }
         Filesystem::format(ifs_storage)
     }
+
+    /// secret for the IFS snapshots in the external flash (see `store::snapshot`),
+    /// `None` => no snapshots
+    ///
+    /// The snapshots contain all secrets of the IFS, thus they are only written if the
+    /// board has a device-unique secret, which never leaves the device.
+    fn ifs_snapshot_secret(ifs_storage: &mut Self::InternalStorage) -> Option<Secret> {
+        let _ = ifs_storage;
+        None
+    }
+
+    /// the spare area of the external flash is in use otherwise (e.g., by an unfinished
+    /// migration), thus no IFS snapshots may be written into it
+    fn is_spare_reserved(efs_storage: &mut Self::ExternalStorage) -> bool {
+        let _ = efs_storage;
+        false
+    }
+
+    /// finish an interrupted recovery of the IFS (e.g., after a power loss), called on every boot
+    fn resume_ifs(
+        ifs_storage: &mut Self::InternalStorage,
+        ifs_alloc: &mut Allocation<Self::InternalStorage>,
+        efs_storage: &mut Self::ExternalStorage,
+    ) -> LfsResult<()> {
+        let _ = (ifs_storage, ifs_alloc, efs_storage);
+        Ok(())
+    }
 }
 
 pub struct Runner<B> {
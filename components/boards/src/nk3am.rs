@@ -1,3 +1,7 @@
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use lfs_backup::{BackupKey, SALT_SIZE};
 use littlefs2::{
     fs::{Allocation, Filesystem},
     io::Result as LfsResult,
@@ -7,6 +11,7 @@ use nfc_device::traits::nfc::{Device as NfcDevice, Error as NfcError, State as N
 use nrf52840_hal::{
     gpio::{p0, p1, Level, Output, Pin, PushPull},
     gpiote::Gpiote,
+    rng::Rng,
     spim, twim, Spim,
 };
 use nrf52840_pac::{FICR, GPIOTE, P0, P1, POWER, PWM0, PWM1, PWM2, SPIM3};
@@ -21,6 +26,7 @@ use {
 use crate::{
     flash::ExtFlashStorage,
     soc::nrf52::{flash::FlashStorage, rtic_monotonic::RtcMonotonic, Nrf52, UsbClockType},
+    store::{self, snapshot::Secret},
     ui::UserInterface,
     Board,
 };
@@ -87,6 +93,7 @@ impl Board for NK3AM {
                 ifs_alloc,
                 ifs_storage,
                 efs_storage,
+                Some(backup_key()),
             );
             // migration went fine => use its resulting IFS
             if let Ok(()) = mounted_ifs {
@@ -101,9 +108,76 @@ impl Board for NK3AM {
             info_now!("recovering from journal");
             // IFS and old-IFS cannot be mounted, try to recover from journal
             ifs_storage.recover_from_journal();
+            // journal recovery failed => restore the newest IFS snapshot
+            if !Filesystem::is_mountable(ifs_storage) {
+                store::restore_ifs_snapshot::<Self>(ifs_storage, ifs_alloc, efs_storage);
+            }
             Ok(())
         }
     }
+
+    fn resume_ifs(
+        ifs_storage: &mut Self::InternalStorage,
+        ifs_alloc: &mut Allocation<Self::InternalStorage>,
+        efs_storage: &mut Self::ExternalStorage,
+    ) -> LfsResult<()> {
+        match ftl_journal::resume(ifs_alloc, ifs_storage, efs_storage, Some(backup_key())) {
+            Ok(true) => {
+                info_now!("interrupted migration finished");
+                Ok(())
+            }
+            Ok(false) => Ok(()),
+            Err(_) => {
+                error_now!("failed to finish interrupted migration");
+                Err(littlefs2::io::Error::IO)
+            }
+        }
+    }
+
+    fn ifs_snapshot_secret(_ifs_storage: &mut Self::InternalStorage) -> Option<Secret> {
+        Some(store_keys().snapshot_secret)
+    }
+
+    // the migration uses the same spare area as the IFS snapshots
+    fn is_spare_reserved(efs_storage: &mut Self::ExternalStorage) -> bool {
+        ftl_journal::is_pending(efs_storage, Some(backup_key()))
+    }
+}
+
+/// keys for the IFS backups in the ext. flash, derived from the hardware key, see
+/// `init_store_keys`
+#[derive(Clone, Copy)]
+struct StoreKeys {
+    snapshot_secret: Secret,
+    migration_key: [u8; 16],
+    // a migration backup is written at most once per boot
+    migration_salt: [u8; SALT_SIZE],
+}
+
+static STORE_KEYS: Mutex<Cell<Option<StoreKeys>>> = Mutex::new(Cell::new(None));
+
+/// derive the keys for the IFS snapshots and the migration backup from the hardware
+/// key, has to be called before `store::init_store`
+pub fn init_store_keys(ficr: &FICR, rng: &mut Rng) {
+    let hw_key = hw_key(ficr);
+    let mut migration_salt = [0; SALT_SIZE];
+    rng.random(&mut migration_salt);
+    let keys = StoreKeys {
+        snapshot_secret: store::derive_key(&hw_key, b"ifs-snapshot"),
+        migration_key: store::derive_key(&hw_key, b"migration"),
+        migration_salt,
+    };
+    interrupt::free(|cs| STORE_KEYS.borrow(cs).set(Some(keys)));
+}
+
+fn store_keys() -> StoreKeys {
+    interrupt::free(|cs| STORE_KEYS.borrow(cs).get()).expect("store keys not initialized")
+}
+
+/// key for the migration backup of the IFS in the ext. flash
+fn backup_key() -> BackupKey {
+    let keys = store_keys();
+    BackupKey::derive(&keys.migration_key, keys.migration_salt)
 }
 
 pub type InternalFlashStorage =
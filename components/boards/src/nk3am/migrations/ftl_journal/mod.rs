@@ -1,13 +1,20 @@
-pub mod backends;
 pub mod ifs_flash_old;
 
 use littlefs2::fs::{Allocation, Filesystem};
 
-use backends::EFSBackupBackend;
 use ifs_flash_old::FlashStorage as OldFlashStorage;
-use lfs_backup::{BackupBackend, FSBackupError, Result};
+use lfs_backup::{BackupBackend, BackupKey, FSBackupError, Progress, Result};
 
-use crate::nk3am::{ExternalFlashStorage, InternalFlashStorage};
+use crate::{
+    flash::SPARE_LEN,
+    nk3am::{ExternalFlashStorage, InternalFlashStorage},
+    store::backend::StorageBackend,
+};
+
+type EFSBackupBackend<'a> = StorageBackend<'a, ExternalFlashStorage>;
+
+// ext.flash = 2MB, spare for e.g., backup operations = 128kb (at end)
+const SPARE_OFFSET: usize = (2 * 1024 * 1024) - SPARE_LEN;
 
 pub fn migrate(
     old_ifs_storage: &mut OldFlashStorage,
@@ -15,35 +22,89 @@ pub fn migrate(
     ifs_alloc: &mut Allocation<InternalFlashStorage>,
     ifs_storage: &mut InternalFlashStorage,
     efs_storage: &mut ExternalFlashStorage,
+    key: Option<BackupKey>,
 ) -> Result<()> {
     let old_mounted = Filesystem::mount(old_ifs_alloc, old_ifs_storage)
         .map_err(|_| FSBackupError::LittleFs2Err)?;
 
     trace!("old IFS mount success - migrating");
 
-    // ext.flash = 2MB, spare for e.g., backup operations = 128kb (at end)
-    let spare_len = 4096 * 32;
-    let spare_offset = (2 * 1024 * 1024) - spare_len;
-    let mut backend = EFSBackupBackend::new(efs_storage, spare_offset, spare_len);
+    let mut backend = EFSBackupBackend::new(efs_storage, SPARE_OFFSET, SPARE_LEN, key);
 
     backend.erase()?;
 
     trace!("backing: old IFS -> backend");
     backend.backup(&old_mounted)?;
 
-    // only format IFS on failed backup...
+    // never format the IFS without a complete and matching backup
+    trace!("verify: backend <-> old IFS");
+    backend.reset();
+    if !backend.verify(&old_mounted)?.is_equal() {
+        return Err(FSBackupError::VerifyErr);
+    }
+
+    // from here on the backup might be the only copy, see `resume`
+    backend.begin_restore()?;
+
     trace!("backup done, format new IFS");
     let _fmt_ifs = Filesystem::format(ifs_storage);
     ifs_storage.format_journal_blocks();
 
+    restore(ifs_alloc, ifs_storage, &mut backend)
+}
+
+/// finish a migration interrupted (e.g., by a power loss) after the IFS has been
+/// formatted, returns whether there was such a migration
+///
+/// A failed or repeatedly interrupted restore is recorded as `Progress::Failed`
+/// (see `BackupBackend::resume`), the backup is kept as the only copy of the old
+/// IFS but never restored into the IFS in use afterwards.
+pub fn resume(
+    ifs_alloc: &mut Allocation<InternalFlashStorage>,
+    ifs_storage: &mut InternalFlashStorage,
+    efs_storage: &mut ExternalFlashStorage,
+    key: Option<BackupKey>,
+) -> Result<bool> {
+    let mut backend = EFSBackupBackend::new(efs_storage, SPARE_OFFSET, SPARE_LEN, key);
+    match backend.progress()? {
+        Progress::Restoring { .. } | Progress::Restored => {}
+        // the IFS is only formatted once the restore has begun
+        Progress::Incomplete | Progress::Complete | Progress::Failed => return Ok(false),
+    }
+
+    // formatting the IFS was interrupted => start the restore all over
+    if !Filesystem::is_mountable(ifs_storage) {
+        backend.begin_restore()?;
+        let _fmt_ifs = Filesystem::format(ifs_storage);
+        ifs_storage.format_journal_blocks();
+    }
+
+    restore(ifs_alloc, ifs_storage, &mut backend)?;
+    Ok(true)
+}
+
+/// the spare area holds a migration backup which is not done yet (including a failed one,
+/// which is kept as the only copy of the old IFS), thus it must not be overwritten
+pub fn is_pending(efs_storage: &mut ExternalFlashStorage, key: Option<BackupKey>) -> bool {
+    let mut backend = EFSBackupBackend::new(efs_storage, SPARE_OFFSET, SPARE_LEN, key);
+    !matches!(
+        backend.progress(),
+        Ok(Progress::Incomplete | Progress::Restored)
+    )
+}
+
+fn restore(
+    ifs_alloc: &mut Allocation<InternalFlashStorage>,
+    ifs_storage: &mut InternalFlashStorage,
+    backend: &mut EFSBackupBackend<'_>,
+) -> Result<()> {
     let new_mounted =
         Filesystem::mount(ifs_alloc, ifs_storage).map_err(|_| FSBackupError::LittleFs2Err)?;
 
     trace!("restore: backend -> new IFS");
-    backend.reset();
-    let _res = backend.restore(&new_mounted)?;
+    backend.resume(&new_mounted)?;
 
-    // any outcome should erase the external flash contents
+    // only erase the external flash contents once the restore is done
     backend.erase()?;
     Ok(())
 }
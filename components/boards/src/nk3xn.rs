@@ -22,6 +22,8 @@ use se05x::embedded_hal::Hal027;
 use memory_regions::MemoryRegions;
 use utils::OptionalStorage;
 
+#[cfg(not(feature = "no-encrypted-storage"))]
+use crate::store::snapshot::Secret;
 use crate::{flash::ExtFlashStorage, soc::lpc55::Lpc55, Board};
 
 pub mod button;
@@ -78,6 +80,11 @@ impl Board for NK3xN {
 
     const BOARD_NAME: &'static str = "nk3xn";
     const HAS_NFC: bool = true;
+
+    #[cfg(not(feature = "no-encrypted-storage"))]
+    fn ifs_snapshot_secret(ifs_storage: &mut Self::InternalStorage) -> Option<Secret> {
+        ifs_storage.snapshot_secret()
+    }
 }
 
 pub type InternalFlashStorage = InternalFilesystem;
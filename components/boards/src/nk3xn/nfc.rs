@@ -11,6 +11,8 @@ use lpc55_hal::{
 
 use fm11nc08::{Configuration, Register, FM11NC08};
 
+use crate::self_test;
+
 pub type NfcCsPin = pins::Pio1_20;
 pub type NfcIrqPin = pins::Pio0_19;
 
@@ -117,5 +119,7 @@ pub fn try_setup(
     // let regu_powered = (0b11 << 4) | (0b10 << 2) | (0b11 << 0);
     // fm.write_reg(Register::ReguCfg, regu_powered);
 
+    self_test::record_nfc_registers(fm.dump_registers().to_bytes());
+
     Some(fm)
 }
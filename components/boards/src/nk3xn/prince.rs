@@ -11,11 +11,13 @@ use littlefs2::{
 use lpc55_hal::{
     drivers::flash::{FlashGordon, PAGE_SIZE, READ_SIZE, WRITE_SIZE},
     peripherals::prince::{Prince, Region},
-    traits::flash::WriteErase,
+    traits::flash::{Read, WriteErase},
     typestates::init_state::Enabled,
 };
+use rand::RngCore;
 
 use super::MEMORY_REGIONS;
+use crate::store::snapshot::Secret;
 
 // The PRINCE peripheral is described in the LPC55S69 user manual (NXP UM11126):
 // https://www.mouser.com/pdfDocs/NXP_LPC55S6x_UM.pdf
@@ -45,6 +47,8 @@ use super::MEMORY_REGIONS;
 // 3. Our filesystem starts at 0x93_000.  As this is not a multiple of the subregion size 8 kB, we
 //    need to restrict the firmware area to 0x92_000, the start of the subregion that contains
 //    0x93_000.
+// 4. The space between the firmware and the filesystem is encrypted, too.  Its last page holds
+//    the secret for the IFS snapshots, see `InternalFilesystem::snapshot_secret`.
 
 const BLOCK_SIZE: usize = PAGE_SIZE;
 const FLASH_SIZE: usize = 631 * 1024 + 512;
@@ -81,6 +85,15 @@ const PRINCE_REGION2_ENABLE: u32 = {
 };
 const PRINCE_REGION2_DISABLE: u32 = 0;
 
+const SECRET_PAGE: usize = {
+    let page = FS_START - BLOCK_SIZE;
+    let subregion = (FS_START - PRINCE_REGION2_START) / PRINCE_SUBREGION_SIZE;
+    // neither used by the firmware nor by the filesystem, but encrypted by PRINCE
+    assert!(page >= MEMORY_REGIONS.firmware.end);
+    assert!(page >= PRINCE_REGION2_START + subregion * PRINCE_SUBREGION_SIZE);
+    page
+};
+
 pub fn enable(prince: &mut Prince<Enabled>) {
     prince.set_region_enable(Region::Region2, PRINCE_REGION2_ENABLE);
 }
@@ -108,6 +121,44 @@ impl InternalFilesystem {
             prince,
         }
     }
+
+    /// generate the secret for the IFS snapshots unless it exists already
+    pub fn init_snapshot_secret(&mut self, rng: &mut impl RngCore) {
+        if self.snapshot_secret().is_some() {
+            return;
+        }
+        info_now!("generating IFS snapshot secret");
+        let mut page = [0; BLOCK_SIZE];
+        rng.fill_bytes(&mut page[..size_of::<Secret>()]);
+        self.flash_gordon.erase_page(SECRET_PAGE / BLOCK_SIZE).ok();
+        self.prince
+            .write_encrypted(|prince| {
+                with_enabled(prince, || self.flash_gordon.write(SECRET_PAGE, &page))
+            })
+            .ok();
+    }
+
+    /// secret for the IFS snapshots, see `Board::ifs_snapshot_secret`
+    ///
+    /// The secret is stored encrypted by PRINCE, i.e., it is protected like the filesystem.
+    pub fn snapshot_secret(&mut self) -> Option<Secret> {
+        // with PRINCE disabled, the flash controller reads the ciphertext and an undefined
+        // page as zeros, reading an undefined page via the memory map would fault
+        let mut page = [0; BLOCK_SIZE];
+        self.flash_gordon.read(SECRET_PAGE, &mut page);
+        if page == [0; BLOCK_SIZE] {
+            return None;
+        }
+
+        let mut secret = Secret::default();
+        with_enabled(&mut self.prince, || {
+            let flash: *const u8 = SECRET_PAGE as *const u8;
+            for (i, byte) in secret.iter_mut().enumerate() {
+                *byte = unsafe { *flash.add(i) };
+            }
+        });
+        Some(secret)
+    }
 }
 
 impl Storage for InternalFilesystem {
@@ -133,6 +184,7 @@ impl Storage for InternalFilesystem {
     }
 
     fn write(&mut self, off: usize, data: &[u8]) -> Result<usize> {
+        crate::store::count_ifs_write();
         let ret = self.prince.write_encrypted(|prince| {
             with_enabled(prince, || self.flash_gordon.write(FS_START + off, data))
         });
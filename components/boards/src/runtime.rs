@@ -6,7 +6,7 @@ use nfc_device::{traits::nfc::Device as NfcDevice, Iso14443};
 use crate::{
     init::{CtaphidDispatch, UsbClasses},
     soc::Soc,
-    ui, Apps, Board, Trussed,
+    store, ui, Apps, Board, Trussed,
 };
 
 pub fn poll_dispatchers<B: Board>(
@@ -126,3 +126,12 @@ where
 pub fn run_trussed<B: Board>(trussed: &mut Trussed<B>, endpoints: &mut Endpoints) {
     trussed.process(endpoints);
 }
+
+/// take a new IFS snapshot once the IFS has not been written for a while, see
+/// `store::snapshot`
+///
+/// Every call only runs one short step of a snapshot, thus it can be called with every
+/// UI update. `trussed` is only borrowed to make sure that no filesystem is in use meanwhile.
+pub fn update_ifs_snapshot<B: Board>(_trussed: &mut Trussed<B>, t_now: Milliseconds) {
+    store::update_ifs_snapshot::<B>(t_now);
+}
@@ -0,0 +1,25 @@
+//! Hardware state that is only available during the initialization, recorded for the self tests
+//! of the provisioner.
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+static EXT_FLASH_JEDEC_ID: Mutex<Cell<Option<[u8; 3]>>> = Mutex::new(Cell::new(None));
+static NFC_REGISTERS: Mutex<Cell<Option<[u8; 13]>>> = Mutex::new(Cell::new(None));
+
+pub fn record_ext_flash_jedec_id(jedec_id: [u8; 3]) {
+    interrupt::free(|cs| EXT_FLASH_JEDEC_ID.borrow(cs).set(Some(jedec_id)));
+}
+
+pub fn ext_flash_jedec_id() -> Option<[u8; 3]> {
+    interrupt::free(|cs| EXT_FLASH_JEDEC_ID.borrow(cs).get())
+}
+
+pub fn record_nfc_registers(registers: [u8; 13]) {
+    interrupt::free(|cs| NFC_REGISTERS.borrow(cs).set(Some(registers)));
+}
+
+pub fn nfc_registers() -> Option<[u8; 13]> {
+    interrupt::free(|cs| NFC_REGISTERS.borrow(cs).get())
+}
@@ -222,6 +222,7 @@ impl<const START: usize, const END: usize> littlefs2::driver::Storage for FlashS
         let off = off + (REAL_BLOCK_SIZE * FTL_JOURNAL_BLOCKS);
 
         trace!("IFw {:x} {:x}", off, buf.len());
+        crate::store::count_ifs_write();
         let res = self.nvmc.write(off as u32, buf);
         nvmc_to_lfs_return(res, buf.len())
     }
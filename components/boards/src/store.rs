@@ -1,18 +1,35 @@
-use core::{marker::PhantomData, mem::MaybeUninit};
+use core::{
+    cell::RefCell,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use apps::InitStatus;
+use embedded_time::duration::Milliseconds;
 use littlefs2::{
     const_ram_storage,
     driver::Storage,
     fs::{Allocation, Filesystem},
-    io::Result,
+    io::{Error, Result},
     object_safe::DynFilesystem,
 };
+use sha2::{Digest as _, Sha256};
 use trussed::store::Store;
 use trussed_manage::FACTORY_RESET_MARKER_FILE;
 
 use crate::Board;
 
+pub(crate) mod backend;
+pub mod snapshot;
+
+/// the IFS has to stay unchanged this long before a snapshot is taken, see `update_ifs_snapshot`
+const SNAPSHOT_DELAY: Milliseconds = Milliseconds(10_000);
+
+/// number of writes into the IFS storage since boot, see `count_ifs_write`
+static IFS_WRITES: AtomicU32 = AtomicU32::new(0);
+
 // 8KB of RAM
 const_ram_storage!(
     name = VolatileStorage,
@@ -28,7 +45,8 @@ const_ram_storage!(
 
 pub struct StoreResources<B: Board> {
     internal: StorageResources<B::InternalStorage>,
-    external: StorageResources<B::ExternalStorage>,
+    external: StorageResources<SharedStorage<B::ExternalStorage>>,
+    external_storage: MaybeUninit<RefCell<B::ExternalStorage>>,
     volatile: StorageResources<VolatileStorage>,
 }
 
@@ -43,6 +61,7 @@ impl<B: Board> StoreResources<B> {
         Self {
             internal: StorageResources::new(),
             external: StorageResources::new(),
+            external_storage: MaybeUninit::uninit(),
             volatile: StorageResources::new(),
         }
     }
@@ -70,6 +89,37 @@ impl<S: Storage + 'static> StorageResources<S> {
     }
 }
 
+/// external storage shared by the mounted EFS and the IFS snapshots in the spare area behind
+/// it, every access only borrows the storage for a single operation
+pub struct SharedStorage<S: 'static> {
+    storage: &'static RefCell<S>,
+}
+
+impl<S: Storage> Storage for SharedStorage<S> {
+    const READ_SIZE: usize = S::READ_SIZE;
+    const WRITE_SIZE: usize = S::WRITE_SIZE;
+    const BLOCK_SIZE: usize = S::BLOCK_SIZE;
+    const BLOCK_COUNT: usize = S::BLOCK_COUNT;
+    const BLOCK_CYCLES: isize = S::BLOCK_CYCLES;
+    type CACHE_SIZE = S::CACHE_SIZE;
+    type LOOKAHEAD_SIZE = S::LOOKAHEAD_SIZE;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut storage = self.storage.try_borrow_mut().map_err(|_| Error::IO)?;
+        storage.read(off, buf)
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> Result<usize> {
+        let mut storage = self.storage.try_borrow_mut().map_err(|_| Error::IO)?;
+        storage.write(off, data)
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> Result<usize> {
+        let mut storage = self.storage.try_borrow_mut().map_err(|_| Error::IO)?;
+        storage.erase(off, len)
+    }
+}
+
 struct StorePointers {
     ifs: MaybeUninit<&'static dyn DynFilesystem>,
     efs: MaybeUninit<&'static dyn DynFilesystem>,
@@ -147,20 +197,37 @@ pub fn init_store<B: Board>(
 ) -> RunnerStore<B> {
     let ifs_storage = resources.internal.storage.write(int_flash);
     let ifs_alloc = resources.internal.alloc.write(Filesystem::allocate());
-    let efs_storage = resources.external.storage.write(ext_flash);
-    let efs_alloc = resources.external.alloc.write(Filesystem::allocate());
+    let efs_cell = resources.external_storage.write(RefCell::new(ext_flash));
     let vfs_storage = resources.volatile.storage.write(VolatileStorage::new());
     let vfs_alloc = resources.volatile.alloc.write(Filesystem::allocate());
 
-    let ifs = match init_ifs::<B>(ifs_storage, ifs_alloc, efs_storage, status) {
-        Ok(ifs) => resources.internal.fs.write(ifs),
-        Err(_e) => {
-            error!("IFS Mount Error {:?}", _e);
-            panic!("IFS");
+    let snapshot_secret = B::ifs_snapshot_secret(ifs_storage);
+    let ifs: &'static Filesystem<'static, B::InternalStorage> =
+        match init_ifs::<B>(ifs_storage, ifs_alloc, efs_cell.get_mut(), status) {
+            Ok(ifs) => resources.internal.fs.write(ifs),
+            Err(_e) => {
+                error!("IFS Mount Error {:?}", _e);
+                panic!("IFS");
+            }
+        };
+
+    // from here on, the EFS and the IFS snapshots only access the storage through `SharedStorage`
+    let efs_cell: &'static RefCell<B::ExternalStorage> = efs_cell;
+    let efs_storage = resources
+        .external
+        .storage
+        .write(SharedStorage { storage: efs_cell });
+    let efs_alloc = resources.external.alloc.write(Filesystem::allocate());
+
+    // the EFS is simulated if the device is powered via NFC, thus there is no time for snapshots
+    if let Some(secret) = snapshot_secret {
+        if !simulated_efs && !cfg!(feature = "provisioner") {
+            init_ifs_snapshots::<B>(ifs, efs_cell, secret);
         }
-    };
+    }
 
-    let efs = match init_efs::<B>(efs_storage, efs_alloc, simulated_efs, status) {
+    let snapshots = snapshot_secret.is_some();
+    let efs = match init_efs(efs_storage, efs_alloc, simulated_efs, snapshots, status) {
         Ok(efs) => resources.external.fs.write(efs),
         Err(_e) => {
             error!("EFS Mount Error {:?}", _e);
@@ -190,6 +257,12 @@ fn init_ifs<B: Board>(
         Filesystem::format(ifs_storage).ok();
     }
 
+    // an interrupted recovery might have formatted the IFS already
+    if !cfg!(feature = "provisioner") {
+        B::resume_ifs(ifs_storage, ifs_alloc, efs_storage).ok();
+        resume_ifs_snapshot::<B>(ifs_storage, ifs_alloc, efs_storage);
+    }
+
     if !Filesystem::is_mountable(ifs_storage) {
         // handle provisioner
         if cfg!(feature = "provisioner") {
@@ -208,12 +281,13 @@ fn init_ifs<B: Board>(
 }
 
 #[inline(always)]
-fn init_efs<'a, B: Board>(
-    efs_storage: &'a mut B::ExternalStorage,
-    efs_alloc: &'a mut Allocation<B::ExternalStorage>,
+fn init_efs<'a, S: Storage>(
+    efs_storage: &'a mut S,
+    efs_alloc: &'a mut Allocation<S>,
     simulated_efs: bool,
+    snapshots: bool,
     status: &mut InitStatus,
-) -> Result<Filesystem<'a, B::ExternalStorage>> {
+) -> Result<Filesystem<'a, S>> {
     use littlefs2::fs::{Config as LfsConfig, MountFlags};
     if cfg!(feature = "format-filesystem") {
         Filesystem::format(efs_storage).ok();
@@ -232,7 +306,7 @@ fn init_efs<'a, B: Board>(
             let shrink_res =
                 Filesystem::mount_and_then_with_config(storage, config.clone(), |fs| {
                     mounted_with_wrong_block_count = true;
-                    fs.shrink(B::ExternalStorage::BLOCK_COUNT)
+                    fs.shrink(S::BLOCK_COUNT)
                 });
             match shrink_res {
                 Ok(_) => return Ok(()),
@@ -261,6 +335,10 @@ fn init_efs<'a, B: Board>(
     if fs.exists(FACTORY_RESET_MARKER_FILE) {
         debug_now!("Reformatting EFS filesystem");
         let (efs_alloc, efs_storage) = fs.into_inner();
+        // the snapshots contain the IFS from before the factory reset
+        if snapshots {
+            snapshot::erase(efs_storage).ok();
+        }
         Filesystem::format(efs_storage).ok();
         Filesystem::mount(efs_alloc, efs_storage)
     } else {
@@ -268,6 +346,167 @@ fn init_efs<'a, B: Board>(
     }
 }
 
+/// derive a key for a single purpose (`label`) from device-unique key material, e.g.,
+/// to keep the IFS snapshots and the NK3AM migration backups from sharing a key
+pub(crate) fn derive_key(key_material: &[u8], label: &[u8]) -> [u8; 16] {
+    let hash = Sha256::new()
+        .chain_update(label)
+        .chain_update(key_material)
+        .finalize();
+    let mut key = [0; 16];
+    key.copy_from_slice(&hash[..16]);
+    key
+}
+
+/// has to be called by the drivers of the IFS storage for every write, see `update_ifs_snapshot`
+pub(crate) fn count_ifs_write() {
+    IFS_WRITES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// state of the IFS snapshots taken at runtime, see `update_ifs_snapshot`
+struct SnapshotState {
+    /// `&Filesystem<B::InternalStorage>`
+    ifs: *const (),
+    /// `&RefCell<B::ExternalStorage>`, shared with the EFS (see `SharedStorage`), which
+    /// never touches the spare area behind its blocks
+    efs_storage: *const (),
+    /// see `Board::ifs_snapshot_secret`
+    secret: snapshot::Secret,
+    /// `IFS_WRITES` when the IFS was compared with the newest snapshot the last time
+    checked: Option<u32>,
+    /// `IFS_WRITES` & the time it was seen first
+    seen: Option<(u32, u32)>,
+    /// snapshot of the IFS as of `checked` which is not yet complete
+    update: Option<snapshot::Update>,
+}
+
+unsafe fn snapshot_state() -> &'static mut Option<SnapshotState> {
+    static mut STATE: Option<SnapshotState> = None;
+    (&raw mut STATE).as_mut().unwrap()
+}
+
+/// enable the IFS snapshots taken by `update_ifs_snapshot`
+fn init_ifs_snapshots<B: Board>(
+    ifs: &'static Filesystem<'static, B::InternalStorage>,
+    efs_storage: &'static RefCell<B::ExternalStorage>,
+    secret: snapshot::Secret,
+) {
+    let mut storage = efs_storage.borrow_mut();
+    if B::is_spare_reserved(&mut storage) {
+        info_now!("spare area reserved, no IFS snapshots");
+        return;
+    }
+    // the IFS is only compared with the newest snapshot after it has been written,
+    // unless there is no snapshot at all
+    let checked = snapshot::exists(&mut *storage).then_some(0);
+    let state = SnapshotState {
+        ifs: ptr::from_ref(ifs).cast(),
+        efs_storage: ptr::from_ref(efs_storage).cast(),
+        secret,
+        checked,
+        seen: None,
+        update: None,
+    };
+    unsafe { *snapshot_state() = Some(state) };
+}
+
+/// write a new IFS snapshot if the IFS changed since the newest one and has not
+/// been written for `SNAPSHOT_DELAY`, see `snapshot::begin`
+///
+/// Every call only runs a single short step of the snapshot (see `snapshot::Update`).
+/// A snapshot is dropped if the IFS is written before it is complete. Has to be called
+/// regularly while the filesystems are not in use, see `runtime::update_ifs_snapshot`.
+pub(crate) fn update_ifs_snapshot<B: Board>(t_now: Milliseconds) {
+    let Some(state) = (unsafe { snapshot_state() }) else {
+        return;
+    };
+    // SAFETY: set by `init_ifs_snapshots` for the same board, the caller makes sure
+    // that the filesystems are not in use
+    let (ifs, efs_storage) = unsafe {
+        (
+            &*state.ifs.cast::<Filesystem<'static, B::InternalStorage>>(),
+            &*state.efs_storage.cast::<RefCell<B::ExternalStorage>>(),
+        )
+    };
+    let mut efs_storage = SharedStorage {
+        storage: efs_storage,
+    };
+    let writes = IFS_WRITES.load(Ordering::Relaxed);
+
+    if let Some(update) = &mut state.update {
+        if state.checked == Some(writes) {
+            match update.step(ifs, &mut efs_storage) {
+                Ok(false) => return,
+                Ok(true) => info_now!("IFS snapshot written"),
+                Err(_e) => error_now!("IFS snapshot failed: {:?}", _e),
+            }
+        } else {
+            info_now!("IFS written during snapshot, starting over");
+        }
+        state.update = None;
+    }
+
+    if state.checked == Some(writes) {
+        return;
+    }
+    match state.seen {
+        Some((seen, since)) if seen == writes => {
+            if t_now.0.wrapping_sub(since) < SNAPSHOT_DELAY.0 {
+                return;
+            }
+        }
+        _ => {
+            state.seen = Some((writes, t_now.0));
+            return;
+        }
+    }
+    // reading the IFS for a snapshot does not write it
+    state.checked = Some(writes);
+
+    match snapshot::begin(ifs, &mut efs_storage, &state.secret) {
+        Ok(update) => state.update = update,
+        Err(_e) => error_now!("IFS snapshot failed: {:?}", _e),
+    }
+}
+
+/// finish an interrupted restore of an IFS snapshot, see `snapshot::resume`
+fn resume_ifs_snapshot<B: Board>(
+    ifs_storage: &mut B::InternalStorage,
+    ifs_alloc: &mut Allocation<B::InternalStorage>,
+    efs_storage: &mut B::ExternalStorage,
+) {
+    let Some(secret) = B::ifs_snapshot_secret(ifs_storage) else {
+        return;
+    };
+    match snapshot::resume::<B>(ifs_storage, ifs_alloc, efs_storage, &secret) {
+        Ok(true) => info_now!("interrupted IFS snapshot restore finished"),
+        Ok(false) => {}
+        Err(_e) => error_now!("failed to finish IFS snapshot restore: {:?}", _e),
+    }
+}
+
+/// restore the newest valid IFS snapshot (formats the IFS), returns whether
+/// a snapshot was restored
+pub fn restore_ifs_snapshot<B: Board + ?Sized>(
+    ifs_storage: &mut B::InternalStorage,
+    ifs_alloc: &mut Allocation<B::InternalStorage>,
+    efs_storage: &mut B::ExternalStorage,
+) -> bool {
+    let Some(secret) = B::ifs_snapshot_secret(ifs_storage) else {
+        return false;
+    };
+    match snapshot::restore::<B>(ifs_storage, ifs_alloc, efs_storage, &secret) {
+        Ok(()) => {
+            info_now!("IFS snapshot restored");
+            true
+        }
+        Err(_e) => {
+            error_now!("no IFS snapshot restored: {:?}", _e);
+            false
+        }
+    }
+}
+
 #[inline(always)]
 fn init_vfs(
     vfs_storage: &'static mut VolatileStorage,
@@ -522,13 +761,7 @@ mod tests {
             unsafe { &mut *(&raw mut *efs_storage_cropped as *mut ExternalStorageCropped) };
         let efs_alloc = &mut Allocation::new();
         let efs_alloc_cropped = &mut Allocation::new();
-        let storage = init_efs::<TestBoard<ExternalStorageFull>>(
-            efs_storage_full,
-            efs_alloc,
-            false,
-            &mut status,
-        )
-        .unwrap();
+        let storage = init_efs(efs_storage_full, efs_alloc, false, false, &mut status).unwrap();
         // The flash was formatted since it's the first boot;
         assert_eq!(status, InitStatus::EXTERNAL_FLASH_ERROR);
         status = InitStatus::empty();
@@ -548,13 +781,7 @@ mod tests {
         let (_, efs_storage_full) = storage.into_inner();
         let efs_alloc = &mut Allocation::new();
 
-        let storage = init_efs::<TestBoard<ExternalStorageFull>>(
-            efs_storage_full,
-            efs_alloc,
-            false,
-            &mut status,
-        )
-        .unwrap();
+        let storage = init_efs(efs_storage_full, efs_alloc, false, false, &mut status).unwrap();
         storage
             .read_dir_and_then(path!("/"), |dir| {
                 assert!(dir.next().is_some());
@@ -567,9 +794,7 @@ mod tests {
 
         let (_, efs_storage) = storage.into_inner();
         let efs_alloc = &mut Allocation::new();
-        let storage =
-            init_efs::<TestBoard<ExternalStorageFull>>(efs_storage, efs_alloc, false, &mut status)
-                .unwrap();
+        let storage = init_efs(efs_storage, efs_alloc, false, false, &mut status).unwrap();
         assert_eq!(status, InitStatus::empty());
         let mut data = vec![0xEE; 4096];
         for i in 0.. {
@@ -604,10 +829,11 @@ mod tests {
 
         status = InitStatus::empty();
 
-        let storage = init_efs::<TestBoard<ExternalStorageCropped>>(
+        let storage = init_efs(
             efs_storage_cropped,
             efs_alloc_cropped,
             false,
+            false,
             &mut status,
         )
         .unwrap();
@@ -619,13 +845,7 @@ mod tests {
         efs_storage_full.buf.fill(0);
 
         status = InitStatus::empty();
-        let storage = init_efs::<TestBoard<ExternalStorageFull>>(
-            efs_storage_full,
-            efs_alloc,
-            false,
-            &mut status,
-        )
-        .unwrap();
+        let storage = init_efs(efs_storage_full, efs_alloc, false, false, &mut status).unwrap();
         // Storage reformatted
         assert_eq!(status, InitStatus::EXTERNAL_FLASH_ERROR);
         status = InitStatus::empty();
@@ -656,10 +876,11 @@ mod tests {
             .buf
             .copy_from_slice(&efs_storage_full.buf[..CROPPED_EXTERNAL_STORAGE_BLOCK_COUNT * 4096]);
 
-        let storage = init_efs::<TestBoard<ExternalStorageCropped>>(
+        let storage = init_efs(
             efs_storage_cropped,
             efs_alloc_cropped,
             false,
+            false,
             &mut status,
         )
         .unwrap();
@@ -675,4 +896,172 @@ mod tests {
             assert_eq!(file, &*test_data);
         }
     }
+
+    /// external storage with the spare area behind the filesystem blocks
+    struct SpareStorage {
+        buf: Vec<u8>,
+    }
+
+    impl Storage for SpareStorage {
+        const READ_SIZE: usize = 4;
+        const WRITE_SIZE: usize = 256;
+        const BLOCK_SIZE: usize = 4096;
+        const BLOCK_COUNT: usize = CROPPED_EXTERNAL_STORAGE_BLOCK_COUNT;
+        type CACHE_SIZE = littlefs2::consts::U256;
+        type LOOKAHEAD_SIZE = littlefs2::consts::U1;
+
+        fn read(&mut self, off: usize, buf: &mut [u8]) -> Result<usize> {
+            buf.copy_from_slice(&self.buf[off..off + buf.len()]);
+            Ok(buf.len())
+        }
+
+        fn write(&mut self, off: usize, data: &[u8]) -> Result<usize> {
+            self.buf[off..off + data.len()].copy_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn erase(&mut self, off: usize, len: usize) -> Result<usize> {
+            self.buf[off..off + len].fill(0xff);
+            Ok(len)
+        }
+    }
+
+    fn read_fido_file(ifs_storage: &mut InternalStorage) -> Vec<u8> {
+        let ifs_alloc = &mut Allocation::new();
+        let ifs = Filesystem::mount(ifs_alloc, ifs_storage).unwrap();
+        ifs.read::<1024>(path!("/fido/file")).unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_ifs_snapshots() {
+        type B = TestBoard<SpareStorage>;
+        const SECRET: snapshot::Secret = *b"hardware secret!";
+
+        const IFS_STORAGE_SIZE: usize = size_of::<InternalStorage>();
+        let mut ifs_storage: Box<[u8; IFS_STORAGE_SIZE]> = vec![0; IFS_STORAGE_SIZE]
+            .into_boxed_slice()
+            .try_into()
+            .unwrap();
+        let ifs_storage: &mut InternalStorage =
+            unsafe { &mut *(&raw mut *ifs_storage as *mut InternalStorage) };
+        let ifs_alloc = &mut Allocation::new();
+        let efs_storage = &mut SpareStorage {
+            buf: vec![0xff; 0x20_0000],
+        };
+
+        Filesystem::format(ifs_storage).unwrap();
+        {
+            let ifs = Filesystem::mount(ifs_alloc, ifs_storage).unwrap();
+            ifs.create_dir_all(path!("/fido")).unwrap();
+            for i in 1..=3 {
+                ifs.write(path!("/fido/file"), &[i; 600]).unwrap();
+                assert_eq!(snapshot::update(&ifs, efs_storage, &SECRET), Ok(true));
+                assert_eq!(snapshot::update(&ifs, efs_storage, &SECRET), Ok(false));
+            }
+            // the RNG state changes on every boot and is not part of the snapshots
+            ifs.create_dir_all(path!("/trussed/dat")).unwrap();
+            ifs.write(path!("/trussed/dat/rng-state.bin"), &[0; 32])
+                .unwrap();
+            assert_eq!(snapshot::update(&ifs, efs_storage, &SECRET), Ok(false));
+        }
+
+        // the newest snapshot replaced the oldest one
+        ifs_storage.buf.fill(0);
+        assert!(!Filesystem::is_mountable(ifs_storage));
+        assert_eq!(
+            snapshot::restore::<B>(ifs_storage, ifs_alloc, efs_storage, &SECRET),
+            Ok(())
+        );
+        assert_eq!(read_fido_file(ifs_storage), vec![3; 600]);
+        // a finished restore is not resumed
+        assert_eq!(
+            snapshot::resume::<B>(ifs_storage, ifs_alloc, efs_storage, &SECRET),
+            Ok(false)
+        );
+
+        // the digest depends on the secret, e.g., if the secret had to be replaced
+        {
+            let ifs = Filesystem::mount(ifs_alloc, ifs_storage).unwrap();
+            assert_eq!(snapshot::update(&ifs, efs_storage, &SECRET), Ok(false));
+            assert_eq!(snapshot::update(&ifs, efs_storage, &[1; 16]), Ok(true));
+        }
+
+        // a factory reset removes all snapshots
+        snapshot::erase(efs_storage).unwrap();
+        ifs_storage.buf.fill(0);
+        assert_eq!(
+            snapshot::restore::<B>(ifs_storage, ifs_alloc, efs_storage, &SECRET),
+            Err(lfs_backup::FSBackupError::RestoreErr)
+        );
+    }
+
+    #[test]
+    fn test_ifs_snapshot_steps() {
+        type B = TestBoard<SpareStorage>;
+        const SECRET: snapshot::Secret = *b"hardware secret!";
+
+        // not compressible, thus the backup needs several steps to write
+        fn data(seed: u32) -> Vec<u8> {
+            let mut x = seed;
+            (0..20_000)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 17;
+                    x ^= x << 5;
+                    x as u8
+                })
+                .collect()
+        }
+
+        const IFS_STORAGE_SIZE: usize = size_of::<InternalStorage>();
+        let mut ifs_storage: Box<[u8; IFS_STORAGE_SIZE]> = vec![0; IFS_STORAGE_SIZE]
+            .into_boxed_slice()
+            .try_into()
+            .unwrap();
+        let ifs_storage: &mut InternalStorage =
+            unsafe { &mut *(&raw mut *ifs_storage as *mut InternalStorage) };
+        let ifs_alloc = &mut Allocation::new();
+        let efs_storage = &mut SpareStorage {
+            buf: vec![0xff; 0x20_0000],
+        };
+
+        Filesystem::format(ifs_storage).unwrap();
+        {
+            let ifs = Filesystem::mount(ifs_alloc, ifs_storage).unwrap();
+            ifs.create_dir_all(path!("/fido")).unwrap();
+            ifs.write(path!("/fido/file"), &data(1)).unwrap();
+            let mut update = snapshot::begin(&ifs, efs_storage, &SECRET)
+                .unwrap()
+                .unwrap();
+            let mut steps = 1;
+            while !update.step(&ifs, efs_storage).unwrap() {
+                steps += 1;
+            }
+            // the erase of the slot and every part of the backup are separate steps
+            assert!(steps > 16 + 4);
+            assert!(snapshot::begin(&ifs, efs_storage, &SECRET)
+                .unwrap()
+                .is_none());
+
+            // a snapshot which is not completed never replaces the newest one
+            ifs.write(path!("/fido/file"), &data(2)).unwrap();
+            let mut update = snapshot::begin(&ifs, efs_storage, &SECRET)
+                .unwrap()
+                .unwrap();
+            for _ in 0..steps - 1 {
+                assert_eq!(update.step(&ifs, efs_storage), Ok(false));
+            }
+        }
+
+        ifs_storage.buf.fill(0);
+        assert_eq!(
+            snapshot::restore::<B>(ifs_storage, ifs_alloc, efs_storage, &SECRET),
+            Ok(())
+        );
+        let ifs = Filesystem::mount(ifs_alloc, ifs_storage).unwrap();
+        assert_eq!(
+            ifs.read::<20_000>(path!("/fido/file")).unwrap().to_vec(),
+            data(1)
+        );
+    }
 }
@@ -0,0 +1,261 @@
+use core::ops::Range;
+
+use littlefs2::driver::Storage;
+use sha2::{Digest, Sha256};
+use trussed_core::types::Bytes;
+use utils::Version;
+
+use lfs_backup::{BackupBackend, BackupKey, FSBackupError, Result, MAX_DUMP_BLOB_LENGTH};
+
+const VERSION: Version = Version::from_str(env!("CARGO_PKG_VERSION"));
+
+// would be good to get this from ext-flash directly
+pub const RW_SIZE: usize = 256;
+
+/// `BackupBackend` for a region of a `Storage` outside of its filesystem,
+/// e.g., the spare area at the end of the external flash
+pub struct StorageBackend<'a, S: Storage> {
+    storage: &'a mut S,
+    initial_offset: usize,
+    offset: usize,
+    len: usize,
+    key: Option<BackupKey>,
+}
+
+impl<'a, S: Storage> StorageBackend<'a, S> {
+    pub fn new(storage: &'a mut S, offset: usize, len: usize, key: Option<BackupKey>) -> Self {
+        Self {
+            storage,
+            initial_offset: offset,
+            offset,
+            len,
+            key,
+        }
+    }
+}
+
+impl<S: Storage> BackupBackend for StorageBackend<'_, S> {
+    const RW_SIZE: usize = RW_SIZE;
+
+    fn write(&mut self, content: &[u8]) -> Result<usize> {
+        let len =
+            content.len() + ((Self::RW_SIZE - (content.len() % Self::RW_SIZE)) % Self::RW_SIZE);
+        let mut data: Bytes<MAX_DUMP_BLOB_LENGTH> = Bytes::try_from(content).unwrap();
+
+        data.resize(len, 0x00)
+            .map_err(|_| FSBackupError::BackendWriteErr)?;
+
+        // never write behind the backup space
+        if self.offset + data.len() > self.initial_offset + self.len {
+            return Err(FSBackupError::BackendWriteErr);
+        }
+
+        let count = self
+            .storage
+            .write(self.offset, &data)
+            .map_err(|_| FSBackupError::BackendWriteErr)?;
+        self.offset += data.len();
+
+        //trace_now!("W-addr: {} len: {} datalen: {}", self.offset, content.len(), data.len());
+        //trace_now!("W-data: {} = {:?}", data);
+
+        Ok(count)
+    }
+
+    fn read<const N: usize>(&mut self, len: usize) -> Result<Bytes<N>> {
+        let mut output = Bytes::<N>::default();
+        output.resize_zero(len).expect("assuming: N > len");
+
+        self.storage
+            .read(self.offset, &mut output)
+            .map_err(|_| FSBackupError::BackendReadErr)?;
+
+        self.offset +=
+            output.len() + ((Self::RW_SIZE - (output.len() % Self::RW_SIZE)) % Self::RW_SIZE);
+
+        //trace_now!("R-addr: {} len {:}", self.offset, output.len());
+        //trace_now!("R-data {:?}", output);
+
+        Ok(output)
+    }
+
+    fn erase(&mut self) -> Result<usize> {
+        self.storage
+            .erase(self.initial_offset, self.len)
+            .map_err(|_| FSBackupError::BackendEraseErr)?;
+        self.offset = self.initial_offset;
+        Ok(self.len)
+    }
+
+    fn reset(&mut self) {
+        self.offset = self.initial_offset;
+    }
+
+    fn position(&self) -> usize {
+        self.offset - self.initial_offset
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.offset = self.initial_offset + position;
+    }
+
+    fn key(&self) -> Option<&BackupKey> {
+        self.key.as_ref()
+    }
+
+    // the spare area is much smaller than the IFS
+    fn compression(&self) -> bool {
+        true
+    }
+
+    fn firmware_version(&self) -> u32 {
+        VERSION.encode()
+    }
+}
+
+/// `BackupBackend` only writing the part of a backup starting inside `window` into a
+/// `StorageBackend`, e.g., to write a long backup in several steps
+///
+/// The header in the first block is written once the backup is done. Every write is read
+/// back and compared. Writes behind `window` fail to stop the backup, see `is_stopped`.
+pub struct WindowBackend<'a, S: Storage> {
+    backend: StorageBackend<'a, S>,
+    window: Range<usize>,
+    stopped: bool,
+}
+
+impl<'a, S: Storage> WindowBackend<'a, S> {
+    pub fn new(backend: StorageBackend<'a, S>, window: Range<usize>) -> Self {
+        Self {
+            backend,
+            window,
+            stopped: false,
+        }
+    }
+
+    /// the backup failed because it reached the end of `window`
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+impl<S: Storage> BackupBackend for WindowBackend<'_, S> {
+    const RW_SIZE: usize = RW_SIZE;
+
+    fn write(&mut self, content: &[u8]) -> Result<usize> {
+        let position = self.backend.position();
+        let len =
+            content.len() + ((Self::RW_SIZE - (content.len() % Self::RW_SIZE)) % Self::RW_SIZE);
+        if position >= self.window.end {
+            self.stopped = true;
+            return Err(FSBackupError::BackendWriteErr);
+        }
+        if position != 0 && position < self.window.start {
+            self.backend.seek(position + len);
+            return Ok(len);
+        }
+
+        let count = self.backend.write(content)?;
+        self.backend.seek(position);
+        let written: Bytes<MAX_DUMP_BLOB_LENGTH> = self.backend.read(len)?;
+        let (data, padding) = written.split_at(content.len());
+        if data != content || padding.iter().any(|&byte| byte != 0) {
+            return Err(FSBackupError::BackendWriteErr);
+        }
+        Ok(count)
+    }
+
+    fn read<const N: usize>(&mut self, len: usize) -> Result<Bytes<N>> {
+        self.backend.read(len)
+    }
+
+    fn erase(&mut self) -> Result<usize> {
+        self.backend.erase()
+    }
+
+    fn reset(&mut self) {
+        self.backend.reset()
+    }
+
+    fn position(&self) -> usize {
+        self.backend.position()
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.backend.seek(position)
+    }
+
+    fn key(&self) -> Option<&BackupKey> {
+        self.backend.key()
+    }
+
+    fn compression(&self) -> bool {
+        self.backend.compression()
+    }
+
+    fn firmware_version(&self) -> u32 {
+        self.backend.firmware_version()
+    }
+}
+
+/// `BackupBackend` only hashing the (plaintext) backup written into it, e.g., to
+/// detect changes of a filesystem without reading an existing backup
+pub struct DigestBackend {
+    hasher: Sha256,
+    position: usize,
+}
+
+impl DigestBackend {
+    /// the digest is keyed with `secret`, see `store::snapshot`
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            hasher: Sha256::new_with_prefix(secret),
+            position: 0,
+        }
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+}
+
+impl BackupBackend for DigestBackend {
+    const RW_SIZE: usize = RW_SIZE;
+
+    fn write(&mut self, content: &[u8]) -> Result<usize> {
+        self.hasher.update(content);
+        self.position += content.len();
+        Ok(content.len())
+    }
+
+    // nothing is stored, thus there is nothing but erased space to read
+    fn read<const N: usize>(&mut self, len: usize) -> Result<Bytes<N>> {
+        let mut output = Bytes::<N>::default();
+        output
+            .resize_zero(len)
+            .map_err(|_| FSBackupError::BackendReadErr)?;
+        self.position += len;
+        Ok(output)
+    }
+
+    fn erase(&mut self) -> Result<usize> {
+        self.position = 0;
+        Ok(0)
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    fn firmware_version(&self) -> u32 {
+        VERSION.encode()
+    }
+}
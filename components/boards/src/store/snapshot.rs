@@ -0,0 +1,342 @@
+//! Snapshots of the IFS inside the spare area of the external flash.
+//!
+//! The spare area (`flash::SPARE_LEN` bytes behind the EFS) is split into
+//! `SLOTS` slots, each one holding an encrypted `lfs-backup` backup of the IFS
+//! followed by its progress journal. The first block of a slot contains the
+//! generation and the `Digest` of the snapshot and is written once the snapshot
+//! is complete and checked. A new snapshot always replaces the oldest one, thus
+//! a power loss while writing it never affects the newest valid snapshot. The
+//! snapshots are written in short steps (see `Update`), the EFS stays usable
+//! meanwhile.
+//!
+//! The snapshots are encrypted with a key derived from the device-unique `Secret`
+//! of the board (see `Board::ifs_snapshot_secret`). The digest is a hash of
+//! another key derived from the secret and the plaintext backup of the IFS (see
+//! `DigestBackend`), a new
+//! snapshot is only written if it differs from the digest of the newest
+//! snapshot. This only reads the IFS, but none of the snapshots. `EXCLUDED`
+//! files change on every boot and are not part of the snapshots.
+//!
+//! The nonce salt of a snapshot is derived from its digest and generation, thus
+//! a salt is only used twice for the same plaintext.
+//!
+//! The NK3AM migration uses the whole spare area for its backup. No snapshot is
+//! written while that backup is not done (see `Board::is_spare_reserved`), and
+//! the generation block keeps the migration from mistaking a snapshot for an
+//! interrupted migration.
+
+use core::cmp::Reverse;
+
+use littlefs2::{
+    driver::Storage,
+    fs::{Allocation, Filesystem},
+};
+
+use lfs_backup::{
+    assemble_block, parse_block, BackupBackend, BackupKey, FSBackupError, PathPatterns, Progress,
+    Result, SALT_SIZE,
+};
+use sha2::{Digest as _, Sha256};
+
+use super::{
+    backend::{DigestBackend, StorageBackend, WindowBackend, RW_SIZE},
+    derive_key,
+};
+use crate::{flash::SPARE_LEN, Board};
+
+const SLOTS: usize = 2;
+const SLOT_LEN: usize = SPARE_LEN / SLOTS;
+// the first block of each slot holds the generation, the remaining ones the backup
+const BACKUP_LEN: usize = SLOT_LEN - RW_SIZE;
+// bytes erased or written per step of an `Update`, one sector of the external flash
+const STEP_LEN: usize = 4096;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SN||";
+
+// labels of the keys derived from the `Secret`, see `store::derive_key`
+const BACKUP_KEY_LABEL: &[u8] = b"ifs-snapshot/backup";
+const DIGEST_KEY_LABEL: &[u8] = b"ifs-snapshot/digest";
+
+// Trussed updates its RNG state on every boot and creates a new one if it is missing
+const EXCLUDED: PathPatterns<'static> = PathPatterns::exclude(&["/trussed/dat/rng-state.bin"]);
+
+/// device-unique secret the snapshot keys are derived from
+pub type Secret = [u8; 16];
+
+/// keyed hash of the plaintext backup of the IFS, see `digest`
+pub type Digest = [u8; 32];
+
+/// generation block of a complete snapshot
+#[derive(Clone, Copy)]
+struct Snapshot {
+    slot: usize,
+    generation: u32,
+    digest: Digest,
+}
+
+/// the spare area starts right behind the blocks used by the filesystem, slot 0
+/// overlaps with the start of the NK3AM migration backup
+fn slot_offset<S: Storage>(slot: usize) -> usize {
+    S::BLOCK_SIZE * S::BLOCK_COUNT + slot * SLOT_LEN
+}
+
+fn backend<S: Storage>(efs_storage: &mut S, slot: usize, key: BackupKey) -> StorageBackend<'_, S> {
+    let offset = slot_offset::<S>(slot) + RW_SIZE;
+    StorageBackend::new(efs_storage, offset, BACKUP_LEN, Some(key))
+}
+
+fn erase_slot<S: Storage>(efs_storage: &mut S, slot: usize) -> Result<()> {
+    efs_storage
+        .erase(slot_offset::<S>(slot), SLOT_LEN)
+        .map_err(|_| FSBackupError::BackendEraseErr)?;
+    Ok(())
+}
+
+/// generation block of the (complete) snapshot inside `slot`
+fn snapshot<S: Storage>(efs_storage: &mut S, slot: usize) -> Option<Snapshot> {
+    let mut block = [0; RW_SIZE];
+    efs_storage.read(slot_offset::<S>(slot), &mut block).ok()?;
+    let data = parse_block(SNAPSHOT_MAGIC, &block).ok()?;
+    let (generation, digest) = data.split_first_chunk::<4>()?;
+    Some(Snapshot {
+        slot,
+        generation: u32::from_be_bytes(*generation),
+        digest: digest.try_into().ok()?,
+    })
+}
+
+/// slots containing a snapshot, newest first
+fn snapshots<S: Storage>(efs_storage: &mut S) -> [Option<Snapshot>; SLOTS] {
+    let mut snapshots = [None; SLOTS];
+    for (slot, entry) in snapshots.iter_mut().enumerate() {
+        *entry = snapshot(efs_storage, slot);
+    }
+    snapshots.sort_unstable_by_key(|snapshot| Reverse(snapshot.map(|s| s.generation)));
+    snapshots
+}
+
+/// there is at least one snapshot
+pub fn exists<S: Storage>(efs_storage: &mut S) -> bool {
+    snapshots(efs_storage)[0].is_some()
+}
+
+fn backup_key(secret: &Secret, salt: [u8; SALT_SIZE]) -> BackupKey {
+    BackupKey::derive(&derive_key(secret, BACKUP_KEY_LABEL), salt)
+}
+
+/// key for restoring a snapshot, the salt is read from the snapshot
+fn restore_key(secret: &Secret) -> BackupKey {
+    backup_key(secret, [0; SALT_SIZE])
+}
+
+/// digest of the IFS as written into a snapshot, i.e., without the `EXCLUDED` files
+pub fn digest<S: Storage>(ifs: &Filesystem<'_, S>, secret: &Secret) -> Result<Digest> {
+    let mut backend = DigestBackend::new(&derive_key(secret, DIGEST_KEY_LABEL));
+    backend.backup_filtered(ifs, &EXCLUDED)?;
+    Ok(backend.finalize())
+}
+
+/// a new snapshot written step by step, see `begin`
+pub struct Update {
+    snapshot: Snapshot,
+    key: BackupKey,
+    state: UpdateState,
+}
+
+enum UpdateState {
+    /// bytes of the slot erased so far
+    Erase(usize),
+    /// position of the next part of the backup to write
+    Write(usize),
+}
+
+/// start a new snapshot of `ifs` unless the digest of the newest snapshot matches
+/// it already, the snapshot is written by `Update::step`
+pub fn begin<I: Storage, E: Storage>(
+    ifs: &Filesystem<'_, I>,
+    efs_storage: &mut E,
+    secret: &Secret,
+) -> Result<Option<Update>> {
+    let digest = digest(ifs, secret)?;
+    let newest = snapshots(efs_storage)[0];
+    if newest.is_some_and(|snapshot| snapshot.digest == digest) {
+        return Ok(None);
+    }
+
+    // replace the oldest (or an empty) slot
+    let (slot, generation) = match newest {
+        Some(snapshot) => (
+            (snapshot.slot + 1) % SLOTS,
+            snapshot.generation.wrapping_add(1),
+        ),
+        None => (0, 0),
+    };
+    trace!("IFS snapshot {} -> slot {}", generation, slot);
+
+    let hash = Sha256::new()
+        .chain_update(digest)
+        .chain_update(generation.to_be_bytes())
+        .finalize();
+    let mut salt = [0; SALT_SIZE];
+    salt.copy_from_slice(&hash[..SALT_SIZE]);
+    Ok(Some(Update {
+        snapshot: Snapshot {
+            slot,
+            generation,
+            digest,
+        },
+        key: backup_key(secret, salt),
+        state: UpdateState::Erase(0),
+    }))
+}
+
+impl Update {
+    /// erase or write the next `STEP_LEN` bytes of the snapshot, returns whether
+    /// the snapshot is complete
+    ///
+    /// The IFS must not change between the steps of a snapshot. The backup is
+    /// created again for every step, but only the part of the step is written
+    /// (see `WindowBackend`), thus no step takes long.
+    pub fn step<I: Storage, E: Storage>(
+        &mut self,
+        ifs: &Filesystem<'_, I>,
+        efs_storage: &mut E,
+    ) -> Result<bool> {
+        let slot = self.snapshot.slot;
+        match self.state {
+            // the first step erases the generation block of the replaced snapshot
+            UpdateState::Erase(offset) => {
+                efs_storage
+                    .erase(slot_offset::<E>(slot) + offset, STEP_LEN)
+                    .map_err(|_| FSBackupError::BackendEraseErr)?;
+                let offset = offset + STEP_LEN;
+                self.state = if offset < SLOT_LEN {
+                    UpdateState::Erase(offset)
+                } else {
+                    UpdateState::Write(0)
+                };
+                Ok(false)
+            }
+            UpdateState::Write(position) => {
+                let backend = backend(efs_storage, slot, self.key.clone());
+                let mut backend = WindowBackend::new(backend, position..position + STEP_LEN);
+                match backend.backup_filtered(ifs, &EXCLUDED) {
+                    Ok(_) => {}
+                    Err(_) if backend.is_stopped() => {
+                        self.state = UpdateState::Write(position + STEP_LEN);
+                        return Ok(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+                write_snapshot(efs_storage, &self.snapshot)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// write the generation block of a complete snapshot
+fn write_snapshot<S: Storage>(efs_storage: &mut S, snapshot: &Snapshot) -> Result<()> {
+    let mut data = [0; 4 + size_of::<Digest>()];
+    data[..4].copy_from_slice(&snapshot.generation.to_be_bytes());
+    data[4..].copy_from_slice(&snapshot.digest);
+    let data = assemble_block(SNAPSHOT_MAGIC, &data)?;
+    let mut block = [0; RW_SIZE];
+    block[..data.len()].copy_from_slice(&data);
+    efs_storage
+        .write(slot_offset::<S>(snapshot.slot), &block)
+        .map_err(|_| FSBackupError::BackendWriteErr)?;
+    Ok(())
+}
+
+/// write a new snapshot of `ifs` at once unless the digest of the newest snapshot
+/// matches it already, returns whether a snapshot was written
+pub fn update<I: Storage, E: Storage>(
+    ifs: &Filesystem<'_, I>,
+    efs_storage: &mut E,
+    secret: &Secret,
+) -> Result<bool> {
+    let Some(mut update) = begin(ifs, efs_storage, secret)? else {
+        return Ok(false);
+    };
+    while !update.step(ifs, efs_storage)? {}
+    Ok(true)
+}
+
+/// format the IFS and restore the newest snapshot which can be restored
+pub fn restore<B: Board + ?Sized>(
+    ifs_storage: &mut B::InternalStorage,
+    ifs_alloc: &mut Allocation<B::InternalStorage>,
+    efs_storage: &mut B::ExternalStorage,
+    secret: &Secret,
+) -> Result<()> {
+    let key = restore_key(secret);
+    for Snapshot { slot, .. } in snapshots(efs_storage).into_iter().flatten() {
+        // from here on the snapshot might be the only copy, see `resume`
+        if backend(efs_storage, slot, key.clone())
+            .begin_restore()
+            .is_err()
+        {
+            continue;
+        }
+
+        info_now!("restoring IFS snapshot from slot {}", slot);
+        Filesystem::format(ifs_storage).map_err(|_| FSBackupError::LittleFs2Err)?;
+        B::prepare_ifs(ifs_storage);
+        if restore_slot::<B>(ifs_storage, ifs_alloc, efs_storage, slot, key.clone()).is_ok() {
+            return Ok(());
+        }
+        error_now!("failed to restore IFS snapshot from slot {}", slot);
+    }
+    Err(FSBackupError::RestoreErr)
+}
+
+/// finish a restore of a snapshot interrupted (e.g., by a power loss),
+/// returns whether there was such a restore
+pub fn resume<B: Board + ?Sized>(
+    ifs_storage: &mut B::InternalStorage,
+    ifs_alloc: &mut Allocation<B::InternalStorage>,
+    efs_storage: &mut B::ExternalStorage,
+    secret: &Secret,
+) -> Result<bool> {
+    let key = restore_key(secret);
+    for Snapshot { slot, .. } in snapshots(efs_storage).into_iter().flatten() {
+        let mut backend = backend(efs_storage, slot, key.clone());
+        if !matches!(backend.progress(), Ok(Progress::Restoring { .. })) {
+            continue;
+        }
+
+        // formatting the IFS was interrupted => start the restore all over
+        if !Filesystem::is_mountable(ifs_storage) {
+            backend.begin_restore()?;
+            Filesystem::format(ifs_storage).map_err(|_| FSBackupError::LittleFs2Err)?;
+            B::prepare_ifs(ifs_storage);
+        }
+
+        restore_slot::<B>(ifs_storage, ifs_alloc, efs_storage, slot, key.clone())?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// erase all snapshots, e.g., on a factory reset
+pub fn erase<S: Storage>(efs_storage: &mut S) -> Result<()> {
+    (0..SLOTS).try_for_each(|slot| erase_slot(efs_storage, slot))
+}
+
+fn restore_slot<B: Board + ?Sized>(
+    ifs_storage: &mut B::InternalStorage,
+    ifs_alloc: &mut Allocation<B::InternalStorage>,
+    efs_storage: &mut B::ExternalStorage,
+    slot: usize,
+    key: BackupKey,
+) -> Result<()> {
+    let ifs = Filesystem::mount(ifs_alloc, ifs_storage).map_err(|_| FSBackupError::LittleFs2Err)?;
+    let result = backend(efs_storage, slot, key).resume(&ifs);
+    if result.is_err() {
+        // a snapshot which cannot be restored must never be resumed into a
+        // filesystem which is in use afterwards
+        erase_slot(efs_storage, slot)?;
+    }
+    result.map(|_| ())
+}
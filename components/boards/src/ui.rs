@@ -23,6 +23,16 @@ const RED: Intensities = Intensities {
     green: 0,
     blue: 0,
 };
+const GREEN: Intensities = Intensities {
+    red: 0,
+    green: u8::MAX,
+    blue: 0,
+};
+const BLUE: Intensities = Intensities {
+    red: 0,
+    green: 0,
+    blue: u8::MAX,
+};
 const TEAL: Intensities = Intensities {
     red: 0,
     green: u8::MAX,
@@ -34,6 +44,8 @@ const WHITE: Intensities = Intensities {
     blue: u8::MAX,
 };
 
+const SELF_TEST_COLORS: &[Intensities] = &[RED, GREEN, BLUE, WHITE];
+
 static WAITING: AtomicBool = AtomicBool::new(false);
 
 fn set_waiting(waiting: bool) {
@@ -48,9 +60,22 @@ pub trait Clock {
     fn uptime(&mut self) -> Duration;
 }
 
+/// Whether the buttons are used for a user presence check although they are ignored, see
+/// `apps::CustomStatus::SelfTestButton`.
+#[derive(Clone, Copy, PartialEq)]
+enum ButtonTest {
+    Off,
+    /// the next user presence check uses the buttons
+    Armed,
+    /// the current user presence check uses the buttons
+    Active,
+}
+
 pub struct UserInterface<C, P, L> {
     clock: C,
     buttons: Option<P>,
+    ignore_buttons: bool,
+    button_test: ButtonTest,
     rgb: Option<L>,
     status: Status,
     provisioner: bool,
@@ -60,16 +85,15 @@ impl<C: Clock, P: UserPresence, L: RgbLed> UserInterface<C, P, L> {
     pub fn new(mut clock: C, buttons: Option<P>, rgb: Option<L>) -> Self {
         let uptime = clock.uptime();
         let status = Status::Startup(uptime);
-        let buttons = if cfg!(feature = "no-buttons") {
-            None
-        } else {
-            buttons
-        };
+        // the buttons are still needed for the self test of the provisioner
+        let ignore_buttons = cfg!(feature = "no-buttons");
         let provisioner = cfg!(feature = "provisioner");
 
         let mut ui = Self {
             clock,
             buttons,
+            ignore_buttons,
+            button_test: ButtonTest::Off,
             status,
             rgb,
             provisioner,
@@ -89,6 +113,9 @@ impl<C: Clock, P: UserPresence, L: RgbLed> UserInterface<C, P, L> {
 
 impl<C: Clock, P: UserPresence, L: RgbLed> platform::UserInterface for UserInterface<C, P, L> {
     fn check_user_presence(&mut self) -> consent::Level {
+        if self.ignore_buttons && self.button_test != ButtonTest::Active {
+            return consent::Level::Normal;
+        }
         if let Some(buttons) = &mut self.buttons {
             set_waiting(true);
             let level = buttons.check_user_presence();
@@ -100,6 +127,15 @@ impl<C: Clock, P: UserPresence, L: RgbLed> platform::UserInterface for UserInter
     }
 
     fn set_status(&mut self, status: ui::Status) {
+        self.button_test = match (&status, self.button_test) {
+            (ui::Status::Custom(custom), _)
+                if *custom == u8::from(apps::CustomStatus::SelfTestButton) =>
+            {
+                ButtonTest::Armed
+            }
+            (ui::Status::WaitingForUserPresence, ButtonTest::Armed) => ButtonTest::Active,
+            _ => ButtonTest::Off,
+        };
         let uptime = self.uptime();
         self.status.update(status, uptime);
         self.refresh_ui(uptime);
@@ -128,18 +164,26 @@ impl CustomStatus {
         let color = match self.0 {
             apps::CustomStatus::ReverseHotpSuccess => TEAL,
             apps::CustomStatus::ReverseHotpError => RED,
+            apps::CustomStatus::SelfTestLed => {
+                return LedMode::cycling(SELF_TEST_COLORS, Duration::from_millis(500), start);
+            }
+            apps::CustomStatus::SelfTestButton => WHITE,
         };
         LedMode::simple_blinking(color, start)
     }
 
     fn allow_update(&self) -> bool {
-        false
+        // the button test is followed by the user presence check
+        matches!(self.0, apps::CustomStatus::SelfTestButton)
     }
 
     fn duration(&self) -> Option<Duration> {
         match self.0 {
             apps::CustomStatus::ReverseHotpSuccess => Some(Duration::from_secs(10)),
             apps::CustomStatus::ReverseHotpError => None,
+            // two cycles
+            apps::CustomStatus::SelfTestLed => Some(Duration::from_secs(4)),
+            apps::CustomStatus::SelfTestButton => None,
         }
     }
 }
@@ -251,6 +295,12 @@ pub enum LedMode {
         period: Duration,
         start: Duration,
     },
+    Cycling {
+        colors: &'static [Intensities],
+        /// duration of each color
+        period: Duration,
+        start: Duration,
+    },
 }
 
 impl LedMode {
@@ -276,6 +326,14 @@ impl LedMode {
         Self::blinking(color, BLACK, Duration::from_millis(500), start)
     }
 
+    pub fn cycling(colors: &'static [Intensities], period: Duration, start: Duration) -> Self {
+        Self::Cycling {
+            colors,
+            period,
+            start,
+        }
+    }
+
     pub fn color(&self, uptime: Duration) -> Intensities {
         match self {
             Self::Constant { color } => *color,
@@ -293,6 +351,14 @@ impl LedMode {
                     *off_color
                 }
             }
+            Self::Cycling {
+                colors,
+                period,
+                start,
+            } => {
+                let index = (uptime - *start).as_millis() / period.as_millis();
+                colors[index as usize % colors.len()]
+            }
         }
     }
 }
@@ -599,6 +599,27 @@ pub struct RegisterBlock {
     pub regu_cfg: u8,
 }
 
+impl RegisterBlock {
+    /// Returns the registers in the order of their addresses, starting at 0x02.
+    pub fn to_bytes(&self) -> [u8; 13] {
+        [
+            self.fifo_count,
+            self.rf_status,
+            self.rf_txen,
+            self.rf_baud,
+            self.rf_rats,
+            self.main_irq,
+            self.fifo_irq,
+            self.aux_irq,
+            self.main_irq_mask,
+            self.fifo_irq_mask,
+            self.aux_irq_mask,
+            self.nfc_cfg,
+            self.regu_cfg,
+        ]
+    }
+}
+
 // impl ufmt::uDisplay for Eeprom {
 //     fn fmt<W: ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
 //     where
@@ -0,0 +1,121 @@
+use crate::lfs_backup::{FSBackupError, Result};
+
+// LZSS-style format: a flag byte announces the type of the following 8 tokens
+// (bit set => match, LSB first). A literal is a single byte, a match is
+// <offset - 1: 12 bits><length - MIN_MATCH: 4 bits>, followed by another
+// length byte if the 4 bits are all set.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + 0xf + 0xff;
+const MAX_OFFSET: usize = 1 << 12;
+const HASH_BITS: u32 = 8;
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+fn push(output: &mut [u8], out: &mut usize, byte: u8) -> Option<()> {
+    *output.get_mut(*out)? = byte;
+    *out += 1;
+    Some(())
+}
+
+/// compress `input` into `output`, returns the compressed length
+///
+/// Returns `None` if the compressed data is not smaller than `input`.
+pub fn compress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    // last position (+1) of each hashed 3-byte sequence, 0 => none
+    let mut table = [0u16; 1 << HASH_BITS];
+    // the compressed data has to be smaller than the input
+    let limit = input.len().min(output.len());
+    let output = &mut output[..limit];
+    let mut out = 0;
+    let mut pos = 0;
+    let mut flags = 0;
+    let mut token = 8;
+
+    while pos < input.len() {
+        if token == 8 {
+            flags = out;
+            push(output, &mut out, 0)?;
+            token = 0;
+        }
+
+        let mut len = 0;
+        let mut offset = 0;
+        if pos + MIN_MATCH <= input.len() {
+            let h = hash(&input[pos..]);
+            if let Some(candidate) = (table[h] as usize).checked_sub(1) {
+                if pos - candidate <= MAX_OFFSET {
+                    // overlapping matches are fine, e.g., for runs of the same byte
+                    len = input[pos..]
+                        .iter()
+                        .zip(&input[candidate..])
+                        .take(MAX_MATCH)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    offset = pos - candidate;
+                }
+            }
+            table[h] = (pos + 1) as u16;
+        }
+
+        if len >= MIN_MATCH {
+            let extra = len - MIN_MATCH;
+            let nibble = extra.min(0xf);
+            let distance = offset - 1;
+            push(output, &mut out, (distance >> 4) as u8)?;
+            push(output, &mut out, ((distance & 0xf) << 4 | nibble) as u8)?;
+            if nibble == 0xf {
+                push(output, &mut out, (extra - 0xf) as u8)?;
+            }
+            output[flags] |= 1 << token;
+            pos += len;
+        } else {
+            push(output, &mut out, input[pos])?;
+            pos += 1;
+        }
+        token += 1;
+    }
+    (out < input.len()).then_some(out)
+}
+
+/// decompress `input` (see `compress`) into `output`, returns the decompressed length
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let mut input = input.iter().copied();
+    let mut next = || input.next().ok_or(FSBackupError::CompressionErr);
+    let mut out = 0;
+
+    while let Ok(flags) = next() {
+        for token in 0..8 {
+            let Ok(byte) = next() else {
+                // the last flag byte may announce less than 8 tokens
+                return Ok(out);
+            };
+            if flags & (1 << token) == 0 {
+                *output.get_mut(out).ok_or(FSBackupError::CompressionErr)? = byte;
+                out += 1;
+                continue;
+            }
+
+            let low = next()?;
+            let offset = ((byte as usize) << 4 | (low >> 4) as usize) + 1;
+            let mut len = (low & 0xf) as usize + MIN_MATCH;
+            if len == MIN_MATCH + 0xf {
+                len += next()? as usize;
+            }
+            let start = out
+                .checked_sub(offset)
+                .ok_or(FSBackupError::CompressionErr)?;
+            if out + len > output.len() {
+                return Err(FSBackupError::CompressionErr);
+            }
+            // byte by byte, as source & destination may overlap
+            for idx in 0..len {
+                output[out + idx] = output[start + idx];
+            }
+            out += len;
+        }
+    }
+    Ok(out)
+}
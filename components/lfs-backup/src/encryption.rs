@@ -0,0 +1,148 @@
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+use sha2::{Digest, Sha256};
+
+use crate::lfs_backup::{FSBackupError, Result};
+
+pub const SALT_SIZE: usize = 8;
+pub const TAG_SIZE: usize = 16;
+
+const KEY_DERIVATION_CONTEXT: &[u8] = b"lfs-backup encryption key";
+
+// nonce counter value reserved for the seal over the whole backup
+const SEAL_COUNTER: u32 = u32::MAX;
+
+/// Key material for the (optional) authenticated encryption of a backup
+#[derive(Clone)]
+pub struct BackupKey {
+    key: [u8; 32],
+    salt: [u8; SALT_SIZE],
+}
+
+impl BackupKey {
+    /// derive the backup key from device-unique key material (e.g., the hardware key)
+    ///
+    /// `salt` is stored in plaintext inside the backup and is used as nonce prefix,
+    /// thus it must never be reused for two backups with the same key material
+    pub fn derive(key_material: &[u8], salt: [u8; SALT_SIZE]) -> Self {
+        let key = Sha256::new()
+            .chain_update(KEY_DERIVATION_CONTEXT)
+            .chain_update(key_material)
+            .finalize()
+            .into();
+        Self { key, salt }
+    }
+}
+
+/// State of the authenticated encryption for a single backup run
+///
+/// Each entry gets its own nonce (`salt || entry-counter`) and is bound to its
+/// predecessor by using the previous tag as associated data. The final seal
+/// authenticates the number of entries and the last tag, thus modified,
+/// reordered, dropped or truncated entries are all detected during restore.
+pub struct BackupCipher {
+    aead: ChaCha20Poly1305,
+    salt: [u8; SALT_SIZE],
+    counter: u32,
+    chain: [u8; TAG_SIZE],
+}
+
+impl BackupCipher {
+    /// cipher state for a new backup using the salt of `key`
+    pub fn new(key: &BackupKey) -> Self {
+        Self::with_salt(key, key.salt)
+    }
+
+    /// cipher state for an existing backup, using the salt read from the backup
+    pub fn with_salt(key: &BackupKey, salt: [u8; SALT_SIZE]) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key.key)),
+            salt,
+            counter: 0,
+            chain: [0u8; TAG_SIZE],
+        }
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_SIZE] {
+        &self.salt
+    }
+
+    fn nonce(&self, counter: u32) -> Nonce {
+        let mut nonce = Nonce::default();
+        nonce[..SALT_SIZE].copy_from_slice(&self.salt);
+        nonce[SALT_SIZE..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    fn next_nonce(&mut self) -> Result<Nonce> {
+        if self.counter == SEAL_COUNTER {
+            return Err(FSBackupError::EncryptionErr);
+        }
+        let nonce = self.nonce(self.counter);
+        self.counter += 1;
+        Ok(nonce)
+    }
+
+    fn associated_data(&self, prefix: &[u8]) -> Result<[u8; 4 + TAG_SIZE]> {
+        let mut aad = [0u8; 4 + TAG_SIZE];
+        aad.get_mut(..prefix.len())
+            .ok_or(FSBackupError::EncryptionErr)?
+            .copy_from_slice(prefix);
+        aad[4..].copy_from_slice(&self.chain);
+        Ok(aad)
+    }
+
+    /// encrypt `buf` in-place and return the tag, `prefix` (the length prefix) is authenticated
+    pub fn encrypt(&mut self, prefix: &[u8], buf: &mut [u8]) -> Result<[u8; TAG_SIZE]> {
+        let aad = self.associated_data(prefix)?;
+        let nonce = self.next_nonce()?;
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(&nonce, &aad, buf)
+            .map_err(|_| FSBackupError::EncryptionErr)?;
+        self.chain.copy_from_slice(&tag);
+        Ok(self.chain)
+    }
+
+    /// authenticate and decrypt `buf` in-place
+    pub fn decrypt(&mut self, prefix: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<()> {
+        if tag.len() != TAG_SIZE {
+            return Err(FSBackupError::AuthenticationErr);
+        }
+        let aad = self.associated_data(prefix)?;
+        let nonce = self.next_nonce()?;
+        self.aead
+            .decrypt_in_place_detached(&nonce, &aad, buf, Tag::from_slice(tag))
+            .map_err(|_| FSBackupError::AuthenticationErr)?;
+        self.chain.copy_from_slice(tag);
+        Ok(())
+    }
+
+    /// calculate the seal over all entries processed so far
+    pub fn seal(&self) -> Result<[u8; TAG_SIZE]> {
+        let aad = self.associated_data(&self.counter.to_be_bytes())?;
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(&self.nonce(SEAL_COUNTER), &aad, &mut [])
+            .map_err(|_| FSBackupError::EncryptionErr)?;
+        Ok(tag.into())
+    }
+
+    /// check `tag` against the seal over all entries processed so far
+    pub fn verify_seal(&self, tag: &[u8]) -> Result<()> {
+        if tag.len() != TAG_SIZE {
+            return Err(FSBackupError::AuthenticationErr);
+        }
+        let aad = self.associated_data(&self.counter.to_be_bytes())?;
+        self.aead
+            .decrypt_in_place_detached(
+                &self.nonce(SEAL_COUNTER),
+                &aad,
+                &mut [],
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| FSBackupError::AuthenticationErr)
+    }
+}
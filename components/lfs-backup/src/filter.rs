@@ -0,0 +1,153 @@
+use littlefs2::path::Path;
+
+/// Selects the entries processed by `backup_filtered` & `restore_filtered`
+///
+/// Any `Fn(&Path) -> bool` (e.g., a `should_preserve_file`-style predicate) can
+/// be used as filter, it is then called for every file and directory.
+pub trait PathFilter {
+    /// the entry (file or directory) at `path` is included
+    fn matches(&self, path: &Path) -> bool;
+
+    /// the directory at `path` may contain included entries, thus it is traversed
+    fn descend(&self, path: &Path) -> bool {
+        let _ = path;
+        true
+    }
+}
+
+impl<F: Fn(&Path) -> bool> PathFilter for F {
+    fn matches(&self, path: &Path) -> bool {
+        self(path)
+    }
+}
+
+/// Include & exclude patterns on absolute paths
+///
+/// A pattern matches a path if it matches the path itself or one of its
+/// parent directories, i.e., `/fido` matches `/fido` and `/fido/sec/00`.
+/// Inside a pattern component `*` matches any number of characters and `?`
+/// matches a single character, a `**` component matches any number of
+/// components. An entry is included if it is matched by any `include`
+/// pattern (or `include` is empty) and by none of the `exclude` patterns.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathPatterns<'a> {
+    pub include: &'a [&'a str],
+    pub exclude: &'a [&'a str],
+}
+
+impl<'a> PathPatterns<'a> {
+    pub const fn new(include: &'a [&'a str], exclude: &'a [&'a str]) -> Self {
+        Self { include, exclude }
+    }
+
+    pub const fn include(include: &'a [&'a str]) -> Self {
+        Self::new(include, &[])
+    }
+
+    pub const fn exclude(exclude: &'a [&'a str]) -> Self {
+        Self::new(&[], exclude)
+    }
+
+    fn is_excluded(&self, path: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| match_path(pattern, path) == Match::Full)
+    }
+}
+
+impl PathFilter for PathPatterns<'_> {
+    fn matches(&self, path: &Path) -> bool {
+        let path = path.as_str();
+        !self.is_excluded(path)
+            && (self.include.is_empty()
+                || self
+                    .include
+                    .iter()
+                    .any(|pattern| match_path(pattern, path) == Match::Full))
+    }
+
+    fn descend(&self, path: &Path) -> bool {
+        let path = path.as_str();
+        !self.is_excluded(path)
+            && (self.include.is_empty()
+                || self
+                    .include
+                    .iter()
+                    .any(|pattern| match_path(pattern, path) != Match::None))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Match {
+    None,
+    /// the path is a parent directory of possible matches
+    Parent,
+    /// the pattern matches the path (or one of its parent directories)
+    Full,
+}
+
+fn components(s: &str) -> impl Iterator<Item = &str> + Clone {
+    s.split('/').filter(|c| !c.is_empty())
+}
+
+fn match_path(pattern: &str, path: &str) -> Match {
+    match_components(components(pattern), components(path))
+}
+
+fn match_components<'p, 'q>(
+    mut pattern: impl Iterator<Item = &'p str> + Clone,
+    mut path: impl Iterator<Item = &'q str> + Clone,
+) -> Match {
+    loop {
+        let Some(pattern_component) = pattern.next() else {
+            return Match::Full;
+        };
+        if pattern_component == "**" {
+            // try to match the rest of the pattern at every position of the path
+            let mut best = Match::Parent;
+            loop {
+                match match_components(pattern.clone(), path.clone()) {
+                    Match::Full => return Match::Full,
+                    Match::Parent => best = Match::Parent,
+                    Match::None => {}
+                }
+                if path.next().is_none() {
+                    return best;
+                }
+            }
+        }
+        let Some(path_component) = path.next() else {
+            return Match::Parent;
+        };
+        if !match_component(pattern_component.as_bytes(), path_component.as_bytes()) {
+            return Match::None;
+        }
+    }
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    // iterative wildcard matching, backtracking to the last `*` on mismatch
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
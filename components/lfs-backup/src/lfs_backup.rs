@@ -1,5 +1,6 @@
 use littlefs2::consts::PATH_MAX;
 use littlefs2::fs::{DirEntry, Filesystem};
+use littlefs2::io::{SeekFrom, Write as _};
 
 use littlefs2::{
     path,
@@ -8,21 +9,46 @@ use littlefs2::{
 
 use serde::{Deserialize, Serialize};
 
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
 use heapless::Vec;
 use heapless_bytes::Bytes;
 
 use trussed::config::USER_ATTRIBUTE_NUMBER;
-use trussed_core::config::MAX_MESSAGE_LENGTH;
-
 use trussed_core::types::{Message, UserAttribute};
 
-pub const MAX_FS_DEPTH: usize = 8;
+use crate::compression;
+use crate::filter::PathFilter;
+
+use crate::encryption::{BackupCipher, BackupKey, SALT_SIZE, TAG_SIZE};
 
 pub const MAX_DUMP_BLOB_LENGTH: usize = 256 * 10;
 
+/// current version of the backup format, see `BackupHeader`
+///
+/// * 1: complete files inside `FSEntryBlob`s
+/// * 2: files split into `FSRecord::Chunk`s
+/// * 3: entries may be compressed, see `COMPRESSED_FLAG`
+pub const FORMAT_VERSION: u8 = 3;
+
+/// maximum size of the file contents inside a single `FSRecord::Chunk`
+pub const CHUNK_SIZE: usize = 1024;
+
+/// a journaled restore records its progress at least every `PROGRESS_INTERVAL` entries
+pub const PROGRESS_INTERVAL: usize = 16;
+/// a journaled restore is given up (`Progress::Failed`) once it was (re-)started this often
+pub const MAX_RESUME_ATTEMPTS: usize = 3;
+
 const LEN_PREFIX_SIZE: usize = 4;
+/// set in the length prefix of an entry with compressed data (format version >= 3)
+pub const COMPRESSED_FLAG: u32 = 0x8000_0000;
+const CRC_SIZE: usize = 4;
+const FS_BACKUP_HEADER_MAGIC: &[u8; 4] = b"HB||";
 const FS_BACKUP_START_DELIM: &[u8; 4] = b"SB||";
 const FS_BACKUP_END_DELIM: &[u8; 4] = b"||EB";
+const FS_BACKUP_START_ENC_DELIM: &[u8; 4] = b"SE||";
+const FS_BACKUP_END_ENC_DELIM: &[u8; 4] = b"||EE";
+const FS_BACKUP_PROGRESS_MAGIC: &[u8; 4] = b"PR||";
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum FSBackupError {
@@ -33,12 +59,20 @@ pub enum FSBackupError {
     BackendEraseErr,
     SerializeErr,
     DeserializeErr,
-    PathStackFullErr,
     EndOfBackupBlobs,
     StartOfBackupBlobs,
     RestoreErr,
     UserAttributeErr,
     DataAssemblyErr,
+    EncryptionErr,
+    AuthenticationErr,
+    MissingKeyErr,
+    ChecksumErr,
+    HeaderErr,
+    UnsupportedVersionErr,
+    VerifyErr,
+    RestoreConflictErr,
+    CompressionErr,
 }
 
 impl From<littlefs2::io::Error> for FSBackupError {
@@ -59,19 +93,423 @@ pub(crate) fn postcard_serialize_bytes<T: serde::Serialize, const N: usize>(
 
 pub type Result<T, E = FSBackupError> = core::result::Result<T, E>;
 
-#[derive(Clone, Debug)]
-pub struct PathCursor {
-    pub path: PathBuf,
-    pub idx: usize,
-    pub attr: Option<UserAttribute>,
+fn to_path(bytes: &[u8]) -> Result<&Path> {
+    Path::from_bytes_with_nul(bytes).map_err(|_| FSBackupError::PathAssemblyErr)
+}
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// assemble a single block => <magic><len: big-endian u32><data><crc: big-endian u32>
+pub fn assemble_block(magic: &[u8; 4], data: &[u8]) -> Result<Bytes<MAX_DUMP_BLOB_LENGTH>> {
+    let mut buf = Bytes::<MAX_DUMP_BLOB_LENGTH>::new();
+    buf.extend_from_slice(magic)
+        .map_err(|_| FSBackupError::DataAssemblyErr)?;
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes())
+        .map_err(|_| FSBackupError::DataAssemblyErr)?;
+    buf.extend_from_slice(data)
+        .map_err(|_| FSBackupError::DataAssemblyErr)?;
+    let crc = CRC32.checksum(&buf);
+    buf.extend_from_slice(&crc.to_be_bytes())
+        .map_err(|_| FSBackupError::DataAssemblyErr)?;
+    Ok(buf)
+}
+
+/// check a block assembled by `assemble_block`, returns its data
+pub fn parse_block<'a>(magic: &[u8; 4], chunk: &'a [u8]) -> Result<&'a [u8]> {
+    if chunk.get(..LEN_PREFIX_SIZE) != Some(magic) {
+        return Err(FSBackupError::HeaderErr);
+    }
+    let len_bytes = chunk
+        .get(LEN_PREFIX_SIZE..2 * LEN_PREFIX_SIZE)
+        .ok_or(FSBackupError::HeaderErr)?;
+    let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+
+    let data_end = (2 * LEN_PREFIX_SIZE)
+        .checked_add(len)
+        .ok_or(FSBackupError::HeaderErr)?;
+    let data = chunk.get(..data_end).ok_or(FSBackupError::HeaderErr)?;
+    let crc = chunk
+        .get(data_end..data_end + CRC_SIZE)
+        .ok_or(FSBackupError::HeaderErr)?;
+    if CRC32.checksum(data).to_be_bytes() != crc {
+        return Err(FSBackupError::ChecksumErr);
+    }
+    Ok(&data[2 * LEN_PREFIX_SIZE..])
+}
+
+/// every level of the tree adds at least a separator and one character to the
+/// path, thus no entry can be nested deeper than this
+const MAX_WALK_DEPTH: usize = PATH_MAX / 2;
+
+/// number of directory levels that `walk` keeps open at the same time, each one
+/// needs a directory handle and an entry on the stack
+const OPEN_WALK_DEPTH: usize = 4;
+
+/// call `f` for every entry inside the directory `dir` and its subdirectories
+///
+/// Directories are passed to `f` before their contents, which are only visited
+/// if `f` returns `true`. The first `OPEN_WALK_DEPTH` levels are kept open while
+/// their subdirectories are visited, thus they are read once and the walk is
+/// linear in the number of entries for the usual layouts (e.g.,
+/// `/fido/dat/rk/<rp>/<credential>`). Deeper levels are visited by
+/// `walk_reopen`, thus the stack usage does not depend on the depth of the tree.
+pub(crate) fn walk<S: littlefs2::driver::Storage>(
+    fs: &Filesystem<S>,
+    dir: &Path,
+    f: &mut impl FnMut(&DirEntry) -> Result<bool>,
+) -> Result<()> {
+    walk_open(fs, dir, OPEN_WALK_DEPTH, f)
+}
+
+/// walk `dir` in one pass, keeping it open while its subdirectories are visited
+/// with `levels - 1` open levels
+fn walk_open<S: littlefs2::driver::Storage>(
+    fs: &Filesystem<S>,
+    dir: &Path,
+    levels: usize,
+    f: &mut impl FnMut(&DirEntry) -> Result<bool>,
+) -> Result<()> {
+    fs.read_dir_and_then(dir, |entries| {
+        let mut visit = || -> Result<()> {
+            // skip "." & ".."
+            for entry in entries.skip(2) {
+                let entry = entry?;
+                if f(&entry)? && entry.file_type().is_dir() {
+                    if levels > 1 {
+                        walk_open(fs, entry.path(), levels - 1, f)?;
+                    } else {
+                        walk_reopen(fs, entry.path(), f)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        Ok(visit())
+    })?
+}
+
+/// walk `dir` with only the current directory open
+///
+/// The position inside the parents is kept on a fixed-size stack of entry
+/// offsets. Each directory is read once, except for the entries preceding a
+/// subdirectory, which are skipped again when returning from that subdirectory.
+fn walk_reopen<S: littlefs2::driver::Storage>(
+    fs: &Filesystem<S>,
+    dir: &Path,
+    f: &mut impl FnMut(&DirEntry) -> Result<bool>,
+) -> Result<()> {
+    // number of entries already visited per open level, the last one is `current`
+    let mut offsets: Vec<usize, MAX_WALK_DEPTH> = Vec::new();
+    offsets.push(0).unwrap();
+    let mut current = PathBuf::from(dir);
+
+    while let Some(offset) = offsets.last_mut() {
+        let start = *offset;
+        let subdir = fs.read_dir_and_then(&current, |entries| {
+            let mut visit = || -> Result<Option<PathBuf>> {
+                // skip "." & ".." and the entries visited before
+                for entry in entries.skip(2 + start) {
+                    let entry = entry?;
+                    *offset += 1;
+                    if f(&entry)? && entry.file_type().is_dir() {
+                        return Ok(Some(PathBuf::from(entry.path())));
+                    }
+                }
+                Ok(None)
+            };
+            Ok(visit())
+        })??;
+
+        match subdir {
+            Some(subdir) => {
+                offsets
+                    .push(0)
+                    .map_err(|_| FSBackupError::PathAssemblyErr)?;
+                current = subdir;
+            }
+            None => {
+                offsets.pop();
+                if !offsets.is_empty() {
+                    current = current.parent().ok_or(FSBackupError::PathAssemblyErr)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// read the user attribute of the entry at `path`
+fn user_attribute<S: littlefs2::driver::Storage>(
+    fs: &Filesystem<S>,
+    path: &Path,
+) -> Result<Option<UserAttribute>> {
+    let mut buffer = UserAttribute::new();
+    buffer.resize_to_capacity();
+    let attr = fs
+        .attribute(path, USER_ATTRIBUTE_NUMBER, &mut buffer)?
+        .map(|v| v.data().len())
+        .map(|n| {
+            buffer.truncate(n);
+            buffer
+        });
+    Ok(attr)
 }
 
+/// Entry of legacy and format version 1 backups, contains the complete file
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FSEntryBlob {
-    path: Bytes<PATH_MAX>,
-    is_dir: bool,
-    content: Option<Message>,
-    attr: Option<UserAttribute>,
+    pub path: Bytes<PATH_MAX>,
+    pub is_dir: bool,
+    pub content: Option<Message>,
+    pub attr: Option<UserAttribute>,
+}
+
+/// Record of format version 2 backups
+///
+/// A `File` is followed by `Chunk`s carrying exactly `len` bytes of its contents.
+// no_std => no boxing, records are only kept one at a time anyways
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FSRecord {
+    Dir {
+        path: Bytes<PATH_MAX>,
+        attr: Option<UserAttribute>,
+    },
+    File {
+        path: Bytes<PATH_MAX>,
+        attr: Option<UserAttribute>,
+        len: u32,
+    },
+    Chunk(Bytes<CHUNK_SIZE>),
+}
+
+/// Differences between a backup and a `Filesystem`, see `BackupBackend::verify`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// number of directories & files inside the backup
+    pub dirs: usize,
+    pub files: usize,
+    /// entries of the backup missing in the filesystem (or of a different type)
+    pub missing: usize,
+    /// files with different contents (or sizes)
+    pub content_mismatch: usize,
+    /// entries with different user attributes
+    pub attr_mismatch: usize,
+    /// entries of the filesystem missing in the backup
+    pub extra: usize,
+}
+
+impl VerifyReport {
+    /// backup and filesystem are equal
+    pub fn is_equal(&self) -> bool {
+        self.missing == 0
+            && self.content_mismatch == 0
+            && self.attr_mismatch == 0
+            && self.extra == 0
+    }
+}
+
+/// Handling of backup entries which already exist in the target `Filesystem`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RestorePolicy {
+    /// fail with `RestoreConflictErr` before anything is written
+    #[default]
+    Fail,
+    /// keep existing entries (contents & user attributes) untouched
+    Skip,
+    /// replace existing entries, also if their type differs
+    Overwrite,
+    /// restore into existing directories, fail for any other existing entry
+    MergeDirs,
+}
+
+/// What happens to a single entry of the backup during restore
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RestoreAction {
+    Create,
+    Skip,
+    /// existing entry of the same type
+    Overwrite,
+    /// existing entry of a different type, removed before the entry is created
+    Replace,
+    Merge,
+}
+
+impl RestorePolicy {
+    /// `existing`: `Some(is_dir)` of the entry at the same path inside the target
+    fn resolve(self, existing: Option<bool>, is_dir: bool) -> Result<RestoreAction> {
+        match (self, existing) {
+            (_, None) => Ok(RestoreAction::Create),
+            (Self::Skip, Some(_)) => Ok(RestoreAction::Skip),
+            (Self::Overwrite, Some(existing)) if existing == is_dir => Ok(RestoreAction::Overwrite),
+            (Self::Overwrite, Some(_)) => Ok(RestoreAction::Replace),
+            (Self::MergeDirs, Some(true)) if is_dir => Ok(RestoreAction::Merge),
+            _ => Err(FSBackupError::RestoreConflictErr),
+        }
+    }
+
+    /// the policy fails for some conflicts, which thus have to be found upfront
+    fn may_fail(self) -> bool {
+        matches!(self, Self::Fail | Self::MergeDirs)
+    }
+}
+
+/// Result of a restore, see `BackupBackend::restore_with`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// number of created directories & files
+    pub dirs: usize,
+    pub files: usize,
+    /// existing directories restored into (`RestorePolicy::MergeDirs`)
+    pub merged: usize,
+    /// existing entries left untouched (`RestorePolicy::Skip`)
+    pub skipped: usize,
+    /// existing entries replaced (`RestorePolicy::Overwrite`)
+    pub overwritten: usize,
+}
+
+/// Progress of a backup & restore run, see `BackupBackend::progress`
+///
+/// `Restoring`, `Restored` & `Failed` are recorded in the journal behind the backup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Progress {
+    /// no (complete) backup, it has to be (re-)started
+    Incomplete,
+    /// complete backup, no restore started
+    Complete,
+    /// restore started, the first `entries` entries are restored completely
+    Restoring { entries: u32 },
+    /// restore finished
+    Restored,
+    /// restore given up, the backup is kept but never resumed again
+    Failed,
+}
+
+/// Entry of a backup of any format version
+// no_std => no boxing, entries are only kept one at a time anyways
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum BackupEntry {
+    Record(FSRecord),
+    Blob(FSEntryBlob),
+}
+
+/// Header stored in the first `RW_SIZE` block of a backup
+///
+/// As the counts are only known after all entries are written, the header is
+/// written last into the (skipped) first block of the backup.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupHeader {
+    pub format_version: u8,
+    /// firmware version (see `utils::Version::encode`) which created the backup
+    pub firmware_version: u32,
+    pub encrypted: bool,
+    pub entries: u32,
+    pub dirs: u32,
+    pub files: u32,
+    /// length of all records following the header (w/o padding)
+    pub total_len: u32,
+}
+
+/// State of a single backup or restore run
+pub struct BackupState {
+    cipher: Option<BackupCipher>,
+    /// `None` => legacy backup w/o header, thus w/o checksums
+    header: Option<BackupHeader>,
+    crc: Digest<'static, u32>,
+    len: usize,
+    entries: usize,
+    dirs: usize,
+    files: usize,
+    /// path of the current file & its number of bytes still to be streamed
+    file: Bytes<PATH_MAX>,
+    remaining: usize,
+    /// position of the next progress record of a journaled restore
+    journal: Option<usize>,
+    /// number of entries restored when the progress was recorded last
+    checkpoint: usize,
+}
+
+impl BackupState {
+    fn new(cipher: Option<BackupCipher>, header: Option<BackupHeader>) -> Self {
+        Self {
+            cipher,
+            header,
+            crc: CRC32.digest(),
+            len: 0,
+            entries: 0,
+            dirs: 0,
+            files: 0,
+            file: Bytes::new(),
+            remaining: 0,
+            journal: None,
+            checkpoint: 0,
+        }
+    }
+
+    /// header of the backup, `None` for legacy (headerless) backups
+    pub fn header(&self) -> Option<&BackupHeader> {
+        self.header.as_ref()
+    }
+
+    fn has_checksums(&self) -> bool {
+        self.header.is_some()
+    }
+
+    /// entries are streamed as `FSRecord`s (format version >= 2)
+    fn is_streamed(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| header.format_version >= 2)
+    }
+
+    /// entries may be compressed (format version >= 3)
+    fn may_be_compressed(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| header.format_version >= 3)
+    }
+
+    /// account `record` & make sure file contents are complete
+    fn account(&mut self, record: &FSRecord) -> Result<()> {
+        match record {
+            FSRecord::Dir { .. } | FSRecord::File { .. } if self.remaining != 0 => {
+                return Err(FSBackupError::DataAssemblyErr);
+            }
+            FSRecord::Dir { .. } => self.dirs += 1,
+            FSRecord::File { path, len, .. } => {
+                self.files += 1;
+                self.file = path.clone();
+                self.remaining = *len as usize;
+            }
+            FSRecord::Chunk(data) => {
+                self.remaining = self
+                    .remaining
+                    .checked_sub(data.len())
+                    .ok_or(FSBackupError::DataAssemblyErr)?;
+            }
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn update(&mut self, record: &[u8]) {
+        self.crc.update(record);
+        self.len += record.len();
+    }
+
+    /// the header describing everything processed so far
+    fn summary(&self, firmware_version: u32) -> BackupHeader {
+        BackupHeader {
+            format_version: FORMAT_VERSION,
+            firmware_version,
+            encrypted: self.cipher.is_some(),
+            entries: self.entries as u32,
+            dirs: self.dirs as u32,
+            files: self.files as u32,
+            total_len: self.len as u32,
+        }
+    }
 }
 
 pub trait BackupBackend {
@@ -86,65 +524,217 @@ pub trait BackupBackend {
     fn erase(&mut self) -> Result<usize>;
     /// reset internal cursor
     fn reset(&mut self);
+    /// position of the internal cursor relative to the start of the backup
+    fn position(&self) -> usize;
+    /// move the internal cursor to `position` (a multiple of `RW_SIZE`)
+    fn seek(&mut self, position: usize);
 
-    /// write backup-start delimiter
-    fn write_start(&mut self) -> Result<usize> {
-        self.write(FS_BACKUP_START_DELIM.as_slice())
+    /// key used to encrypt & authenticate the backup, `None` => plaintext backup
+    fn key(&self) -> Option<&BackupKey> {
+        None
     }
 
-    /// write backup-end delimiter
-    fn write_end(&mut self) -> Result<usize> {
-        self.write(FS_BACKUP_END_DELIM.as_slice())
+    /// compress entries if this makes them smaller
+    fn compression(&self) -> bool {
+        false
     }
 
-    /// write a `littlefs2::fs::DirEntry` to the backend
-    fn write_entry(
-        &mut self,
-        entry: &DirEntry,
-        content: Option<Message>,
-        attr: Option<UserAttribute>,
-    ) -> Result<usize> {
-        let path_bytes =
-            Bytes::<PATH_MAX>::try_from(entry.path().as_str_ref_with_trailing_nul().as_bytes())
-                .map_err(|_| FSBackupError::PathAssemblyErr)?;
-
-        let blob = FSEntryBlob {
-            path: path_bytes,
-            is_dir: entry.file_type().is_dir(),
-            content,
-            attr,
+    /// firmware version (see `utils::Version::encode`) recorded in the header
+    fn firmware_version(&self) -> u32 {
+        0
+    }
+
+    /// write the header into the first block, the cursor has to point at the start
+    fn write_header(&mut self, header: &BackupHeader) -> Result<usize> {
+        let raw_header: Vec<u8, 64> =
+            postcard_serialize_bytes(header).map_err(|_| FSBackupError::SerializeErr)?;
+        let buf = assemble_block(FS_BACKUP_HEADER_MAGIC, &raw_header)?;
+        self.write(buf.as_slice())
+    }
+
+    /// write a record (start, entry or end) and account it in `state`
+    fn write_record(&mut self, record: &[u8], state: &mut BackupState) -> Result<usize> {
+        state.update(record);
+        self.write(record)
+    }
+
+    /// write backup-start delimiter (followed by the salt for encrypted backups)
+    fn write_start(&mut self, state: &mut BackupState) -> Result<usize> {
+        let mut buf = [0u8; LEN_PREFIX_SIZE + SALT_SIZE];
+        let len = match &state.cipher {
+            None => {
+                buf[..LEN_PREFIX_SIZE].copy_from_slice(FS_BACKUP_START_DELIM);
+                LEN_PREFIX_SIZE
+            }
+            Some(cipher) => {
+                buf[..LEN_PREFIX_SIZE].copy_from_slice(FS_BACKUP_START_ENC_DELIM);
+                buf[LEN_PREFIX_SIZE..].copy_from_slice(cipher.salt());
+                buf.len()
+            }
+        };
+        self.write_record(&buf[..len], state)
+    }
+
+    /// write backup-end delimiter (followed by the seal for encrypted backups)
+    /// and the checksum over all records
+    fn write_end(&mut self, state: &mut BackupState) -> Result<usize> {
+        let mut buf = [0u8; LEN_PREFIX_SIZE + TAG_SIZE + CRC_SIZE];
+        let mut len = match &state.cipher {
+            None => {
+                buf[..LEN_PREFIX_SIZE].copy_from_slice(FS_BACKUP_END_DELIM);
+                LEN_PREFIX_SIZE
+            }
+            Some(cipher) => {
+                buf[..LEN_PREFIX_SIZE].copy_from_slice(FS_BACKUP_END_ENC_DELIM);
+                buf[LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + TAG_SIZE].copy_from_slice(&cipher.seal()?);
+                LEN_PREFIX_SIZE + TAG_SIZE
+            }
+        };
+        state.crc.update(&buf[..len]);
+        let crc = state.crc.clone().finalize();
+        buf[len..len + CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+        len += CRC_SIZE;
+        state.len += len;
+        self.write(&buf[..len])
+    }
+
+    /// read the header (if any) and the backup-start delimiter, returns the
+    /// state for reading the entries
+    fn read_start(&mut self) -> Result<BackupState> {
+        let mut chunk_one: Bytes<MAX_DUMP_BLOB_LENGTH> = self.read(Self::RW_SIZE)?;
+
+        let header = if chunk_one.get(..LEN_PREFIX_SIZE) == Some(FS_BACKUP_HEADER_MAGIC) {
+            let header = Self::parse_header(&chunk_one)?;
+            chunk_one = self.read(Self::RW_SIZE)?;
+            Some(header)
+        } else {
+            // legacy backup, starts directly with the start delimiter
+            None
+        };
+
+        let prefix = chunk_one
+            .get(..LEN_PREFIX_SIZE)
+            .ok_or(FSBackupError::BackendReadErr)?;
+
+        let cipher = if prefix == FS_BACKUP_START_DELIM {
+            // never silently accept a plaintext backup if we expect an encrypted one
+            if self.key().is_some() {
+                return Err(FSBackupError::AuthenticationErr);
+            }
+            None
+        } else if prefix == FS_BACKUP_START_ENC_DELIM {
+            let key = self.key().ok_or(FSBackupError::MissingKeyErr)?;
+            let mut salt = [0u8; SALT_SIZE];
+            salt.copy_from_slice(
+                chunk_one
+                    .get(LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + SALT_SIZE)
+                    .ok_or(FSBackupError::BackendReadErr)?,
+            );
+            Some(BackupCipher::with_salt(key, salt))
+        } else {
+            return Err(FSBackupError::RestoreErr);
+        };
+
+        if let Some(header) = &header {
+            if header.encrypted != cipher.is_some() {
+                return Err(FSBackupError::HeaderErr);
+            }
+        }
+
+        let record_len = match cipher {
+            None => LEN_PREFIX_SIZE,
+            Some(_) => LEN_PREFIX_SIZE + SALT_SIZE,
         };
-        // assemble to-be-written blob => <data-len: big-endian u32><data>
+        let mut state = BackupState::new(cipher, header);
+        state.update(&chunk_one[..record_len]);
+        Ok(state)
+    }
+
+    /// parse and check the header block
+    fn parse_header(chunk: &[u8]) -> Result<BackupHeader> {
+        let data = parse_block(FS_BACKUP_HEADER_MAGIC, chunk)?;
+        let header: BackupHeader =
+            postcard::from_bytes(data).map_err(|_| FSBackupError::HeaderErr)?;
+        if header.format_version > FORMAT_VERSION {
+            return Err(FSBackupError::UnsupportedVersionErr);
+        }
+        Ok(header)
+    }
+
+    /// write a single `FSRecord` to the backend
+    fn write_entry(&mut self, record: &FSRecord, state: &mut BackupState) -> Result<usize> {
+        // assemble to-be-written blob => <data-len: big-endian u32><data>[<tag>]<crc>
         let raw_blob: Vec<u8, MAX_DUMP_BLOB_LENGTH> =
-            postcard_serialize_bytes(&blob).map_err(|_| FSBackupError::SerializeErr)?;
-        let raw_blob_len: u32 = raw_blob.len() as u32;
-        let raw_blob_len_bin = raw_blob_len.to_be_bytes();
+            postcard_serialize_bytes(record).map_err(|_| FSBackupError::SerializeErr)?;
 
         let mut buf = Bytes::<MAX_DUMP_BLOB_LENGTH>::new();
-        buf.extend_from_slice(&raw_blob_len_bin)
-            .map_err(|_| FSBackupError::DataAssemblyErr)?;
-        buf.extend_from_slice(&raw_blob)
+        buf.resize_to_capacity();
+        let compressed = match self.compression() {
+            true => compression::compress(&raw_blob, &mut buf[LEN_PREFIX_SIZE..]),
+            false => None,
+        };
+        let data_len = match compressed {
+            Some(len) => len,
+            None => {
+                buf.get_mut(LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + raw_blob.len())
+                    .ok_or(FSBackupError::DataAssemblyErr)?
+                    .copy_from_slice(&raw_blob);
+                raw_blob.len()
+            }
+        };
+        buf.truncate(LEN_PREFIX_SIZE + data_len);
+
+        let tag_len = if state.cipher.is_some() { TAG_SIZE } else { 0 };
+        let mut raw_blob_len: u32 = (data_len + tag_len + CRC_SIZE) as u32;
+        if compressed.is_some() {
+            raw_blob_len |= COMPRESSED_FLAG;
+        }
+        let raw_blob_len_bin = raw_blob_len.to_be_bytes();
+        buf[..LEN_PREFIX_SIZE].copy_from_slice(&raw_blob_len_bin);
+
+        if let Some(cipher) = state.cipher.as_mut() {
+            let tag = cipher.encrypt(&raw_blob_len_bin, &mut buf[LEN_PREFIX_SIZE..])?;
+            buf.extend_from_slice(&tag)
+                .map_err(|_| FSBackupError::DataAssemblyErr)?;
+        }
+
+        let crc = CRC32.checksum(&buf);
+        buf.extend_from_slice(&crc.to_be_bytes())
             .map_err(|_| FSBackupError::DataAssemblyErr)?;
 
-        self.write(buf.as_slice())
+        state.account(record)?;
+        self.write_record(buf.as_slice(), state)
     }
 
-    /// read and return the next `FSEntryBlob` from the backend
-    fn read_next(&mut self) -> Result<FSEntryBlob> {
+    /// read the next record from the backend, returns its (checked) postcard-serialized data
+    fn read_record(&mut self, state: &mut BackupState) -> Result<Bytes<MAX_DUMP_BLOB_LENGTH>> {
         let chunk_one: Bytes<MAX_DUMP_BLOB_LENGTH> = self.read(Self::RW_SIZE)?;
 
         let mut prefix = [0u8; LEN_PREFIX_SIZE];
         prefix.copy_from_slice(&chunk_one.as_slice()[..4]);
 
-        if &prefix == FS_BACKUP_START_DELIM {
+        if &prefix == FS_BACKUP_START_DELIM
+            || &prefix == FS_BACKUP_START_ENC_DELIM
+            || &prefix == FS_BACKUP_HEADER_MAGIC
+        {
             return Err(FSBackupError::StartOfBackupBlobs);
-        } else if &prefix == FS_BACKUP_END_DELIM {
+        } else if &prefix == FS_BACKUP_END_DELIM || &prefix == FS_BACKUP_END_ENC_DELIM {
+            Self::read_end(&chunk_one, state)?;
             return Err(FSBackupError::EndOfBackupBlobs);
         }
-        let blob_len: u32 = u32::from_be_bytes(prefix);
+        let mut blob_len: u32 = u32::from_be_bytes(prefix);
+        let compressed = state.may_be_compressed() && blob_len & COMPRESSED_FLAG != 0;
+        if compressed {
+            blob_len &= !COMPRESSED_FLAG;
+        }
+
+        // e.g., erased flash after a truncated backup
+        if blob_len as usize > MAX_DUMP_BLOB_LENGTH - LEN_PREFIX_SIZE {
+            return Err(FSBackupError::DataAssemblyErr);
+        }
 
         // handle blob with length > (RW_SIZE - 4) => more `read` calls
-        let postcard_bytes = if blob_len > (Self::RW_SIZE - 4) as u32 {
+        let mut postcard_bytes = if blob_len > (Self::RW_SIZE - 4) as u32 {
             let mut buf = Bytes::<MAX_DUMP_BLOB_LENGTH>::new();
             buf.extend_from_slice(&chunk_one.as_slice()[4..])
                 .map_err(|_| FSBackupError::DataAssemblyErr)?;
@@ -155,25 +745,130 @@ pub trait BackupBackend {
             buf
         // all data for entry already `read` no further `read` calls needed
         } else {
-            Bytes::try_from(&chunk_one.as_slice()[4..])
+            Bytes::try_from(&chunk_one.as_slice()[4..4 + blob_len as usize])
                 .map_err(|_| FSBackupError::BackendReadErr)?
         };
 
-        postcard::from_bytes(postcard_bytes.as_slice()).map_err(|_| FSBackupError::DeserializeErr)
+        state.update(&prefix);
+        state.update(&postcard_bytes);
+
+        let mut crc_valid = true;
+        if state.has_checksums() {
+            let data_len = postcard_bytes.len().saturating_sub(CRC_SIZE);
+            let mut digest = CRC32.digest();
+            digest.update(&prefix);
+            digest.update(&postcard_bytes[..data_len]);
+            crc_valid = postcard_bytes.len() >= CRC_SIZE
+                && postcard_bytes[data_len..] == digest.finalize().to_be_bytes();
+            postcard_bytes.truncate(data_len);
+        }
+
+        // authentication failures take precedence over checksum failures
+        if let Some(cipher) = state.cipher.as_mut() {
+            let data_len = postcard_bytes
+                .len()
+                .checked_sub(TAG_SIZE)
+                .ok_or(FSBackupError::AuthenticationErr)?;
+            let (data, tag) = postcard_bytes.split_at_mut(data_len);
+            cipher.decrypt(&prefix, data, tag)?;
+            postcard_bytes.truncate(data_len);
+        }
+
+        if !crc_valid {
+            return Err(FSBackupError::ChecksumErr);
+        }
+        if compressed {
+            let mut buf = Bytes::<MAX_DUMP_BLOB_LENGTH>::new();
+            buf.resize_to_capacity();
+            let len = compression::decompress(&postcard_bytes, &mut buf)?;
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        Ok(postcard_bytes)
     }
 
-    /// return the next filesystem entry inside 'path' after offset 'off'
-    fn get_next_entry<S: littlefs2::driver::Storage>(
-        fs: &Filesystem<S>,
-        path: &PathBuf,
-        off: usize,
-    ) -> Result<Option<DirEntry>> {
-        fs.read_dir_and_then(path, |it| {
-            // skip "." & ".."
-            Ok(it.enumerate().nth(off + 2))
-        })
-        .map_err(|_| FSBackupError::LittleFs2Err)
-        .map(|v| v.map(|iv| iv.1.unwrap()))
+    /// read and return the next `FSRecord` from the backend (format version >= 2)
+    fn read_next(&mut self, state: &mut BackupState) -> Result<FSRecord> {
+        if !state.is_streamed() {
+            return Err(FSBackupError::UnsupportedVersionErr);
+        }
+        let postcard_bytes = self.read_record(state)?;
+        let record: FSRecord = postcard::from_bytes(postcard_bytes.as_slice())
+            .map_err(|_| FSBackupError::DeserializeErr)?;
+        state.account(&record)?;
+        Ok(record)
+    }
+
+    /// read and return the next `FSEntryBlob` from the backend (legacy & format version 1)
+    fn read_next_blob(&mut self, state: &mut BackupState) -> Result<FSEntryBlob> {
+        if state.is_streamed() {
+            return Err(FSBackupError::UnsupportedVersionErr);
+        }
+        let postcard_bytes = self.read_record(state)?;
+        let blob: FSEntryBlob = postcard::from_bytes(postcard_bytes.as_slice())
+            .map_err(|_| FSBackupError::DeserializeErr)?;
+        if blob.is_dir {
+            state.dirs += 1;
+        } else {
+            state.files += 1;
+        }
+        state.entries += 1;
+        Ok(blob)
+    }
+
+    /// read and return the next entry from the backend (any format version)
+    fn read_entry(&mut self, state: &mut BackupState) -> Result<BackupEntry> {
+        if state.is_streamed() {
+            self.read_next(state).map(BackupEntry::Record)
+        } else {
+            self.read_next_blob(state).map(BackupEntry::Blob)
+        }
+    }
+
+    /// check the backup-end record
+    fn read_end(chunk: &[u8], state: &mut BackupState) -> Result<()> {
+        let mut len = LEN_PREFIX_SIZE;
+        if &chunk[..LEN_PREFIX_SIZE] == FS_BACKUP_END_DELIM {
+            // an encrypted backup must always end with a seal
+            if state.cipher.is_some() {
+                return Err(FSBackupError::AuthenticationErr);
+            }
+        } else {
+            let cipher = state.cipher.as_ref().ok_or(FSBackupError::MissingKeyErr)?;
+            let seal = chunk
+                .get(LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + TAG_SIZE)
+                .ok_or(FSBackupError::BackendReadErr)?;
+            cipher.verify_seal(seal)?;
+            len += TAG_SIZE;
+        }
+        state.crc.update(&chunk[..len]);
+
+        // the last file has to be complete
+        if state.remaining != 0 {
+            return Err(FSBackupError::DataAssemblyErr);
+        }
+
+        if let Some(header) = &state.header {
+            let crc = chunk
+                .get(len..len + CRC_SIZE)
+                .ok_or(FSBackupError::BackendReadErr)?;
+            if state.crc.clone().finalize().to_be_bytes() != crc {
+                return Err(FSBackupError::ChecksumErr);
+            }
+            len += CRC_SIZE;
+            state.len += len;
+
+            let expected = BackupHeader {
+                format_version: header.format_version,
+                ..state.summary(header.firmware_version)
+            };
+            if header != &expected {
+                return Err(FSBackupError::HeaderErr);
+            }
+        } else {
+            state.len += len;
+        }
+        Ok(())
     }
 
     /// execute backup operation for `fs` into backend
@@ -181,131 +876,634 @@ pub trait BackupBackend {
         &mut self,
         fs: &Filesystem<S>,
     ) -> Result<(usize, usize)> {
-        let root_dir = PathBuf::from(path!("/"));
-
-        let mut path_stack: Vec<PathCursor, MAX_FS_DEPTH> = Vec::new();
-        path_stack
-            .push(PathCursor {
-                path: root_dir,
-                idx: 0,
-                attr: None,
-            })
-            .map_err(|_| FSBackupError::PathStackFullErr)?;
-
-        self.write_start()?;
-
-        let mut d_cnt: usize = 0;
-        let mut f_cnt: usize = 0;
-        while !path_stack.is_empty() {
-            let mut current = path_stack.pop().unwrap();
-            let next_path = Self::get_next_entry(fs, &current.path, current.idx)?;
-
-            // 'None' => no next item inside this directory, continue w/o adding 'current'
-            // back to 'path_stack' implicitly means this subtree is done
-            if next_path.is_none() {
-                continue;
-            }
+        self.backup_filtered(fs, &|_: &Path| true)
+    }
 
-            let entry = next_path.unwrap();
+    /// execute backup operation for all entries of `fs` matching `filter` into backend
+    fn backup_filtered<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        filter: &impl PathFilter,
+    ) -> Result<(usize, usize)> {
+        let cipher = self.key().map(BackupCipher::new);
+        let mut state = BackupState::new(cipher, None);
 
-            // move index "pointer" to next item
-            current.idx += 1;
+        // skip the first block, the header is written once all entries are done
+        let _: Bytes<MAX_DUMP_BLOB_LENGTH> = self.read(Self::RW_SIZE)?;
+        self.write_start(&mut state)?;
 
-            let mut buffer = UserAttribute::new();
-            buffer.resize_to_capacity();
-            let attr = fs
-                .attribute(entry.path(), USER_ATTRIBUTE_NUMBER, &mut buffer)?
-                .map(|v| v.data().len())
-                .map(|n| {
-                    buffer.truncate(n);
-                    buffer
-                });
+        walk(fs, path!("/"), &mut |entry| {
+            let path = entry.path();
+            let is_dir = entry.file_type().is_dir();
+            if filter.matches(path) {
+                let attr = user_attribute(fs, path)?;
+                let path_bytes =
+                    Bytes::<PATH_MAX>::try_from(path.as_str_ref_with_trailing_nul().as_bytes())
+                        .map_err(|_| FSBackupError::PathAssemblyErr)?;
+                if is_dir {
+                    let record = FSRecord::Dir {
+                        path: path_bytes,
+                        attr,
+                    };
+                    self.write_entry(&record, &mut state)?;
+                } else {
+                    let len = entry.metadata().len();
+                    let record = FSRecord::File {
+                        path: path_bytes,
+                        attr,
+                        len: len as u32,
+                    };
+                    self.write_entry(&record, &mut state)?;
+                    self.write_chunks(fs, path, len, &mut state)?;
+                }
+            }
+            Ok(is_dir && filter.descend(path))
+        })?;
 
-            if entry.file_type().is_dir() {
-                d_cnt += 1;
+        self.write_end(&mut state)?;
 
-                let path = PathBuf::from(entry.path());
+        self.reset();
+        self.write_header(&state.summary(self.firmware_version()))?;
 
-                path_stack
-                    .push(current)
-                    .map_err(|_| FSBackupError::PathStackFullErr)?;
-                path_stack
-                    .push(PathCursor {
-                        path,
-                        idx: 0,
-                        attr: attr.clone(),
-                    })
-                    .map_err(|_| FSBackupError::PathStackFullErr)?;
-                self.write_entry(&entry, None, attr)?;
-            } else {
-                f_cnt += 1;
-                path_stack
-                    .push(current)
-                    .map_err(|_| FSBackupError::PathStackFullErr)?;
+        Ok((state.dirs, state.files))
+    }
 
-                let file_contents = entry.file_type().is_file().then(|| {
-                    Message::try_from(
-                        fs.read::<MAX_MESSAGE_LENGTH>(entry.path())
-                            .unwrap()
-                            .as_slice(),
-                    )
-                    .expect("file contents: bytes creation failed")
-                });
-                self.write_entry(&entry, file_contents, attr)?;
+    /// stream the contents of the file at `path` as `FSRecord::Chunk`s into the backend
+    fn write_chunks<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        path: &Path,
+        len: usize,
+        state: &mut BackupState,
+    ) -> Result<()> {
+        let mut offset = 0;
+        while offset < len {
+            let mut chunk = Bytes::<CHUNK_SIZE>::new();
+            chunk.resize_to_capacity();
+            let n = fs.open_file_and_then(path, |file| {
+                file.seek(SeekFrom::Start(offset as u32))?;
+                file.read(&mut chunk)
+            })?;
+            // the file shrank while reading it
+            if n == 0 {
+                return Err(FSBackupError::LittleFs2Err);
             }
+            chunk.truncate(n);
+            offset += n;
+            self.write_entry(&FSRecord::Chunk(chunk), state)?;
         }
+        Ok(())
+    }
 
-        self.write_end()?;
-
-        Ok((d_cnt, f_cnt))
+    /// read the complete backup and check its integrity without touching any filesystem
+    ///
+    /// The cursor has to point at the start of the backup, returns the number
+    /// of directories and files inside the backup.
+    fn check(&mut self) -> Result<(usize, usize)> {
+        let mut state = self.read_start()?;
+        loop {
+            match self.read_entry(&mut state) {
+                Ok(_) => {}
+                Err(FSBackupError::EndOfBackupBlobs) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((state.dirs, state.files))
     }
 
     /// execute restore operation from backend into `fs`
+    ///
+    /// Fails (w/o touching `fs`) if any entry of the backup already exists.
     fn restore<S: littlefs2::driver::Storage>(
         &mut self,
         fs: &Filesystem<S>,
     ) -> Result<(usize, usize)> {
-        let next_entry = self.read_next();
-        if let Err(err) = next_entry {
-            if err != FSBackupError::StartOfBackupBlobs {
-                return Err(err);
+        self.restore_filtered(fs, &|_: &Path| true)
+    }
+
+    /// execute restore operation for all entries matching `filter` from backend into `fs`
+    ///
+    /// Fails (w/o touching `fs`) if any of these entries already exists.
+    fn restore_filtered<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        filter: &impl PathFilter,
+    ) -> Result<(usize, usize)> {
+        let report = self.restore_with(fs, filter, RestorePolicy::default())?;
+        Ok((report.dirs, report.files))
+    }
+
+    /// execute restore operation for all entries matching `filter` from backend
+    /// into `fs`, existing entries are handled according to `policy`
+    ///
+    /// Missing parent directories of restored entries are created (w/o user attributes).
+    fn restore_with<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        filter: &impl PathFilter,
+        policy: RestorePolicy,
+    ) -> Result<RestoreReport> {
+        // never touch `fs` for a corrupted or truncated backup, or one with conflicts
+        if policy.may_fail() {
+            self.check_conflicts(fs, filter, policy)?;
+        } else {
+            self.check()?;
+        }
+        self.reset();
+
+        let mut state = self.read_start()?;
+        self.restore_entries(fs, filter, policy, &mut state)
+    }
+
+    /// restore all entries following the cursor into `fs`
+    ///
+    /// Entries before `state.checkpoint` are skipped. For a journaled restore
+    /// (`state.journal` is set) the progress is recorded in the journal.
+    fn restore_entries<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        filter: &impl PathFilter,
+        policy: RestorePolicy,
+        state: &mut BackupState,
+    ) -> Result<RestoreReport> {
+        let mut report = RestoreReport::default();
+        // chunks belong to the last file, thus are only written if the file is
+        let mut writing = false;
+        loop {
+            let index = state.entries;
+            let entry = match self.read_entry(state) {
+                Ok(entry) => entry,
+                Err(FSBackupError::EndOfBackupBlobs) => break,
+                Err(e) => return Err(e),
+            };
+            // already restored before an interruption
+            if index < state.checkpoint {
+                continue;
+            }
+            // a file is only complete with all its chunks, thus never record
+            // the progress in between
+            let is_chunk = matches!(entry, BackupEntry::Record(FSRecord::Chunk(_)));
+            if state.journal.is_some() && !is_chunk && index >= state.checkpoint + PROGRESS_INTERVAL
+            {
+                let entries = index as u32;
+                self.record_progress(state, &Progress::Restoring { entries })?;
+                state.checkpoint = index;
+            }
+
+            match entry {
+                BackupEntry::Record(FSRecord::Dir { path, attr }) => {
+                    writing = false;
+                    let path = to_path(&path)?;
+                    if filter.matches(path) {
+                        Self::restore_entry(fs, policy, path, None, attr.as_ref(), &mut report)?;
+                    }
+                }
+                BackupEntry::Record(FSRecord::File { path, attr, .. }) => {
+                    let path = to_path(&path)?;
+                    writing = filter.matches(path)
+                        && Self::restore_entry(
+                            fs,
+                            policy,
+                            path,
+                            Some(&[]),
+                            attr.as_ref(),
+                            &mut report,
+                        )?;
+                }
+                BackupEntry::Record(FSRecord::Chunk(data)) => {
+                    if writing {
+                        let path = to_path(state.file.as_slice())?;
+                        fs.open_file_with_options_and_then(
+                            |o| o.write(true).append(true),
+                            path,
+                            |file| file.write_all(&data),
+                        )?;
+                    }
+                }
+                BackupEntry::Blob(blob) => {
+                    let path = to_path(&blob.path)?;
+                    if !filter.matches(path) {
+                        continue;
+                    }
+                    let content = match (blob.is_dir, blob.content.as_deref()) {
+                        (true, _) => None,
+                        (false, Some(content)) => Some(content),
+                        (false, None) => return Err(FSBackupError::RestoreErr),
+                    };
+                    let attr = blob.attr.as_ref();
+                    Self::restore_entry(fs, policy, path, content, attr, &mut report)?;
+                }
             }
         }
 
-        let mut d_cnt: usize = 0;
-        let mut f_cnt: usize = 0;
+        if state.journal.is_some() {
+            self.record_progress(state, &Progress::Restored)?;
+        }
+        Ok(report)
+    }
+
+    /// like `check`, additionally fails with `RestoreConflictErr` if restoring
+    /// the entries matching `filter` into `fs` conflicts with `policy`
+    fn check_conflicts<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+        filter: &impl PathFilter,
+        policy: RestorePolicy,
+    ) -> Result<()> {
+        let mut state = self.read_start()?;
         loop {
-            let next_entry = self.read_next();
-            match next_entry {
-                Ok(v) => {
-                    let path = Path::from_bytes_with_nul(v.path.as_slice())
-                        .map_err(|_| FSBackupError::PathAssemblyErr)?;
-                    if v.is_dir {
-                        d_cnt += 1;
-                        fs.create_dir(path)?;
+            let (path, is_dir) = match self.read_entry(&mut state) {
+                Ok(BackupEntry::Record(FSRecord::Dir { path, .. })) => (path, true),
+                Ok(BackupEntry::Record(FSRecord::File { path, .. })) => (path, false),
+                Ok(BackupEntry::Record(FSRecord::Chunk(_))) => continue,
+                Ok(BackupEntry::Blob(blob)) => (blob.path, blob.is_dir),
+                Err(FSBackupError::EndOfBackupBlobs) => break,
+                Err(e) => return Err(e),
+            };
+            let path = to_path(&path)?;
+            if filter.matches(path) {
+                policy.resolve(Self::existing(fs, path)?, is_dir)?;
+            }
+        }
+        Ok(())
+    }
 
-                        if let Some(user_attr) = v.attr {
-                            fs.set_attribute(path, USER_ATTRIBUTE_NUMBER, &user_attr)?
-                        };
-                    } else {
-                        f_cnt += 1;
-                        let content = v.content.unwrap();
-                        fs.write(path, content.as_slice())?;
+    /// determine the progress of an (interrupted) backup & restore run
+    ///
+    /// The complete backup is checked and the progress journal behind it is
+    /// read, afterwards the cursor points at the position of the next
+    /// progress record.
+    fn progress(&mut self) -> Result<Progress> {
+        self.journal().map(|(progress, _)| progress)
+    }
+
+    /// like `progress`, additionally returns the number of `resume` attempts
+    /// recorded in the journal
+    ///
+    /// Every attempt records the current progress before restoring anything,
+    /// thus a record which does not advance the progress marks an attempt.
+    fn journal(&mut self) -> Result<(Progress, usize)> {
+        self.reset();
+        let chunk: Bytes<MAX_DUMP_BLOB_LENGTH> = self.read(Self::RW_SIZE)?;
+        // the header is written last, thus its absence marks an interrupted backup
+        if Self::parse_header(&chunk).is_err() {
+            return Ok((Progress::Incomplete, 0));
+        }
+        self.reset();
+        if self.check().is_err() {
+            return Ok((Progress::Incomplete, 0));
+        }
+
+        let mut progress = Progress::Complete;
+        let mut attempts = 0;
+        loop {
+            let position = self.position();
+            match self.read_progress() {
+                Ok(recorded) => {
+                    if let (
+                        Progress::Restoring { entries: previous },
+                        Progress::Restoring { entries },
+                    ) = (progress, recorded)
+                    {
+                        if entries <= previous {
+                            attempts += 1;
+                        }
+                    }
+                    progress = recorded;
+                }
+                // e.g., erased flash or the end of the backup space
+                Err(_) => {
+                    self.seek(position);
+                    return Ok((progress, attempts));
+                }
+            }
+        }
+    }
+
+    /// read a single progress record of the journal
+    fn read_progress(&mut self) -> Result<Progress> {
+        let chunk: Bytes<MAX_DUMP_BLOB_LENGTH> = self.read(Self::RW_SIZE)?;
+        let data = parse_block(FS_BACKUP_PROGRESS_MAGIC, &chunk)?;
+        postcard::from_bytes(data).map_err(|_| FSBackupError::DeserializeErr)
+    }
+
+    /// append a progress record to the journal, the cursor has to point at its end
+    fn write_progress(&mut self, progress: &Progress) -> Result<usize> {
+        let raw_progress: Vec<u8, 16> =
+            postcard_serialize_bytes(progress).map_err(|_| FSBackupError::SerializeErr)?;
+        let buf = assemble_block(FS_BACKUP_PROGRESS_MAGIC, &raw_progress)?;
+        self.write(buf.as_slice())
+    }
+
+    /// append a progress record to the journal of a journaled restore and
+    /// return to the current position afterwards
+    fn record_progress(&mut self, state: &mut BackupState, progress: &Progress) -> Result<()> {
+        let journal = state.journal.ok_or(FSBackupError::RestoreErr)?;
+        let position = self.position();
+        self.seek(journal);
+        self.write_progress(progress)?;
+        state.journal = Some(self.position());
+        self.seek(position);
+        Ok(())
+    }
+
+    /// record the start of a journaled restore, see `resume`
+    ///
+    /// Has to be called before the target filesystem is formatted, as from
+    /// then on the backup might be the only copy of the data.
+    fn begin_restore(&mut self) -> Result<usize> {
+        match self.progress()? {
+            Progress::Incomplete | Progress::Failed => Err(FSBackupError::RestoreErr),
+            _ => self.write_progress(&Progress::Restoring { entries: 0 }),
+        }
+    }
+
+    /// execute (or continue) the journaled restore started by `begin_restore` into `fs`
+    ///
+    /// After an interruption (e.g., a power loss) `resume` continues with the
+    /// first entry not recorded as restored, existing (i.e., partially
+    /// restored) entries are overwritten. `fs` has to be formatted if it
+    /// cannot be mounted.
+    ///
+    /// As `fs` is used once `resume` returns, a failed restore is recorded as
+    /// `Progress::Failed` and never resumed again, the same goes for a restore
+    /// interrupted `MAX_RESUME_ATTEMPTS` times (e.g., by a crash). If the
+    /// attempt cannot even be recorded, nothing is restored.
+    fn resume<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+    ) -> Result<RestoreReport> {
+        let (entries, attempts) = match self.journal()? {
+            (Progress::Restoring { entries }, attempts) => (entries, attempts),
+            (Progress::Restored, _) => return Ok(RestoreReport::default()),
+            _ => return Err(FSBackupError::RestoreErr),
+        };
+        if attempts >= MAX_RESUME_ATTEMPTS {
+            self.write_progress(&Progress::Failed)?;
+            return Err(FSBackupError::RestoreErr);
+        }
+        self.write_progress(&Progress::Restoring { entries })?;
+        let mut journal = self.position();
+        self.reset();
+
+        let result = self.read_start().and_then(|mut state| {
+            state.journal = Some(journal);
+            state.checkpoint = entries as usize;
+            let all = |_: &Path| true;
+            let result = self.restore_entries(fs, &all, RestorePolicy::Overwrite, &mut state);
+            journal = state.journal.unwrap_or(journal);
+            result
+        });
+        if result.is_err() {
+            self.seek(journal);
+            self.write_progress(&Progress::Failed)?;
+        }
+        result
+    }
+
+    /// `Some(is_dir)` if there is an entry at `path` inside `fs`
+    fn existing<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        path: &Path,
+    ) -> Result<Option<bool>> {
+        match fs.metadata(path) {
+            Ok(metadata) => Ok(Some(metadata.is_dir())),
+            Err(littlefs2::io::Error::NO_SUCH_ENTRY) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// create the parent directories of `path` if they are missing
+    fn restore_parents<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        path: &Path,
+    ) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            if !fs.exists(&parent) {
+                fs.create_dir_all(&parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// restore a single directory (`content` is `None`) or file into `fs`
+    ///
+    /// Returns whether the entry was written, i.e., its chunks have to be appended.
+    fn restore_entry<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        policy: RestorePolicy,
+        path: &Path,
+        content: Option<&[u8]>,
+        attr: Option<&UserAttribute>,
+        report: &mut RestoreReport,
+    ) -> Result<bool> {
+        let action = policy.resolve(Self::existing(fs, path)?, content.is_none())?;
+        match action {
+            RestoreAction::Skip => {
+                report.skipped += 1;
+                return Ok(false);
+            }
+            RestoreAction::Merge => report.merged += 1,
+            RestoreAction::Overwrite | RestoreAction::Replace => report.overwritten += 1,
+            RestoreAction::Create if content.is_none() => report.dirs += 1,
+            RestoreAction::Create => report.files += 1,
+        }
+
+        match (action, content) {
+            (RestoreAction::Replace, None) => fs.remove(path)?,
+            (RestoreAction::Replace, Some(_)) => fs.remove_dir_all(path)?,
+            _ => {}
+        }
+        match content {
+            None if matches!(action, RestoreAction::Create | RestoreAction::Replace) => {
+                Self::restore_parents(fs, path)?;
+                fs.create_dir(path)?;
+            }
+            None => {}
+            Some(content) => {
+                Self::restore_parents(fs, path)?;
+                fs.write(path, content)?;
+            }
+        }
+
+        match attr {
+            Some(_) => Self::restore_attr(fs, path, attr)?,
+            // existing entries get the user attribute of the backup, even if it has none
+            None if matches!(action, RestoreAction::Overwrite | RestoreAction::Merge) => {
+                fs.remove_attribute(path, USER_ATTRIBUTE_NUMBER)?
+            }
+            None => {}
+        }
+        Ok(true)
+    }
+
+    fn restore_attr<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        path: &Path,
+        attr: Option<&UserAttribute>,
+    ) -> Result<()> {
+        if let Some(user_attr) = attr {
+            fs.set_attribute(path, USER_ATTRIBUTE_NUMBER, user_attr)?;
+        }
+        Ok(())
+    }
+
+    /// compare the backup against `fs` without writing anything
+    ///
+    /// The cursor has to point at the start of the backup. The backup is
+    /// checked for integrity first (see `check`), afterwards every entry is
+    /// compared against `fs` (paths, contents and user attributes).
+    fn verify<S: littlefs2::driver::Storage>(
+        &mut self,
+        fs: &Filesystem<S>,
+    ) -> Result<VerifyReport> {
+        self.check()?;
+        self.reset();
+
+        let mut report = VerifyReport::default();
+        let mut state = self.read_start()?;
+        // `Some(equal)` => current file exists in `fs`, thus its contents are compared
+        let mut current: Option<bool> = None;
+        let mut offset = 0;
+        loop {
+            let record = match self.read_entry(&mut state) {
+                Ok(BackupEntry::Record(record)) => record,
+                Ok(BackupEntry::Blob(blob)) => {
+                    Self::verify_blob(fs, &blob, &mut report)?;
+                    continue;
+                }
+                Err(FSBackupError::EndOfBackupBlobs) => break,
+                Err(e) => return Err(e),
+            };
 
-                        if let Some(user_attr) = v.attr {
-                            fs.set_attribute(path, USER_ATTRIBUTE_NUMBER, &user_attr)?;
-                        };
+            match record {
+                FSRecord::Dir { path, attr } => {
+                    let path = to_path(path.as_slice())?;
+                    match fs.metadata(path) {
+                        Ok(metadata) if metadata.is_dir() => {
+                            Self::verify_attr(fs, path, attr.as_ref(), &mut report)?
+                        }
+                        _ => report.missing += 1,
                     }
                 }
-                Err(e) => {
-                    if e == FSBackupError::EndOfBackupBlobs {
-                        break;
-                    } else {
-                        return Err(e);
+                FSRecord::File { path, attr, len } => {
+                    let path = to_path(path.as_slice())?;
+                    current = match fs.metadata(path) {
+                        Ok(metadata) if metadata.is_file() => {
+                            Self::verify_attr(fs, path, attr.as_ref(), &mut report)?;
+                            if metadata.len() == len as usize {
+                                Some(true)
+                            } else {
+                                report.content_mismatch += 1;
+                                None
+                            }
+                        }
+                        _ => {
+                            report.missing += 1;
+                            None
+                        }
+                    };
+                    offset = 0;
+                }
+                FSRecord::Chunk(data) => {
+                    if current == Some(true) {
+                        let path = to_path(state.file.as_slice())?;
+                        if !Self::verify_contents(fs, path, offset, &data)? {
+                            report.content_mismatch += 1;
+                            current = Some(false);
+                        }
                     }
+                    offset += data.len();
+                }
+            }
+        }
+
+        report.dirs = state.dirs;
+        report.files = state.files;
+        let present = report.dirs + report.files - report.missing;
+        report.extra = Self::count_entries(fs)?.saturating_sub(present);
+        Ok(report)
+    }
+
+    /// compare a single (legacy) `FSEntryBlob` against `fs`
+    fn verify_blob<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        blob: &FSEntryBlob,
+        report: &mut VerifyReport,
+    ) -> Result<()> {
+        let path = to_path(blob.path.as_slice())?;
+        let metadata = match fs.metadata(path) {
+            Ok(metadata) if metadata.is_dir() == blob.is_dir => metadata,
+            _ => {
+                report.missing += 1;
+                return Ok(());
+            }
+        };
+        Self::verify_attr(fs, path, blob.attr.as_ref(), report)?;
+
+        if let Some(content) = &blob.content {
+            let mut equal = metadata.len() == content.len();
+            for (idx, data) in content.chunks(CHUNK_SIZE).enumerate() {
+                if !equal {
+                    break;
                 }
+                equal = Self::verify_contents(fs, path, idx * CHUNK_SIZE, data)?;
+            }
+            if !equal {
+                report.content_mismatch += 1;
             }
         }
-        Ok((d_cnt, f_cnt))
+        Ok(())
+    }
+
+    /// compare the user attribute of `path` against `attr`
+    fn verify_attr<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        path: &Path,
+        attr: Option<&UserAttribute>,
+        report: &mut VerifyReport,
+    ) -> Result<()> {
+        let mut buffer = UserAttribute::new();
+        buffer.resize_to_capacity();
+        let fs_attr = fs
+            .attribute(path, USER_ATTRIBUTE_NUMBER, &mut buffer)?
+            .map(|v| v.data().len());
+        let equal = match (fs_attr, attr) {
+            (Some(n), Some(attr)) => &buffer[..n] == attr.as_slice(),
+            (None, None) => true,
+            _ => false,
+        };
+        if !equal {
+            report.attr_mismatch += 1;
+        }
+        Ok(())
+    }
+
+    /// compare the contents of the file at `path` starting at `offset` against `data`
+    fn verify_contents<S: littlefs2::driver::Storage>(
+        fs: &Filesystem<S>,
+        path: &Path,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<bool> {
+        let mut buffer = [0u8; CHUNK_SIZE];
+        let buffer = buffer
+            .get_mut(..data.len())
+            .ok_or(FSBackupError::DataAssemblyErr)?;
+        let n = fs.open_file_and_then(path, |file| {
+            file.seek(SeekFrom::Start(offset as u32))?;
+            file.read(buffer)
+        })?;
+        Ok(&buffer[..n] == data)
+    }
+
+    /// count all entries (directories & files) inside `fs`
+    fn count_entries<S: littlefs2::driver::Storage>(fs: &Filesystem<S>) -> Result<usize> {
+        let mut count = 0;
+        walk(fs, path!("/"), &mut |_| {
+            count += 1;
+            Ok(true)
+        })?;
+        Ok(count)
     }
 }
@@ -13,14 +13,84 @@
 //! # Backup Data Layout
 //! A full backup binary blob consists of:
 //! ```text
-//! FS_BACKUP_START_DELIM | Entry1 | Entry2 | … | FS_BACKUP_END_DELIM
+//! Header | FS_BACKUP_START_DELIM | Entry1 | Entry2 | … | FS_BACKUP_END_DELIM | CRC
 //! ```
+//! The header occupies the first `RW_SIZE` block and is written last, as it
+//! contains the number of entries and the total length of the backup:
+//!
+//! | bytes     | content                                |
+//! |-----------|----------------------------------------|
+//! |  0 - 3    | `FS_BACKUP_HEADER_MAGIC`               |
+//! |  4 - 7    | Big-Endian length of the header        |
+//! |  8 - n    | postcard-serialized `BackupHeader`     |
+//! |  n - n+4  | Big-Endian CRC-32 over bytes 0 - n     |
+//!
 //! With `EntryX` being:
 //!
-//! | bytes   | content                           |
-//! |---------|-----------------------------------|
-//! |  0 - 3  | Big-Endian length of the blob     |
-//! |  4 - n  | postcard-serialized `FSEntryBlob` |
+//! | bytes     | content                                |
+//! |-----------|----------------------------------------|
+//! |  0 - 3    | Big-Endian length of the blob          |
+//! |  4 - n    | postcard-serialized `FSRecord`         |
+//! |  n - n+4  | Big-Endian CRC-32 over bytes 0 - n     |
+//!
+//! Files are stored as `FSRecord::File` followed by `FSRecord::Chunk`s of at
+//! most `CHUNK_SIZE` bytes. Thus, a file is never read into memory at once and
+//! any file littlefs can hold can be backed up. Backups with format version 1
+//! and legacy backups use `FSEntryBlob`s containing the complete file instead.
+//!
+//! The trailing CRC-32 covers all records from the start delimiter up to
+//! (and including) the end delimiter. `restore` checks the complete backup
+//! before it touches the target `Filesystem`. Legacy backups without header
+//! (starting directly with `FS_BACKUP_START_DELIM`) carry no checksums, but
+//! are still accepted by `restore`.
+//!
+//! # Compression
+//! If `BackupBackend::compression` returns `true`, the serialized `FSRecord`
+//! of each entry is compressed (LZSS) if this makes it smaller. Such entries
+//! are marked by `COMPRESSED_FLAG` within their length prefix, which is covered
+//! by the CRC and, for encrypted backups, authenticated. Compression is applied
+//! before encryption, backups with compressed entries have format version 3.
+//!
+//! # Filters
+//! `backup_filtered` & `restore_filtered` only process the entries matching a
+//! `PathFilter`, e.g., `PathPatterns` (include & exclude patterns) or any
+//! `Fn(&Path) -> bool` predicate.
+//!
+//! # Restore Policies
+//! `restore` & `restore_filtered` fail if an entry of the backup already
+//! exists in the target `Filesystem`. `restore_with` takes a `RestorePolicy`
+//! to skip or overwrite existing entries or to merge existing directories
+//! instead, e.g., to restore into a partially recovered filesystem. Conflicts
+//! are detected before anything is written, the returned `RestoreReport`
+//! contains the number of entries handled in each way.
+//!
+//! # Progress Journal
+//! A restore into a formatted filesystem leaves the backup as the only copy of
+//! the data. `begin_restore` & `resume` therefore record the progress of a
+//! restore in a journal of single-block records (`FS_BACKUP_PROGRESS_MAGIC`,
+//! same layout as the header) directly behind the backup. `progress` reads it
+//! back after a power loss: an interrupted backup (no valid header) has to be
+//! restarted, an interrupted restore is continued by `resume` with the first
+//! entry not recorded as restored. The progress is recorded at least every
+//! `PROGRESS_INTERVAL` entries, thus the backend needs some room behind the
+//! backup and has to support `seek`. As the target is used afterwards, a
+//! failed restore or one started `MAX_RESUME_ATTEMPTS` times is recorded as
+//! `Progress::Failed` and never resumed again.
+//!
+//! # Verification
+//! `check` reads the complete backup and checks its integrity without writing
+//! anything. `verify` additionally compares every entry against a live
+//! `Filesystem` and returns a `VerifyReport` listing the differences, e.g., to
+//! make sure a backup is complete before the source filesystem is formatted.
+//!
+//! # Encryption
+//! If `BackupBackend::key` returns a `BackupKey`, every entry is encrypted and
+//! authenticated using ChaCha20-Poly1305. The backup then starts with
+//! `FS_BACKUP_START_ENC_DELIM` followed by the nonce salt and ends with
+//! `FS_BACKUP_END_ENC_DELIM` followed by a seal over all entries. Each entry
+//! carries its 16 byte tag after the ciphertext (in front of the CRC, the length
+//! prefix includes both). `restore` refuses backups which fail authentication, are truncated or
+//! are not encrypted although a key is set.
 //!
 //! # Important Implementation Details
 //! * The `BackupBackend` implementation has to maintain an internal *cursor*
@@ -32,9 +102,18 @@
 //!   *every* `read` & `write` invocation as by definition the low-level
 //!   interfaces for most flash memories will not allow multiple writes
 //!   within the same block (i.e., within `RW_SIZE` bytes)
+//! * The filesystem is traversed keeping the first few directory levels open,
+//!   so that each of them is read once, and iteratively below, so that the
+//!   stack usage is constant and the depth of the tree is only bounded by
+//!   `PATH_MAX`
 
+mod compression;
+mod encryption;
+mod filter;
 mod lfs_backup;
 
+pub use crate::encryption::{BackupCipher, BackupKey, SALT_SIZE, TAG_SIZE};
+pub use crate::filter::{PathFilter, PathPatterns};
 pub use crate::lfs_backup::*;
 
 #[cfg(test)]
@@ -1,13 +1,21 @@
 use littlefs2::fs::Filesystem;
+use littlefs2::path;
 use littlefs2::path::{Path, PathBuf};
 
 use heapless::Vec;
 use heapless_bytes::Bytes;
 
-use crate::lfs_backup::{BackupBackend, FSBackupError, PathCursor, Result, MAX_FS_DEPTH};
+use crate::compression::{compress, decompress};
+use crate::encryption::BackupKey;
+use crate::filter::{PathFilter, PathPatterns};
+use crate::lfs_backup::{
+    postcard_serialize_bytes, walk, BackupBackend, FSBackupError, FSEntryBlob, FSRecord, Progress,
+    RestorePolicy, RestoreReport, Result, VerifyReport, CHUNK_SIZE, FORMAT_VERSION,
+    MAX_DUMP_BLOB_LENGTH, MAX_RESUME_ATTEMPTS,
+};
 
 use trussed_core::config::USER_ATTRIBUTE_NUMBER;
-use trussed_core::types::UserAttribute;
+use trussed_core::types::{Message, UserAttribute};
 
 use std::{
     fs::{remove_file, File},
@@ -15,12 +23,10 @@ use std::{
     path::Path as StdPath,
     path::PathBuf as StdPathBuf,
     string::{String, ToString},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-pub use generic_array::{
-    typenum::{consts, U128, U16, U2, U256, U4096, U512},
-    GenericArray,
-};
+pub use generic_array::typenum::{U2, U256};
 
 pub struct FileFlash {
     path: std::path::PathBuf,
@@ -30,16 +36,37 @@ pub struct FileFlash {
 struct FileBackend {
     offset: usize,
     path: StdPathBuf,
+    key: Option<BackupKey>,
+    compress: bool,
 }
 
 type LfsResult<T> = Result<T, littlefs2::io::Error>;
 
 pub const FS_SIZE: usize = 1920 * 1024; // 2MB - 128kb
 
+const MAX_TEST_FILE_SIZE: usize = 32 * 1024;
+
 const ORIGIN_FS_PATH: &str = "/tmp/test.fs";
 const TARGET_FS_PATH: &str = "/tmp/target.fs";
 const BACKUP_DATA_PATH: &str = "/tmp/backend.test.bin";
 
+/// remaining writes (to the backend & all test filesystems) until the power is cut
+static WRITES_UNTIL_POWER_CUT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// number of reads from all test filesystems
+static STORAGE_READS: AtomicUsize = AtomicUsize::new(0);
+
+/// `false` once the power is cut, the write must not happen then
+fn power_on() -> bool {
+    WRITES_UNTIL_POWER_CUT
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok()
+}
+
+fn cut_power_after(writes: usize) {
+    WRITES_UNTIL_POWER_CUT.store(writes, Ordering::SeqCst);
+}
+
 impl FileFlash {
     pub fn new(state_path: impl AsRef<std::path::Path>) -> Self {
         let path: std::path::PathBuf = state_path.as_ref().into();
@@ -70,7 +97,92 @@ impl FileBackend {
             println!("Created new backend file: {:?}", &data_path);
         }
 
-        Self { offset: 0, path }
+        Self {
+            offset: 0,
+            path,
+            key: None,
+            compress: false,
+        }
+    }
+
+    pub fn with_key(data_path: &StdPath, key: BackupKey) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::new(data_path)
+        }
+    }
+
+    pub fn with_compression(self) -> Self {
+        Self {
+            compress: true,
+            ..self
+        }
+    }
+
+    /// flip a single bit inside the backup data at `offset`
+    fn corrupt(&self, offset: usize) {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .unwrap();
+        let mut byte = [0u8; 1];
+        file.seek(SeekFrom::Start(offset as _)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        byte[0] ^= 0x01;
+        file.seek(SeekFrom::Start(offset as _)).unwrap();
+        file.write_all(&byte).unwrap();
+    }
+
+    /// rewrite the backup into the legacy format (w/o header & checksums)
+    fn convert_to_legacy(&mut self) {
+        let mut state = self.read_start().unwrap();
+        let mut blobs: std::vec::Vec<FSEntryBlob> = std::vec::Vec::new();
+        loop {
+            match self.read_next(&mut state) {
+                Ok(FSRecord::Dir { path, attr }) => blobs.push(FSEntryBlob {
+                    path,
+                    is_dir: true,
+                    content: None,
+                    attr,
+                }),
+                Ok(FSRecord::File { path, attr, .. }) => blobs.push(FSEntryBlob {
+                    path,
+                    is_dir: false,
+                    content: Some(Message::new()),
+                    attr,
+                }),
+                Ok(FSRecord::Chunk(data)) => {
+                    let blob = blobs.last_mut().unwrap();
+                    let content = blob.content.as_mut().unwrap();
+                    content.extend_from_slice(&data).unwrap();
+                }
+                Err(FSBackupError::EndOfBackupBlobs) => break,
+                Err(e) => panic!("{e:?}"),
+            }
+        }
+
+        self.erase().unwrap();
+        self.reset();
+        self.write(b"SB||").unwrap();
+        for blob in blobs {
+            let raw_blob: Vec<u8, MAX_DUMP_BLOB_LENGTH> = postcard_serialize_bytes(&blob).unwrap();
+            let mut buf = (raw_blob.len() as u32).to_be_bytes().to_vec();
+            buf.extend_from_slice(&raw_blob);
+            self.write(&buf).unwrap();
+        }
+        self.write(b"||EB").unwrap();
+        self.reset();
+    }
+
+    /// zero out the backup data starting at `offset`
+    fn truncate(&self, offset: usize) {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .unwrap();
+        file.seek(SeekFrom::Start(offset as _)).unwrap();
+        file.write_all(&vec![0u8; FS_SIZE - offset]).unwrap();
     }
 }
 
@@ -78,6 +190,9 @@ impl BackupBackend for FileBackend {
     const RW_SIZE: usize = 256;
 
     fn write(&mut self, content: &[u8]) -> Result<usize> {
+        if !power_on() {
+            return Err(FSBackupError::BackendWriteErr);
+        }
         let mut file = std::fs::OpenOptions::new()
             .write(true)
             .open(&self.path)
@@ -128,6 +243,9 @@ impl BackupBackend for FileBackend {
     }
 
     fn erase(&mut self) -> Result<usize> {
+        if !power_on() {
+            return Err(FSBackupError::BackendEraseErr);
+        }
         let mut file = std::fs::OpenOptions::new()
             .write(true)
             .open(&self.path)
@@ -140,6 +258,22 @@ impl BackupBackend for FileBackend {
     fn reset(&mut self) {
         self.offset = 0;
     }
+
+    fn position(&self) -> usize {
+        self.offset
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.offset = position;
+    }
+
+    fn key(&self) -> Option<&BackupKey> {
+        self.key.as_ref()
+    }
+
+    fn compression(&self) -> bool {
+        self.compress
+    }
 }
 
 fn fill_test_file(fs: &Filesystem<FileFlash>, p: &str, data: &str) -> usize {
@@ -159,7 +293,7 @@ fn fill_random_test_file(fs: &Filesystem<FileFlash>, p: &str) -> usize {
         .map(char::from)
         .collect();
 
-    fs.write(path, &data.as_bytes()).expect("write fail");
+    fs.write(path, data.as_bytes()).expect("write fail");
 
     path.to_string().len() + data.len()
 }
@@ -176,7 +310,7 @@ fn fill_test_data(
     let mut f_cnt: usize = 0;
     let mut data_size = 0;
 
-    let path = PathBuf::from("/was/geht/denn/bluba");
+    let path = PathBuf::try_from("/was/geht/denn/bluba").unwrap();
     let data = "blablblalbalab".as_bytes();
     fs.create_dir_all(&path.parent().unwrap())
         .expect("dir create 1 fail");
@@ -185,16 +319,16 @@ fn fill_test_data(
     d_cnt += 3;
     data_size += data.len() + path.to_string().len();
 
-    let path = PathBuf::from("/was/geht/denn/hier");
+    let path = PathBuf::try_from("/was/geht/denn/hier").unwrap();
     let data = "".as_bytes();
     fs.create_dir_all(&path.parent().unwrap())
         .expect("dir create 2 fail");
     fs.write(&path, data).expect("write 2 fail");
     f_cnt += 1;
     d_cnt += 0;
-    data_size += data.len() + &path.to_string().len();
+    data_size += data.len() + path.to_string().len();
 
-    let path = PathBuf::from("/was/dort");
+    let path = PathBuf::try_from("/was/dort").unwrap();
     let data = "".as_bytes();
     fs.create_dir_all(&path.parent().unwrap())
         .expect("dir create 3 fail");
@@ -203,7 +337,7 @@ fn fill_test_data(
     d_cnt += 0;
     data_size += data.len() + path.to_string().len();
 
-    let path = PathBuf::from("/testdir/");
+    let path = PathBuf::try_from("/testdir/").unwrap();
     fs.create_dir_all(&path).expect("dir create fail");
 
     let data =
@@ -232,13 +366,14 @@ impl littlefs2::driver::Storage for FileFlash {
     const WRITE_SIZE: usize = 4;
     const BLOCK_SIZE: usize = 256;
 
-    const BLOCK_COUNT: usize = (FS_SIZE / Self::BLOCK_SIZE as usize);
+    const BLOCK_COUNT: usize = FS_SIZE / Self::BLOCK_SIZE;
     const BLOCK_CYCLES: isize = -1;
 
     type CACHE_SIZE = U256;
-    type LOOKAHEADWORDS_SIZE = U2;
+    type LOOKAHEAD_SIZE = U2;
 
     fn read(&mut self, offset: usize, buffer: &mut [u8]) -> LfsResult<usize> {
+        STORAGE_READS.fetch_add(1, Ordering::SeqCst);
         let mut file = File::open(&self.path).unwrap();
         file.seek(SeekFrom::Start(offset as _)).unwrap();
         let bytes_read = file.read(buffer).unwrap();
@@ -247,6 +382,9 @@ impl littlefs2::driver::Storage for FileFlash {
     }
 
     fn write(&mut self, offset: usize, data: &[u8]) -> LfsResult<usize> {
+        if !power_on() {
+            return Err(littlefs2::io::Error::IO);
+        }
         let mut file = std::fs::OpenOptions::new()
             .write(true)
             .open(&self.path)
@@ -259,6 +397,9 @@ impl littlefs2::driver::Storage for FileFlash {
     }
 
     fn erase(&mut self, offset: usize, len: usize) -> LfsResult<usize> {
+        if !power_on() {
+            return Err(littlefs2::io::Error::IO);
+        }
         let mut file = std::fs::OpenOptions::new()
             .write(true)
             .open(&self.path)
@@ -278,51 +419,26 @@ pub fn equal_filesystems(
     fs1: &Filesystem<FileFlash>,
     fs2: &Filesystem<FileFlash>,
 ) -> (usize, usize) {
-    let root_dir = PathBuf::from("/");
-
-    let mut path_stack: Vec<PathCursor, MAX_FS_DEPTH> = Vec::new();
-    path_stack
-        .push(PathCursor {
-            path: root_dir,
-            idx: 0,
-            attr: None,
-        })
-        .map_err(|_| FSBackupError::PathStackFullErr)
-        .unwrap();
-
     let mut d_cnt: usize = 0;
     let mut f_cnt: usize = 0;
 
-    while !path_stack.is_empty() {
-        let mut current = path_stack.pop().unwrap();
-        let next_path = FileBackend::get_next_entry(fs1, &current.path, current.idx).unwrap();
-
-        // 'None' => no next item inside this directory, continue w/o adding 'current'
-        // back to 'path_stack' implicitly means this subtree is done
-        if next_path == None {
-            continue;
-        }
-
-        let entry = next_path.unwrap();
-
-        // move index "pointer" to next item
-        current.idx += 1;
-
+    walk(fs1, path!("/"), &mut |entry| {
         let info = fs2
             .metadata(entry.path())
             .expect("path (metadata) not found");
 
         assert_eq!(info, entry.metadata());
 
+        let mut buffer = [0u8; 256];
         let attr1 = fs1
-            .attribute(entry.path(), USER_ATTRIBUTE_NUMBER)
+            .attribute(entry.path(), USER_ATTRIBUTE_NUMBER, &mut buffer)
             .unwrap()
             .map(|v| UserAttribute::from_slice(v.data()))
             .transpose()
             .expect("user attr err");
 
         let attr2 = fs2
-            .attribute(entry.path(), USER_ATTRIBUTE_NUMBER)
+            .attribute(entry.path(), USER_ATTRIBUTE_NUMBER, &mut buffer)
             .unwrap()
             .map(|v| UserAttribute::from_slice(v.data()))
             .transpose()
@@ -332,38 +448,40 @@ pub fn equal_filesystems(
 
         if entry.metadata().is_file() {
             f_cnt += 1;
-            let content1 = fs1.read::<1024>(entry.path()).unwrap();
-            let content2 = fs2.read::<1024>(entry.path()).unwrap();
+            let content1 = fs1.read::<MAX_TEST_FILE_SIZE>(entry.path()).unwrap();
+            let content2 = fs2.read::<MAX_TEST_FILE_SIZE>(entry.path()).unwrap();
             assert_eq!(content1, content2);
-
-            path_stack.push(current).expect("path stack full");
         } else {
             d_cnt += 1;
-            path_stack.push(current).expect("path stack full");
-
-            let mut path = PathBuf::new();
-            path.push(entry.path());
-
-            path_stack
-                .push(PathCursor {
-                    path,
-                    idx: 0,
-                    attr: attr1,
-                })
-                .expect("path stack full");
         }
-    }
+        Ok(true)
+    })
+    .unwrap();
     (d_cnt, f_cnt)
 }
 
-fn fsbackup(num_files: u32, deterministic: bool) {
-    // cleanup old paths
+fn cleanup() {
     for del_path in [ORIGIN_FS_PATH, TARGET_FS_PATH, BACKUP_DATA_PATH].iter() {
         if StdPath::new(del_path).exists() {
             remove_file(del_path).expect("failed deleting file");
         }
     }
+}
 
+fn test_key(salt: u8) -> BackupKey {
+    BackupKey::derive(b"0123456789abcdef", [salt; 8])
+}
+
+fn fsbackup(num_files: u32, deterministic: bool) {
+    cleanup();
+    fsbackup_with(
+        num_files,
+        deterministic,
+        FileBackend::new(StdPath::new(BACKUP_DATA_PATH)),
+    );
+}
+
+fn fsbackup_with(num_files: u32, deterministic: bool, mut backend: FileBackend) {
     // prepare origin fs (backup target)
     let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
     let mut storage = FileFlash::new(ORIGIN_FS_PATH);
@@ -403,10 +521,6 @@ fn fsbackup(num_files: u32, deterministic: bool) {
     let target_fs =
         Filesystem::mount(&mut target_alloc, &mut target_storage).expect("failed target mount");
 
-    // prepare intermediate `FSBackup` storage blob
-    let backend_path = StdPath::new(BACKUP_DATA_PATH);
-    let mut backend = FileBackend::new(backend_path);
-
     // execute backup from origin fs -> `FSBackup` storage blob
     let res_backup = backend.backup(&fs);
 
@@ -475,3 +589,826 @@ fn fsbackup_nondeterministic_medium() {
 fn fsbackup_nondeterministic_big() {
     fsbackup(750, false);
 }
+
+/// backup freshly generated test data into `backend`
+fn backup_test_data(backend: &mut FileBackend, num_files: u32) {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill_test_data(&fs, num_files, true);
+
+    backend.backup(&fs).expect("backup failed");
+    backend.reset();
+}
+
+/// restore `backend` into a freshly formatted target filesystem
+fn restore_test_data(backend: &mut FileBackend) -> Result<(usize, usize)> {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(TARGET_FS_PATH);
+    Filesystem::format(&mut storage).expect("(target) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed target mount");
+
+    let res = backend.restore(&fs);
+    backend.reset();
+
+    // a failed restore must not touch the target filesystem
+    if res.is_err() {
+        let root = PathBuf::try_from("/").unwrap();
+        let entries = fs.read_dir_and_then(&root, |it| Ok(it.count())).unwrap();
+        assert_eq!(entries, 2);
+    }
+    res
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_small() {
+    cleanup();
+    let backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(1));
+    fsbackup_with(100, true, backend);
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_nondeterministic_medium() {
+    cleanup();
+    let backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(2));
+    fsbackup_with(300, false, backend);
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_tampered() {
+    cleanup();
+    let mut backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(3));
+    backup_test_data(&mut backend, 20);
+
+    // unmodified backup restores fine
+    assert!(restore_test_data(&mut backend).is_ok());
+
+    // flip a bit inside the ciphertext of the 3rd entry
+    backend.corrupt(3 * FileBackend::RW_SIZE + 10);
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::AuthenticationErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_tampered_length() {
+    cleanup();
+    let mut backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(4));
+    backup_test_data(&mut backend, 20);
+
+    // the length prefix is authenticated as well
+    backend.corrupt(2 * FileBackend::RW_SIZE + 3);
+    assert!(restore_test_data(&mut backend).is_err());
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_truncated() {
+    cleanup();
+    let mut backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(5));
+    backup_test_data(&mut backend, 20);
+
+    backend.truncate(5 * FileBackend::RW_SIZE);
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::AuthenticationErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_encrypted_wrong_key() {
+    cleanup();
+    let mut backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(6));
+    backup_test_data(&mut backend, 20);
+
+    let mut backend = FileBackend::with_key(
+        StdPath::new(BACKUP_DATA_PATH),
+        BackupKey::derive(b"fedcba9876543210", [6; 8]),
+    );
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::AuthenticationErr)
+    );
+
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::MissingKeyErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_plaintext_rejected_with_key() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    let mut backend = FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(7));
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::AuthenticationErr)
+    );
+}
+
+#[test]
+fn compression_roundtrip() {
+    let mut compressed = [0u8; MAX_DUMP_BLOB_LENGTH];
+    let mut decompressed = [0u8; MAX_DUMP_BLOB_LENGTH];
+
+    let mut data = std::vec::Vec::new();
+    data.extend_from_slice(b"/testdir/testfile0001\0");
+    data.extend(core::iter::repeat_n(b'x', 600));
+    data.extend((0..=255u8).cycle().take(1500));
+    let len = compress(&data, &mut compressed).unwrap();
+    assert!(len < data.len());
+    assert_eq!(
+        decompress(&compressed[..len], &mut decompressed),
+        Ok(data.len())
+    );
+    assert_eq!(&decompressed[..data.len()], &data[..]);
+
+    // incompressible data is left alone
+    let data: std::vec::Vec<u8> = (0..=255u8).collect();
+    assert_eq!(compress(&data, &mut compressed), None);
+    assert_eq!(compress(&[], &mut compressed), None);
+
+    // malformed data must never panic
+    for garbage in [&[0xff, 0xff, 0xff][..], &[0x01, 0x00, 0x10], &[0xff; 64]] {
+        assert_eq!(
+            decompress(garbage, &mut decompressed),
+            Err(FSBackupError::CompressionErr)
+        );
+    }
+    let mut rng = thread_rng();
+    for _ in 0..1000 {
+        let garbage: std::vec::Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+        let _ = decompress(&garbage, &mut decompressed[..256]);
+    }
+}
+
+#[test]
+#[serial]
+fn fsbackup_compressed_small() {
+    cleanup();
+    let backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH)).with_compression();
+    fsbackup_with(100, true, backend);
+}
+
+#[test]
+#[serial]
+fn fsbackup_compressed_encrypted_medium() {
+    cleanup();
+    let backend =
+        FileBackend::with_key(StdPath::new(BACKUP_DATA_PATH), test_key(3)).with_compression();
+    fsbackup_with(300, false, backend);
+}
+
+#[test]
+#[serial]
+fn fsbackup_compressed_size() {
+    let total_len = |backend: &mut FileBackend| {
+        backup_test_data(backend, 100);
+        let state = backend.read_start().unwrap();
+        let header = state.header().unwrap().clone();
+        backend.reset();
+        assert_eq!(backend.check(), Ok((4, 103)));
+        backend.reset();
+        header.total_len
+    };
+
+    cleanup();
+    let plain = total_len(&mut FileBackend::new(StdPath::new(BACKUP_DATA_PATH)));
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH)).with_compression();
+    let compressed = total_len(&mut backend);
+    assert!(compressed < plain, "{compressed} >= {plain}");
+    assert_eq!(restore_test_data(&mut backend), Ok((4, 103)));
+}
+
+#[test]
+#[serial]
+fn fsbackup_header() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    let state = backend.read_start().unwrap();
+    let header = state.header().unwrap().clone();
+    backend.reset();
+
+    assert_eq!(header.format_version, FORMAT_VERSION);
+    assert!(!header.encrypted);
+    assert_eq!((header.dirs, header.files), (4, 23));
+    // one chunk for each of the 19 non-empty files
+    assert_eq!(header.entries, header.dirs + header.files + 19);
+    assert_eq!(backend.check(), Ok((4, 23)));
+}
+
+#[test]
+#[serial]
+fn fsbackup_corrupted_entry() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    backend.corrupt(3 * FileBackend::RW_SIZE + 10);
+    assert_eq!(backend.check(), Err(FSBackupError::ChecksumErr));
+    backend.reset();
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::ChecksumErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_corrupted_header() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    backend.corrupt(10);
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::ChecksumErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_truncated() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    backend.truncate(5 * FileBackend::RW_SIZE);
+    assert_eq!(
+        restore_test_data(&mut backend),
+        Err(FSBackupError::ChecksumErr)
+    );
+}
+
+#[test]
+#[serial]
+fn fsbackup_legacy() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    backend.convert_to_legacy();
+    assert!(backend.read_start().unwrap().header().is_none());
+    backend.reset();
+    assert_eq!(restore_test_data(&mut backend), Ok((4, 23)));
+}
+
+#[test]
+#[serial]
+fn fsbackup_large_files() {
+    cleanup();
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill_test_data(&fs, 10, true);
+
+    // files way beyond `MAX_DUMP_BLOB_LENGTH` and a file made of exactly 3 chunks
+    let large = PathBuf::try_from("/large/first").unwrap();
+    let data: std::vec::Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+    fs.create_dir_all(&large.parent().unwrap()).unwrap();
+    fs.write(&large, &data).unwrap();
+    fs.set_attribute(&large, USER_ATTRIBUTE_NUMBER, b"large attr")
+        .unwrap();
+    let exact = PathBuf::try_from("/large/exact").unwrap();
+    fs.write(&exact, &[0x42; 3 * CHUNK_SIZE]).unwrap();
+
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    let res_backup = backend.backup(&fs).expect("backup failed");
+    backend.reset();
+
+    let mut target_alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut target_storage = FileFlash::new(TARGET_FS_PATH);
+    Filesystem::format(&mut target_storage).expect("(target) formatting failed");
+    let target_fs =
+        Filesystem::mount(&mut target_alloc, &mut target_storage).expect("failed target mount");
+    let res_restore = backend.restore(&target_fs).expect("restore failed");
+
+    assert_eq!(res_backup, res_restore);
+    assert_eq!(equal_filesystems(&fs, &target_fs), res_backup);
+    assert_eq!(equal_filesystems(&target_fs, &fs), res_backup);
+}
+
+#[test]
+#[serial]
+fn fsbackup_verify() {
+    cleanup();
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill_test_data(&fs, 20, true);
+
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backend.backup(&fs).expect("backup failed");
+    backend.reset();
+
+    let report = backend.verify(&fs).unwrap();
+    backend.reset();
+    assert!(report.is_equal());
+    assert_eq!((report.dirs, report.files), (4, 23));
+
+    // modify contents, size & attribute, remove one file and add another one
+    let path = |p: &str| PathBuf::try_from(p).unwrap();
+    fs.write(
+        &path("/testdir/testfile0001"),
+        b"sowqxxxxxxxxxxxxxxxxxxxxxxxxxxxxxdwfqefewefwfwefeweffwewdqwdqdwfewefwefxwefxoejfofwe0002",
+    )
+    .unwrap();
+    fs.write(&path("/testdir/testfile0002"), b"short").unwrap();
+    fs.set_attribute(&path("/was"), USER_ATTRIBUTE_NUMBER, b"attr")
+        .unwrap();
+    fs.remove(&path("/was/dort")).unwrap();
+    fs.write(&path("/testdir/new"), b"new").unwrap();
+
+    let report = backend.verify(&fs).unwrap();
+    backend.reset();
+    assert!(!report.is_equal());
+    assert_eq!(report.missing, 1);
+    assert_eq!(report.content_mismatch, 2);
+    assert_eq!(report.attr_mismatch, 1);
+    assert_eq!(report.extra, 1);
+
+    // legacy backups are compared as well
+    backend.convert_to_legacy();
+    let report = backend.verify(&fs).unwrap();
+    assert_eq!(report.missing, 1);
+    assert_eq!(report.content_mismatch, 2);
+    assert_eq!(report.attr_mismatch, 1);
+    assert_eq!(report.extra, 1);
+}
+
+#[test]
+fn path_patterns() {
+    let path = |p: &str| PathBuf::try_from(p).unwrap();
+
+    let patterns = PathPatterns::new(&["/fido", "/*/sec/0?", "/**/x5c"], &["/fido/rk"]);
+    assert!(patterns.matches(&path("/fido")));
+    assert!(patterns.matches(&path("/fido/dat/00")));
+    assert!(!patterns.matches(&path("/fido/rk")));
+    assert!(!patterns.matches(&path("/fido/rk/01")));
+    assert!(patterns.matches(&path("/attn/sec/01")));
+    assert!(!patterns.matches(&path("/attn/sec/010")));
+    assert!(patterns.matches(&path("/opcard/a/b/x5c/00")));
+    assert!(!patterns.matches(&path("/opcard")));
+    assert!(!patterns.matches(&path("/attn")));
+
+    // parents of possible matches are traversed
+    assert!(patterns.descend(&path("/")));
+    assert!(patterns.descend(&path("/attn")));
+    assert!(patterns.descend(&path("/opcard/a")));
+    assert!(!patterns.descend(&path("/fido/rk")));
+
+    let patterns = PathPatterns::exclude(&["/testdir/*1*"]);
+    assert!(patterns.matches(&path("/testdir/testfile0000")));
+    assert!(!patterns.matches(&path("/testdir/testfile0010")));
+    assert!(patterns.descend(&path("/testdir")));
+}
+
+/// backup the test data filtered by `backup_filter`, then restore it filtered
+/// by `restore_filter`, returns both counts
+fn filtered_backup_restore(
+    backup_filter: &impl PathFilter,
+    restore_filter: &impl PathFilter,
+) -> ((usize, usize), (usize, usize)) {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill_test_data(&fs, 20, true);
+
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    let res_backup = backend.backup_filtered(&fs, backup_filter).unwrap();
+    backend.reset();
+
+    let mut target_alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut target_storage = FileFlash::new(TARGET_FS_PATH);
+    Filesystem::format(&mut target_storage).expect("(target) formatting failed");
+    let target_fs =
+        Filesystem::mount(&mut target_alloc, &mut target_storage).expect("failed target mount");
+    let res_restore = backend
+        .restore_filtered(&target_fs, restore_filter)
+        .unwrap();
+
+    // everything restored is equal to the origin
+    for file in ["/was/geht/denn/bluba", "/was/geht/denn/hier", "/was/dort"] {
+        let path = PathBuf::try_from(file).unwrap();
+        if target_fs.exists(&path) {
+            assert_eq!(
+                fs.read::<MAX_TEST_FILE_SIZE>(&path).unwrap(),
+                target_fs.read::<MAX_TEST_FILE_SIZE>(&path).unwrap()
+            );
+        }
+    }
+
+    (res_backup, res_restore)
+}
+
+#[test]
+#[serial]
+fn fsbackup_filtered() {
+    cleanup();
+    let all = |_: &Path| true;
+
+    // include a subtree
+    let was = PathPatterns::include(&["/was"]);
+    assert_eq!(filtered_backup_restore(&was, &all), ((3, 3), (3, 3)));
+
+    // exclude files by glob
+    let exclude = PathPatterns::exclude(&["/testdir/testfile001?"]);
+    assert_eq!(filtered_backup_restore(&exclude, &all), ((4, 13), (4, 13)));
+
+    // only files (w/o their parent directories) => parents are created on restore
+    let hier = PathPatterns::include(&["/**/hier"]);
+    assert_eq!(filtered_backup_restore(&hier, &all), ((0, 1), (0, 1)));
+
+    // `should_preserve_file`-style predicate during restore
+    let deep = |path: &Path| path.as_str().starts_with("/was/geht/");
+    assert_eq!(filtered_backup_restore(&all, &deep), ((4, 23), (1, 2)));
+}
+
+/// restore the test data with `policy` into a target filesystem containing
+/// some of its entries, returns the restore result & the verify report
+fn restore_with_conflicts(
+    backend: &mut FileBackend,
+    policy: RestorePolicy,
+    prepare: impl FnOnce(&Filesystem<FileFlash>),
+) -> (Result<RestoreReport>, VerifyReport) {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(TARGET_FS_PATH);
+    Filesystem::format(&mut storage).expect("(target) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed target mount");
+    prepare(&fs);
+
+    let res = backend.restore_with(&fs, &|_: &Path| true, policy);
+    backend.reset();
+    let report = backend.verify(&fs).unwrap();
+    backend.reset();
+    (res, report)
+}
+
+#[test]
+#[serial]
+fn fsbackup_restore_policies() {
+    cleanup();
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backup_test_data(&mut backend, 20);
+
+    let path = |p: &str| PathBuf::try_from(p).unwrap();
+    // existing directories, a modified file, a directory in place of a file & an extra file
+    let conflicts = |fs: &Filesystem<FileFlash>| {
+        fs.create_dir_all(&path("/was/geht")).unwrap();
+        fs.write(&path("/was/dort"), b"other").unwrap();
+        fs.set_attribute(&path("/was/dort"), USER_ATTRIBUTE_NUMBER, b"attr")
+            .unwrap();
+        fs.create_dir_all(&path("/testdir/testfile0001")).unwrap();
+        fs.write(&path("/extra"), b"extra").unwrap();
+    };
+    let dirs_only = |fs: &Filesystem<FileFlash>| {
+        fs.create_dir_all(&path("/was/geht")).unwrap();
+    };
+
+    // nothing is written on conflicts
+    let (res, report) = restore_with_conflicts(&mut backend, RestorePolicy::Fail, dirs_only);
+    assert_eq!(res, Err(FSBackupError::RestoreConflictErr));
+    assert_eq!(report.missing, 25);
+    let (res, report) = restore_with_conflicts(&mut backend, RestorePolicy::MergeDirs, conflicts);
+    assert_eq!(res, Err(FSBackupError::RestoreConflictErr));
+    assert_eq!(report.missing, 23);
+
+    let (res, report) = restore_with_conflicts(&mut backend, RestorePolicy::MergeDirs, dirs_only);
+    let expected = RestoreReport {
+        dirs: 2,
+        files: 23,
+        merged: 2,
+        ..Default::default()
+    };
+    assert_eq!(res, Ok(expected));
+    assert!(report.is_equal());
+
+    // existing entries are left untouched
+    let (res, report) = restore_with_conflicts(&mut backend, RestorePolicy::Skip, conflicts);
+    let expected = RestoreReport {
+        dirs: 1,
+        files: 21,
+        skipped: 5,
+        ..Default::default()
+    };
+    assert_eq!(res, Ok(expected));
+    assert_eq!(report.missing, 1);
+    assert_eq!(report.content_mismatch, 1);
+    assert_eq!(report.attr_mismatch, 1);
+
+    // existing entries are replaced, only the extra file remains
+    let (res, report) = restore_with_conflicts(&mut backend, RestorePolicy::Overwrite, conflicts);
+    let expected = RestoreReport {
+        dirs: 1,
+        files: 21,
+        overwritten: 5,
+        ..Default::default()
+    };
+    assert_eq!(res, Ok(expected));
+    assert_eq!(
+        (
+            report.missing,
+            report.content_mismatch,
+            report.attr_mismatch
+        ),
+        (0, 0, 0)
+    );
+    assert_eq!(report.extra, 1);
+}
+
+/// backup the tree created by `fill`, restore it & compare both filesystems
+fn tree_backup_restore(fill: impl FnOnce(&Filesystem<FileFlash>)) -> (usize, usize) {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill(&fs);
+
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    let res_backup = backend.backup(&fs).expect("backup failed");
+    backend.reset();
+
+    let mut target_alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut target_storage = FileFlash::new(TARGET_FS_PATH);
+    Filesystem::format(&mut target_storage).expect("(target) formatting failed");
+    let target_fs =
+        Filesystem::mount(&mut target_alloc, &mut target_storage).expect("failed target mount");
+    let res_restore = backend.restore(&target_fs).expect("restore failed");
+
+    assert_eq!(res_backup, res_restore);
+    assert_eq!(equal_filesystems(&fs, &target_fs), res_backup);
+    assert_eq!(equal_filesystems(&target_fs, &fs), res_backup);
+    res_backup
+}
+
+#[test]
+#[serial]
+fn fsbackup_deep_tree() {
+    cleanup();
+    // 100 nested directories, i.e., close to `PATH_MAX`
+    let counts = tree_backup_restore(|fs| {
+        let mut path = String::new();
+        for _ in 0..100 {
+            path.push_str("/d");
+        }
+        fs.create_dir_all(&PathBuf::try_from(path.as_str()).unwrap())
+            .unwrap();
+        path.push_str("/file");
+        fs.write(&PathBuf::try_from(path.as_str()).unwrap(), b"deep")
+            .unwrap();
+    });
+    assert_eq!(counts, (100, 1));
+}
+
+#[test]
+#[serial]
+fn fsbackup_wide_tree() {
+    cleanup();
+    // many files & subdirectories (each with a file) inside a single directory
+    let counts = tree_backup_restore(|fs| {
+        fs.create_dir(path!("/wide")).unwrap();
+        for idx in 0..200 {
+            let path = PathBuf::try_from(format!("/wide/file{idx:0>3}").as_str()).unwrap();
+            fs.write(&path, format!("{idx}").as_bytes()).unwrap();
+        }
+        for idx in 0..50 {
+            let path = PathBuf::try_from(format!("/wide/dir{idx:0>3}/file").as_str()).unwrap();
+            fs.create_dir(&path.parent().unwrap()).unwrap();
+            fs.write(&path, format!("{idx}").as_bytes()).unwrap();
+        }
+    });
+    assert_eq!(counts, (51, 250));
+}
+
+#[test]
+#[serial]
+fn walk_linear() {
+    cleanup();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("format failed");
+    Filesystem::mount_and_then(&mut storage, |fs| {
+        fs.create_dir(path!("/wide")).unwrap();
+        for idx in 0..200 {
+            let path = PathBuf::try_from(format!("/wide/dir{idx:0>3}/file").as_str()).unwrap();
+            fs.create_dir(&path.parent().unwrap()).unwrap();
+            fs.write(&path, b"x").unwrap();
+            let path = PathBuf::try_from(format!("/wide/file{idx:0>3}").as_str()).unwrap();
+            fs.write(&path, b"x").unwrap();
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    // reads every directory once, opening a subdirectory looks up its path in the parent
+    let reads_once = Filesystem::mount_and_then(&mut storage, |fs| {
+        let start = STORAGE_READS.load(Ordering::SeqCst);
+        fs.read_dir_and_then(path!("/"), |entries| Ok(entries.count()))?;
+        fs.read_dir_and_then(path!("/wide"), |entries| {
+            for entry in entries.skip(2) {
+                let entry = entry?;
+                if entry.file_type().is_dir() {
+                    fs.read_dir_and_then(entry.path(), |entries| Ok(entries.count()))?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(STORAGE_READS.load(Ordering::SeqCst) - start)
+    })
+    .unwrap();
+
+    let (entries, reads) = Filesystem::mount_and_then(&mut storage, |fs| {
+        let mut entries = 0;
+        let start = STORAGE_READS.load(Ordering::SeqCst);
+        walk(fs, path!("/"), &mut |_| {
+            entries += 1;
+            Ok(true)
+        })
+        .unwrap();
+        Ok((entries, STORAGE_READS.load(Ordering::SeqCst) - start))
+    })
+    .unwrap();
+    assert_eq!(entries, 1 + 3 * 200);
+    assert!(reads <= reads_once, "{reads} > {reads_once}");
+}
+
+#[test]
+#[serial]
+fn walk_order() {
+    cleanup();
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    for dir in ["/a/b/c", "/a/d", "/e/f", "/g"] {
+        fs.create_dir_all(&PathBuf::try_from(dir).unwrap()).unwrap();
+    }
+    for file in ["/a/b/c/1", "/a/2", "/e/f/3", "/4"] {
+        fs.write(&PathBuf::try_from(file).unwrap(), b"x").unwrap();
+    }
+
+    // directories precede their contents, the contents of "/e" are skipped
+    let mut visited = std::vec::Vec::new();
+    walk(&fs, path!("/"), &mut |entry| {
+        visited.push(entry.path().as_str().to_string());
+        Ok(entry.path() != path!("/e"))
+    })
+    .unwrap();
+    for (idx, path) in visited.iter().enumerate() {
+        let parent = &path[..path.rfind('/').unwrap()];
+        assert!(parent.is_empty() || visited[..idx].iter().any(|p| p == parent));
+    }
+    visited.sort();
+    assert_eq!(
+        visited,
+        ["/4", "/a", "/a/2", "/a/b", "/a/b/c", "/a/b/c/1", "/a/d", "/e", "/g"]
+    );
+}
+
+/// backup `origin` and restore it into the re-formatted `target` using the
+/// progress journal, like the NK3AM migration
+fn journaled_migrate(
+    origin: &Filesystem<FileFlash>,
+    target: &mut FileFlash,
+    backend: &mut FileBackend,
+) -> Result<()> {
+    backend.erase()?;
+    backend.reset();
+    backend.backup(origin)?;
+    backend.begin_restore()?;
+    Filesystem::format(target)?;
+    journaled_restore(target, backend)
+}
+
+/// resume the restore into `target`, the backup is erased once it is done
+fn journaled_restore(target: &mut FileFlash, backend: &mut FileBackend) -> Result<()> {
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let fs = Filesystem::mount(&mut alloc, target)?;
+    backend.resume(&fs)?;
+    backend.erase()?;
+    Ok(())
+}
+
+/// boot after a power cut, i.e., finish the interrupted migration
+fn recover(
+    origin: &Filesystem<FileFlash>,
+    target: &mut FileFlash,
+    backend: &mut FileBackend,
+) -> Result<()> {
+    let progress = backend.progress()?;
+    match progress {
+        Progress::Restoring { .. } | Progress::Restored => {
+            // restart the restore if the target was not formatted completely
+            if !Filesystem::is_mountable(target) {
+                backend.begin_restore()?;
+                Filesystem::format(target)?;
+            }
+            journaled_restore(target, backend)
+        }
+        // the target is only touched once the restore has begun
+        Progress::Incomplete | Progress::Complete if !Filesystem::is_mountable(target) => {
+            journaled_migrate(origin, target, backend)
+        }
+        // migration done
+        Progress::Incomplete | Progress::Complete => Ok(()),
+        Progress::Failed => Err(FSBackupError::RestoreErr),
+    }
+}
+
+#[test]
+#[serial]
+fn fsbackup_power_cut() {
+    // cut the power at every single write of the migration, then reboot & recover
+    for writes in 0.. {
+        cleanup();
+        let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+        let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+        Filesystem::format(&mut storage).expect("(origin) format failed");
+        let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+        fill_test_data(&fs, 5, true);
+
+        let mut target = FileFlash::new(TARGET_FS_PATH);
+        let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+
+        cut_power_after(writes);
+        let res = journaled_migrate(&fs, &mut target, &mut backend);
+        cut_power_after(usize::MAX);
+
+        let done = res.is_ok();
+        if !done {
+            let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+            recover(&fs, &mut target, &mut backend)
+                .unwrap_or_else(|e| panic!("recovery after {writes} writes failed: {e:?}"));
+        }
+
+        let mut target_alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+        let target_fs = Filesystem::mount(&mut target_alloc, &mut target).expect("failed mount");
+        assert_eq!(equal_filesystems(&fs, &target_fs), (4, 8));
+        assert_eq!(equal_filesystems(&target_fs, &fs), (4, 8));
+
+        // the backup is erased after the migration
+        let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+        assert_eq!(backend.progress(), Ok(Progress::Incomplete));
+
+        if done {
+            break;
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn fsbackup_resume_gives_up() {
+    cleanup();
+    let mut alloc: littlefs2::fs::Allocation<FileFlash> = Filesystem::allocate();
+    let mut storage = FileFlash::new(ORIGIN_FS_PATH);
+    Filesystem::format(&mut storage).expect("(origin) format failed");
+    let fs = Filesystem::mount(&mut alloc, &mut storage).expect("failed mount");
+    fill_test_data(&fs, 5, true);
+
+    let mut target = FileFlash::new(TARGET_FS_PATH);
+    let mut backend = FileBackend::new(StdPath::new(BACKUP_DATA_PATH));
+    backend.backup(&fs).unwrap();
+    backend.begin_restore().unwrap();
+    Filesystem::format(&mut target).unwrap();
+
+    // crash right after recording every attempt, before any checkpoint
+    for _ in 0..MAX_RESUME_ATTEMPTS {
+        cut_power_after(1);
+        assert!(journaled_restore(&mut target, &mut backend).is_err());
+        cut_power_after(usize::MAX);
+        assert_eq!(backend.progress(), Ok(Progress::Restoring { entries: 0 }));
+    }
+
+    // the restore is given up, but the backup is kept
+    assert_eq!(
+        journaled_restore(&mut target, &mut backend),
+        Err(FSBackupError::RestoreErr)
+    );
+    assert_eq!(backend.progress(), Ok(Progress::Failed));
+    assert_eq!(backend.begin_restore(), Err(FSBackupError::RestoreErr));
+    assert_eq!(
+        journaled_restore(&mut target, &mut backend),
+        Err(FSBackupError::RestoreErr)
+    );
+    backend.reset();
+    assert!(backend.check().is_ok());
+}
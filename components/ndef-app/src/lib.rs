@@ -1,4 +1,10 @@
 #![no_std]
 
+pub mod message;
 pub mod ndef;
+pub mod otp;
+mod pin;
+pub mod tag;
+pub mod template;
+pub use message::{Message, Template};
 pub use ndef::*;
@@ -0,0 +1,363 @@
+//! Encoding of the NDEF file, i.e., the message length followed by the NDEF records.
+//!
+//! URIs and texts can contain the placeholders `{uuid}` and `{serial}`, which are replaced with
+//! the device UUID and the serial number, i.e., the first four bytes of the UUID, in hex.  The
+//! placeholders `{otp}` and `{otp_key}` are replaced with a fresh one-time value on every read,
+//! see `otp`.
+
+use core::convert::{TryFrom, TryInto};
+use heapless::{String, Vec};
+
+use crate::otp::{OTP_KEY_LEN, OTP_LEN};
+
+/// Maximum size of the NDEF file, including the two-byte length.
+pub const NDEF_FILE_SIZE: usize = 256;
+
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_SR: u8 = 0x10;
+const TNF_WELL_KNOWN: u8 = 0x01;
+
+const TYPE_URI: u8 = b'U';
+const TYPE_TEXT: u8 = b'T';
+
+// URI identifier codes of the NFC Forum URI RTD, longest prefixes first
+const URI_PREFIXES: [(u8, &str); 4] = [
+    (0x02, "https://www."),
+    (0x01, "http://www."),
+    (0x04, "https://"),
+    (0x03, "http://"),
+];
+
+const TEXT_LANGUAGE: &str = "en";
+
+const TEMPLATE_URI_LEN: usize = 128;
+const TEMPLATE_TEXT_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// the message does not fit into the NDEF file
+    TooLarge,
+    /// the one-time value could not be computed
+    Otp,
+}
+
+pub type Uuid = [u8; 16];
+
+/// The values of the placeholders.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Values<'a> {
+    pub uuid: Uuid,
+    pub otp: &'a str,
+    pub otp_key: &'a str,
+}
+
+impl Values<'_> {
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            ..Default::default()
+        }
+    }
+}
+
+/// A URI and optional texts with placeholders, rendered to a message on every read if it
+/// contains a one-time value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    uri: String<TEMPLATE_URI_LEN>,
+    text: String<TEMPLATE_TEXT_LEN>,
+}
+
+impl Template {
+    /// Creates a template with a URI record and a text record for every non-empty line of `text`.
+    pub fn new(uri: &str, text: &str) -> Result<Self, Error> {
+        Ok(Self {
+            uri: uri.try_into().map_err(|_| Error::TooLarge)?,
+            text: text.try_into().map_err(|_| Error::TooLarge)?,
+        })
+    }
+
+    /// Parses a template stored as the URI, followed by a newline and the text.
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let (uri, text) = template.split_once('\n').unwrap_or((template, ""));
+        Self::new(uri, text)
+    }
+
+    /// The template of the default message, see `Message::nitrokey`.
+    pub fn nitrokey() -> Self {
+        Self::new("https://www.nitrokey.com/", "").unwrap()
+    }
+
+    pub fn needs_otp(&self) -> bool {
+        self.contains("{otp}")
+    }
+
+    pub fn needs_otp_key(&self) -> bool {
+        self.contains("{otp_key}")
+    }
+
+    /// Returns true if the message has to be rendered on every read.
+    pub fn is_dynamic(&self) -> bool {
+        self.needs_otp() || self.needs_otp_key()
+    }
+
+    pub fn render(&self, values: &Values<'_>) -> Result<Message, Error> {
+        let mut message = Message::new();
+        message.push_uri(&self.uri, values)?;
+        for text in self.text.lines().filter(|text| !text.is_empty()) {
+            message.push_text(text, values)?;
+        }
+        Ok(message)
+    }
+
+    /// Checks that the message fits into the NDEF file for all values of the placeholders.
+    pub fn check(&self, uuid: Uuid) -> Result<(), Error> {
+        let otp = [b'A'; OTP_LEN];
+        let otp_key = [b'A'; OTP_KEY_LEN];
+        let values = Values {
+            uuid,
+            otp: core::str::from_utf8(&otp).unwrap(),
+            otp_key: core::str::from_utf8(&otp_key).unwrap(),
+        };
+        self.render(&values).map(|_| ())
+    }
+
+    fn contains(&self, placeholder: &str) -> bool {
+        self.uri.contains(placeholder) || self.text.contains(placeholder)
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self::nitrokey()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    file: Vec<u8, NDEF_FILE_SIZE>,
+    // offset of the header of the last record
+    last_record: Option<usize>,
+}
+
+impl Message {
+    pub fn new() -> Self {
+        let mut file = Vec::new();
+        file.extend_from_slice(&[0, 0]).unwrap();
+        Self {
+            file,
+            last_record: None,
+        }
+    }
+
+    /// The default message, a link to the Nitrokey website.
+    pub fn nitrokey() -> Self {
+        let mut message = Self::new();
+        message
+            .push_uri("https://www.nitrokey.com/", &Values::default())
+            .unwrap();
+        message
+    }
+
+    /// Appends a URI record, expanding the placeholders.
+    pub fn push_uri(&mut self, uri: &str, values: &Values<'_>) -> Result<(), Error> {
+        let uri = expand(uri, values)?;
+        let (code, rest) = URI_PREFIXES
+            .iter()
+            .find_map(|(code, prefix)| uri.strip_prefix(prefix).map(|rest| (*code, rest)))
+            .unwrap_or((0x00, &uri));
+        let mut payload = Vec::<u8, NDEF_FILE_SIZE>::new();
+        payload.push(code).map_err(|_| Error::TooLarge)?;
+        payload
+            .extend_from_slice(rest.as_bytes())
+            .map_err(|_| Error::TooLarge)?;
+        self.push_record(TYPE_URI, &payload)
+    }
+
+    /// Appends an English UTF-8 text record, expanding the placeholders.
+    pub fn push_text(&mut self, text: &str, values: &Values<'_>) -> Result<(), Error> {
+        let text = expand(text, values)?;
+        let mut payload = Vec::<u8, NDEF_FILE_SIZE>::new();
+        // status byte: UTF-8, length of the language code
+        payload
+            .push(TEXT_LANGUAGE.len() as u8)
+            .map_err(|_| Error::TooLarge)?;
+        payload
+            .extend_from_slice(TEXT_LANGUAGE.as_bytes())
+            .and_then(|_| payload.extend_from_slice(text.as_bytes()))
+            .map_err(|_| Error::TooLarge)?;
+        self.push_record(TYPE_TEXT, &payload)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_record.is_none()
+    }
+
+    /// The content of the NDEF file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.file
+    }
+
+    fn push_record(&mut self, record_type: u8, payload: &[u8]) -> Result<(), Error> {
+        // short records only, as the payload always fits into the file
+        let payload_len = u8::try_from(payload.len()).map_err(|_| Error::TooLarge)?;
+        let header = match self.last_record {
+            Some(_) => FLAG_ME | FLAG_SR | TNF_WELL_KNOWN,
+            None => FLAG_MB | FLAG_ME | FLAG_SR | TNF_WELL_KNOWN,
+        };
+        let offset = self.file.len();
+        self.file
+            .extend_from_slice(&[header, 1, payload_len, record_type])
+            .and_then(|_| self.file.extend_from_slice(payload))
+            .map_err(|_| {
+                self.file.truncate(offset);
+                Error::TooLarge
+            })?;
+
+        if let Some(last_record) = self.last_record.replace(offset) {
+            self.file[last_record] &= !FLAG_ME;
+        }
+        let len = (self.file.len() - 2) as u16;
+        self.file[..2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::nitrokey()
+    }
+}
+
+fn expand(template: &str, values: &Values<'_>) -> Result<String<NDEF_FILE_SIZE>, Error> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let (before, placeholder) = rest.split_at(start);
+        expanded.push_str(before).map_err(|_| Error::TooLarge)?;
+        if let Some(after) = placeholder.strip_prefix("{uuid}") {
+            push_hex(&mut expanded, &values.uuid)?;
+            rest = after;
+        } else if let Some(after) = placeholder.strip_prefix("{serial}") {
+            push_hex(&mut expanded, &values.uuid[..4])?;
+            rest = after;
+        } else if let Some(after) = placeholder.strip_prefix("{otp}") {
+            expanded.push_str(values.otp).map_err(|_| Error::TooLarge)?;
+            rest = after;
+        } else if let Some(after) = placeholder.strip_prefix("{otp_key}") {
+            expanded
+                .push_str(values.otp_key)
+                .map_err(|_| Error::TooLarge)?;
+            rest = after;
+        } else {
+            expanded.push('{').map_err(|_| Error::TooLarge)?;
+            rest = &placeholder[1..];
+        }
+    }
+    expanded.push_str(rest).map_err(|_| Error::TooLarge)?;
+    Ok(expanded)
+}
+
+fn push_hex<const N: usize>(out: &mut String<N>, bytes: &[u8]) -> Result<(), Error> {
+    for byte in bytes {
+        for nibble in [byte >> 4, byte & 0xf] {
+            out.push(char::from(b"0123456789ABCDEF"[usize::from(nibble)]))
+                .map_err(|_| Error::TooLarge)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: Uuid = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        0x77,
+    ];
+    const VALUES: Values<'static> = Values {
+        uuid: UUID,
+        otp: "OTP",
+        otp_key: "KEY",
+    };
+
+    #[test]
+    fn nitrokey() {
+        let expected = [
+            0x00, 0x12, 0xd1, 0x01, 0x0e, 0x55, 0x02, 0x6e, 0x69, 0x74, 0x72, 0x6f, 0x6b, 0x65,
+            0x79, 0x2e, 0x63, 0x6f, 0x6d, 0x2f,
+        ];
+        assert_eq!(Message::nitrokey().as_bytes(), expected);
+    }
+
+    #[test]
+    fn placeholders() {
+        let mut message = Message::new();
+        message
+            .push_uri(
+                "https://example.com/{serial}?id={uuid}&{x}&o={otp}&k={otp_key}",
+                &VALUES,
+            )
+            .unwrap();
+        let uri = &message.as_bytes()[7..];
+        assert_eq!(
+            uri,
+            b"example.com/01234567?id=0123456789ABCDEF0011223344556677&{x}&o=OTP&k=KEY"
+        );
+    }
+
+    #[test]
+    fn records() {
+        let mut message = Message::new();
+        message.push_uri("urn:nk", &VALUES).unwrap();
+        message.push_text("hi", &VALUES).unwrap();
+        let expected = [
+            0x00, 0x14, /* length */
+            0x91, 0x01, 0x07, 0x55, 0x00, b'u', b'r', b'n', b':', b'n', b'k', /* URI */
+            0x51, 0x01, 0x05, 0x54, 0x02, b'e', b'n', b'h', b'i', /* text */
+        ];
+        assert_eq!(message.as_bytes(), expected);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut message = Message::new();
+        let text = [b'a'; 200];
+        let text = core::str::from_utf8(&text).unwrap();
+        message.push_text(text, &VALUES).unwrap();
+        let before = message.clone();
+        assert_eq!(message.push_text(text, &VALUES), Err(Error::TooLarge));
+        assert_eq!(message, before);
+    }
+
+    #[test]
+    fn template() {
+        let template = Template::nitrokey();
+        assert!(!template.is_dynamic());
+        assert_eq!(template.render(&Values::new(UUID)), Ok(Message::nitrokey()));
+
+        let template = Template::new("urn:nk", "hi\n\n{serial}\n").unwrap();
+        let mut message = Message::new();
+        message.push_uri("urn:nk", &VALUES).unwrap();
+        message.push_text("hi", &VALUES).unwrap();
+        message.push_text("01234567", &VALUES).unwrap();
+        assert_eq!(template.render(&VALUES), Ok(message));
+
+        let parsed = Template::parse("urn:nk\nhi\n\n{serial}\n").unwrap();
+        assert_eq!(parsed, template);
+        assert_eq!(Template::parse("urn:nk"), Template::new("urn:nk", ""));
+
+        let template = Template::new("https://example.com/{otp}", "{otp_key}").unwrap();
+        assert!(template.needs_otp());
+        assert!(template.needs_otp_key());
+        assert_eq!(template.check(UUID), Ok(()));
+
+        let uri = [b'a'; 128];
+        let uri = core::str::from_utf8(&uri).unwrap();
+        let template = Template::new(uri, "{otp}{otp_key}").unwrap();
+        assert_eq!(template.render(&Values::new(UUID)).map(|_| ()), Ok(()));
+        assert_eq!(template.check(UUID), Err(Error::TooLarge));
+    }
+}
@@ -1,103 +1,201 @@
+use crate::message::{Error, Message, Template, Uuid, Values, NDEF_FILE_SIZE};
+use crate::otp::Otp;
+use crate::pin::{INS_CHANGE_REFERENCE_DATA, INS_VERIFY};
+use crate::tag::{Event, File, Tag};
+use crate::template::INS_PUT_DATA;
 use apdu_app::{CommandView, Interface};
-use heapless::VecView;
-use iso7816::{Instruction, Status};
+use heapless::{String, Vec, VecView};
+use iso7816::Status;
+use littlefs2_core::{path, Path};
+use trussed_auth::AuthClient;
+use trussed_core::{
+    try_syscall,
+    types::{Location, Message as Data},
+    CryptoClient, FilesystemClient,
+};
 
-pub struct App<'a> {
-    reader: &'a [u8],
-}
+// the message written with UPDATE BINARY, including the length
+const MESSAGE_FILE: &Path = path!("message");
+
+/// The Trussed client required by the app.
+pub trait Client: CryptoClient + FilesystemClient + AuthClient {}
+
+impl<T: CryptoClient + FilesystemClient + AuthClient> Client for T {}
 
-impl<'a> App<'a> {
-    pub const CAPABILITY_CONTAINER: [u8; 15] = [
-        0x00, 0x0f, /* CCEN_HI, CCEN_LOW */
-        0x20, /* VERSION */
-        0x00, 0x7f, /* MLe_HI, MLe_LOW */
-        0x00, 0x7f, /* MLc_HI, MLc_LOW */
-        /* TLV */
-        0x04, 0x06, 0xe1, 0x04, 0x00, 0x7f, 0x00, 0x00,
-    ];
+pub struct Options {
+    pub uuid: Uuid,
+    /// Allows writing the NDEF file.  The written message is served instead of the template.
+    pub writable: bool,
+    /// Requires the PIN for writing the NDEF file, see `pin`.
+    pub pin_required: bool,
+}
 
-    pub const NDEF: [u8; 20] = [
-        0x00, 0x12, /* two-byte length */
-        0xd1, /* TNF: well-known + flags */
-        0x01, /* payload type length */
-        0x0e, /* payload data length */
-        0x55, /* payload type: U = URL */
-        0x02, /* https://www. */
-        0x6e, 0x69, 0x74, 0x72, 0x6f, 0x6b, 0x65, 0x79, 0x2e, 0x63, 0x6f, 0x6d,
-        0x2f, /* nitrokey.com/ */
-    ];
+pub struct App<T> {
+    pub(crate) trussed: T,
+    pub(crate) options: Options,
+    otp: Otp,
+    // the stored template, read when the app is selected
+    pub(crate) template: Option<Template>,
+    // the content of the NDEF file
+    file: Vec<u8, NDEF_FILE_SIZE>,
+    // the file contains a message written with UPDATE BINARY and stored in `MESSAGE_FILE`
+    written: bool,
+    pub(crate) verified: bool,
+    tag: Tag,
+}
 
-    pub fn new() -> App<'a> {
+impl<T: Client> App<T> {
+    /// Serves the rendered template, see `template`, or the written message.
+    ///
+    /// If the template contains a one-time value, it is rendered when the NDEF file is selected.
+    pub fn new(trussed: T, options: Options) -> Self {
         App {
-            reader: &Self::NDEF,
+            trussed,
+            options,
+            otp: Otp::default(),
+            template: None,
+            file: Vec::new(),
+            written: false,
+            verified: false,
+            tag: Tag::default(),
         }
     }
-}
 
-impl Default for App<'_> {
-    fn default() -> Self {
-        Self::new()
+    /// Loads the written message or renders the template if it is static.
+    fn load(&mut self) {
+        self.written = false;
+        if self.options.writable {
+            let stored = try_syscall!(self
+                .trussed
+                .read_file(Location::Internal, MESSAGE_FILE.into()));
+            if let Some(file) = stored
+                .ok()
+                .and_then(|reply| Vec::from_slice(&reply.data).ok())
+            {
+                self.file = file;
+                self.written = true;
+                return;
+            }
+        }
+        if self.template.is_none() {
+            self.template = Some(self.load_template());
+        }
+        let template = self.template.as_ref().unwrap();
+        let message = if template.is_dynamic() {
+            Message::new()
+        } else {
+            let values = Values::new(self.options.uuid);
+            template.render(&values).unwrap_or_default()
+        };
+        self.file = Vec::from_slice(message.as_bytes()).unwrap();
+    }
+
+    fn save(&mut self) -> Result<(), Status> {
+        let data = Data::from_slice(&self.file).map_err(|_| Status::NotEnoughMemory)?;
+        try_syscall!(self
+            .trussed
+            .write_file(Location::Internal, MESSAGE_FILE.into(), data, None))
+        .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        Ok(())
+    }
+
+    /// Renders the template with a fresh one-time value.
+    fn refresh(&mut self) -> Result<(), Error> {
+        let Some(template) = self.template.as_ref() else {
+            return Ok(());
+        };
+        if self.written || !template.is_dynamic() {
+            return Ok(());
+        }
+        // never serve a previous one-time value
+        self.file = Vec::from_slice(Message::new().as_bytes()).unwrap();
+        let otp = if template.needs_otp() {
+            self.otp.next(&mut self.trussed, &self.options.uuid)?
+        } else {
+            String::new()
+        };
+        let otp_key = if template.needs_otp_key() {
+            self.otp.public_key(&mut self.trussed)?
+        } else {
+            String::new()
+        };
+        let values = Values {
+            uuid: self.options.uuid,
+            otp: &otp,
+            otp_key: &otp_key,
+        };
+        let message = template.render(&values)?;
+        self.file = Vec::from_slice(message.as_bytes()).unwrap();
+        Ok(())
+    }
+
+    /// Grants write access if the NDEF file is writable and the PIN has been verified if
+    /// required.
+    pub(crate) fn update_write_access(&mut self) {
+        let writable = self.options.writable && (!self.options.pin_required || self.verified);
+        self.tag.set_writable(writable);
     }
 }
 
-impl iso7816::App for App<'_> {
+impl<T> iso7816::App for App<T> {
     fn aid(&self) -> iso7816::Aid {
         iso7816::Aid::new(&[0xD2u8, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01])
     }
 }
 
-impl apdu_app::App for App<'_> {
+impl<T: Client> apdu_app::App for App<T> {
     fn select(
         &mut self,
         _interface: Interface,
         _apdu: CommandView<'_>,
         _reply: &mut VecView<u8>,
     ) -> apdu_app::Result {
+        self.tag.reset();
+        self.verified = false;
+        self.update_write_access();
+        self.load();
         Ok(())
     }
 
-    fn deselect(&mut self) {}
+    fn deselect(&mut self) {
+        self.tag.reset();
+        self.verified = false;
+        self.update_write_access();
+    }
 
     fn call(
         &mut self,
-        _type: Interface,
+        interface: Interface,
         apdu: CommandView<'_>,
         reply: &mut VecView<u8>,
     ) -> apdu_app::Result {
-        let instruction = apdu.instruction();
-        let p1 = apdu.p1;
-        let p2 = apdu.p2;
-        let expected = apdu.expected();
-        let payload = apdu.data();
-
-        match instruction {
-            Instruction::Select => {
-                if payload.starts_with(&[0xE1u8, 0x03]) {
-                    self.reader = &Self::CAPABILITY_CONTAINER;
-                    Ok(())
-                } else if payload.starts_with(&[0xE1u8, 0x04]) {
-                    self.reader = &Self::NDEF;
-                    Ok(())
-                } else {
-                    Err(Status::NotFound)
-                }
+        if self.options.writable && self.options.pin_required {
+            match u8::from(apdu.instruction()) {
+                INS_VERIFY => return self.verify(apdu),
+                INS_CHANGE_REFERENCE_DATA => return self.change_pin(interface, apdu),
+                _ => {}
             }
-            Instruction::ReadBinary => {
-                let offset = (((p1 & 0xef) as usize) << 8) | p2 as usize;
-                let len_to_read = if expected > (self.reader.len() - offset) {
-                    self.reader.len() - offset
-                } else if expected > 0 {
-                    expected
-                } else {
-                    self.reader.len() - offset
-                };
+        }
+        if u8::from(apdu.instruction()) == INS_PUT_DATA {
+            let result = self.put_template(interface, apdu);
+            self.load();
+            return result;
+        }
 
-                reply
-                    .extend_from_slice(&self.reader[offset..offset + len_to_read])
-                    .ok();
+        match self.tag.call(apdu, &mut self.file, reply)? {
+            Some(Event::Selected(File::Ndef)) => self
+                .refresh()
+                .map_err(|_| Status::UnspecifiedNonpersistentExecutionError),
+            Some(Event::Updated { complete: true }) => {
+                if let Err(status) = self.save() {
+                    // never serve a message that is not stored
+                    self.load();
+                    return Err(status);
+                }
+                self.written = true;
                 Ok(())
             }
-            _ => Err(Status::ConditionsOfUseNotSatisfied),
+            _ => Ok(()),
         }
     }
 }
@@ -0,0 +1,204 @@
+//! One-time values for the `{otp}` and `{otp_key}` placeholders.
+//!
+//! The one-time value is a counter that is incremented on every read of the NDEF file, signed
+//! with a device-specific Ed25519 key.  `{otp}` expands to `base64url(counter || signature)`,
+//! with the counter as a big-endian `u32` and the signature over `uuid || counter`.  `{otp_key}`
+//! expands to the base64url-encoded public key so that a server can enroll the device.  The
+//! server accepts a value if the signature is valid and the counter is greater than the last
+//! accepted counter.
+//!
+//! The key and the counter are stored in the internal filesystem because the external flash is
+//! not available if the device is powered by NFC.  To avoid a flash write on every read, the
+//! counter values are reserved in blocks of `COUNTER_RESERVATION`: the end of a block is written
+//! before its first value is returned, and after a reboot, the counter continues after the last
+//! reserved block.  So a counter value is never reused, but there can be gaps.
+//!
+//! HOTP codes of secrets-app credentials are not supported because the credentials are only
+//! accessible for the Trussed client of secrets-app.
+
+use core::convert::TryInto;
+use heapless::String;
+use littlefs2_core::{path, Path};
+use serde::{Deserialize, Serialize};
+use trussed_core::{
+    syscall, try_syscall,
+    types::{
+        KeyId, KeySerialization, Location, Mechanism, Message, SignatureSerialization,
+        StorageAttributes,
+    },
+    CryptoClient, FilesystemClient,
+};
+
+use crate::message::{Error, Uuid};
+
+const STATE_FILE: &Path = path!("otp");
+
+// number of counter values reserved with one write of the state file
+const COUNTER_RESERVATION: u32 = 32;
+
+const COUNTER_LEN: usize = 4;
+const SIGNATURE_LEN: usize = 64;
+const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of the expanded `{otp}` placeholder.
+pub const OTP_LEN: usize = base64url_len(COUNTER_LEN + SIGNATURE_LEN);
+/// Length of the expanded `{otp_key}` placeholder.
+pub const OTP_KEY_LEN: usize = base64url_len(PUBLIC_KEY_LEN);
+
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+struct State {
+    key: KeyId,
+    // the last reserved counter value
+    counter: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Otp {
+    state: Option<State>,
+    // the last used counter value
+    counter: u32,
+}
+
+impl Otp {
+    /// Increments the counter and returns the signed counter, see the module documentation.
+    pub fn next<T: CryptoClient + FilesystemClient>(
+        &mut self,
+        trussed: &mut T,
+        uuid: &Uuid,
+    ) -> Result<String<OTP_LEN>, Error> {
+        let mut state = self.state(trussed)?;
+        let counter = self.counter.checked_add(1).ok_or(Error::Otp)?;
+        if counter > state.counter {
+            state.counter = counter.saturating_add(COUNTER_RESERVATION - 1);
+            save(trussed, &state)?;
+            self.state = Some(state);
+        }
+        self.counter = counter;
+
+        let counter = counter.to_be_bytes();
+        let mut data = [0; 16 + COUNTER_LEN];
+        data[..16].copy_from_slice(uuid);
+        data[16..].copy_from_slice(&counter);
+        let signature = try_syscall!(trussed.sign(
+            Mechanism::Ed255,
+            state.key,
+            &data,
+            SignatureSerialization::Raw
+        ))
+        .map_err(|_| Error::Otp)?
+        .signature;
+        let signature: [u8; SIGNATURE_LEN] =
+            signature.as_slice().try_into().map_err(|_| Error::Otp)?;
+
+        let mut value = [0; COUNTER_LEN + SIGNATURE_LEN];
+        value[..COUNTER_LEN].copy_from_slice(&counter);
+        value[COUNTER_LEN..].copy_from_slice(&signature);
+        base64url(&value)
+    }
+
+    /// Returns the public key for the signatures, see the module documentation.
+    pub fn public_key<T: CryptoClient + FilesystemClient>(
+        &mut self,
+        trussed: &mut T,
+    ) -> Result<String<OTP_KEY_LEN>, Error> {
+        let state = self.state(trussed)?;
+        let public_key = try_syscall!(trussed.derive_key(
+            Mechanism::Ed255,
+            state.key,
+            None,
+            StorageAttributes::new().set_persistence(Location::Volatile)
+        ))
+        .map_err(|_| Error::Otp)?
+        .key;
+        let serialized = try_syscall!(trussed.serialize_key(
+            Mechanism::Ed255,
+            public_key,
+            KeySerialization::Raw
+        ));
+        syscall!(trussed.delete(public_key));
+        let serialized = serialized.map_err(|_| Error::Otp)?.serialized_key;
+        let public_key: [u8; PUBLIC_KEY_LEN] =
+            serialized.as_slice().try_into().map_err(|_| Error::Otp)?;
+        base64url(&public_key)
+    }
+
+    /// Loads the state or generates a new key if it does not exist yet.
+    ///
+    /// After loading the state, the counter continues after the last reserved value.
+    fn state<T: CryptoClient + FilesystemClient>(
+        &mut self,
+        trussed: &mut T,
+    ) -> Result<State, Error> {
+        if let Some(state) = self.state {
+            return Ok(state);
+        }
+        let state = match try_syscall!(trussed.read_file(Location::Internal, STATE_FILE.into())) {
+            Ok(reply) => cbor_smol::cbor_deserialize(&reply.data).map_err(|_| Error::Otp)?,
+            Err(_) => {
+                let key = try_syscall!(trussed.generate_key(
+                    Mechanism::Ed255,
+                    StorageAttributes::new().set_persistence(Location::Internal)
+                ))
+                .map_err(|_| Error::Otp)?
+                .key;
+                let state = State { key, counter: 0 };
+                save(trussed, &state)?;
+                state
+            }
+        };
+        self.state = Some(state);
+        self.counter = state.counter;
+        Ok(state)
+    }
+}
+
+fn save<T: FilesystemClient>(trussed: &mut T, state: &State) -> Result<(), Error> {
+    let mut buffer = [0; 64];
+    let data = cbor_smol::cbor_serialize(state, &mut buffer).map_err(|_| Error::Otp)?;
+    let data = Message::from_slice(data).map_err(|_| Error::Otp)?;
+    try_syscall!(trussed.write_file(Location::Internal, STATE_FILE.into(), data, None))
+        .map_err(|_| Error::Otp)?;
+    Ok(())
+}
+
+const fn base64url_len(n: usize) -> usize {
+    (n * 4).div_ceil(3)
+}
+
+/// Encodes the data without padding.
+fn base64url<const N: usize>(data: &[u8]) -> Result<String<N>, Error> {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let mut bytes = [0; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..=chunk.len() {
+            let index = (bits >> (18 - 6 * i)) & 0x3f;
+            encoded
+                .push(char::from(BASE64URL_ALPHABET[index as usize]))
+                .map_err(|_| Error::TooLarge)?;
+        }
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64url() {
+        let encode = |data: &[u8]| base64url::<8>(data).unwrap();
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg");
+        assert_eq!(encode(b"fo"), "Zm8");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foob"), "Zm9vYg");
+        assert_eq!(encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(OTP_LEN, 91);
+        assert_eq!(OTP_KEY_LEN, 43);
+    }
+}
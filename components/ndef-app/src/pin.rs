@@ -0,0 +1,92 @@
+//! Optional PIN protection of writes to the NDEF file.
+//!
+//! The PIN is managed by the auth backend.  It can only be set or changed over the contact
+//! interface, i.e., USB, so that it cannot be set by someone who only has NFC access.  If the
+//! PIN is required but not set, the NDEF file is not writable.  After a successful VERIFY, the
+//! NDEF file is writable until the application is deselected.
+//!
+//! - VERIFY: `00 20 00 80 <PIN>`, without data it returns the number of remaining retries
+//! - CHANGE REFERENCE DATA: `00 24 01 80 <new PIN>`, requires a successful VERIFY if a PIN is
+//!   already set
+
+use apdu_app::{CommandView, Interface};
+use iso7816::Status;
+use trussed_auth::{PinData, MAX_PIN_LENGTH};
+use trussed_core::{syscall, try_syscall};
+
+use crate::ndef::{App, Client};
+
+pub const INS_VERIFY: u8 = 0x20;
+pub const INS_CHANGE_REFERENCE_DATA: u8 = 0x24;
+
+const P1_VERIFY: u8 = 0x00;
+const P1_NEW_PIN_ONLY: u8 = 0x01;
+const P2_PIN_REFERENCE: u8 = 0x80;
+
+const PIN_ID: u8 = 0;
+const PIN_RETRIES: u8 = 8;
+const MIN_PIN_LENGTH: usize = 4;
+
+impl<T: Client> App<T> {
+    pub(crate) fn verify(&mut self, apdu: CommandView<'_>) -> apdu_app::Result {
+        if apdu.p1 != P1_VERIFY || apdu.p2 != P2_PIN_REFERENCE {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        if !syscall!(self.trussed.has_pin(PIN_ID)).has_pin {
+            return Err(Status::KeyReferenceNotFound);
+        }
+        let pin = apdu.data();
+        if pin.is_empty() {
+            return if self.verified {
+                Ok(())
+            } else {
+                Err(self.remaining_retries())
+            };
+        }
+
+        self.verified = false;
+        let pin = PinData::from_slice(pin).map_err(|_| Status::WrongLength)?;
+        let success = try_syscall!(self.trussed.check_pin(PIN_ID, pin))
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?
+            .success;
+        self.verified = success;
+        self.update_write_access();
+        if success {
+            Ok(())
+        } else {
+            Err(self.remaining_retries())
+        }
+    }
+
+    pub(crate) fn change_pin(
+        &mut self,
+        interface: Interface,
+        apdu: CommandView<'_>,
+    ) -> apdu_app::Result {
+        if !matches!(interface, Interface::Contact) {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        if apdu.p1 != P1_NEW_PIN_ONLY || apdu.p2 != P2_PIN_REFERENCE {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        if syscall!(self.trussed.has_pin(PIN_ID)).has_pin && !self.verified {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        let pin = apdu.data();
+        if !(MIN_PIN_LENGTH..=MAX_PIN_LENGTH).contains(&pin.len()) {
+            return Err(Status::WrongLength);
+        }
+        let pin = PinData::from_slice(pin).map_err(|_| Status::WrongLength)?;
+        try_syscall!(self.trussed.set_pin(PIN_ID, pin, Some(PIN_RETRIES), false))
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        Ok(())
+    }
+
+    fn remaining_retries(&mut self) -> Status {
+        match syscall!(self.trussed.pin_retries(PIN_ID)).retries {
+            Some(0) => Status::OperationBlocked,
+            Some(retries) => Status::RemainingRetries(retries),
+            None => Status::UnspecifiedPersistentExecutionError,
+        }
+    }
+}
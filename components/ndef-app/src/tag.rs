@@ -0,0 +1,472 @@
+//! File access of the NFC Forum Type 4 Tag, mapping version 2.0.
+//!
+//! The tag has two files, the capability container (CC) and the NDEF file.  The reader has to
+//! select a file by its identifier before it can read or update it.  The CC declares whether the
+//! NDEF file is currently writable or locked, i.e., read-only, and UPDATE BINARY is only accepted
+//! if the CC grants write access.
+
+use core::convert::TryFrom;
+use heapless::{Vec, VecView};
+use iso7816::{command::CommandView, Instruction, Status};
+
+use crate::message::NDEF_FILE_SIZE;
+
+const FILE_ID_CC: [u8; 2] = [0xe1, 0x03];
+const FILE_ID_NDEF: [u8; 2] = [0xe1, 0x04];
+
+// access conditions of the NDEF file control TLV
+const ACCESS_GRANTED: u8 = 0x00;
+const ACCESS_DENIED: u8 = 0xff;
+
+const INS_UPDATE_BINARY: u8 = 0xd6;
+
+// bit 8 of P1 selects a file by its short identifier, which is not supported
+const P1_SHORT_FILE_ID: u8 = 0x80;
+
+const SELECT_P1_FILE_ID: u8 = 0x00;
+const SELECT_P2_FIRST: u8 = 0x00;
+const SELECT_P2_NO_RESPONSE: u8 = 0x0c;
+
+// size of the length NLEN at the start of the NDEF file
+const NLEN_LEN: usize = 2;
+
+// the maximum size and the access conditions are set by `capability_container`
+const CAPABILITY_CONTAINER: [u8; 15] = [
+    0x00, 0x0f, /* CCEN_HI, CCEN_LOW */
+    0x20, /* VERSION */
+    0x00, 0x7f, /* MLe_HI, MLe_LOW */
+    0x00, 0x7f, /* MLc_HI, MLc_LOW */
+    /* NDEF file control TLV: file ID, maximum size, read and write access */
+    0x04, 0x06, 0xe1, 0x04, 0x00, 0x00, 0x00, 0x00,
+];
+
+const _: () = assert!(NDEF_FILE_SIZE <= 0x7fff);
+
+/// Returns the CC for the NDEF file that is stored in a buffer of size `NDEF_FILE_SIZE`.
+pub fn capability_container(writable: bool) -> [u8; 15] {
+    let mut cc = CAPABILITY_CONTAINER;
+    cc[11..13].copy_from_slice(&(NDEF_FILE_SIZE as u16).to_be_bytes());
+    cc[13] = ACCESS_GRANTED;
+    cc[14] = if writable {
+        ACCESS_GRANTED
+    } else {
+        ACCESS_DENIED
+    };
+    cc
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum File {
+    CapabilityContainer,
+    Ndef,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The file has been selected.
+    Selected(File),
+    /// The NDEF file has been updated.  `complete` is set if the length of the NDEF message has
+    /// been written and the message is complete.
+    Updated { complete: bool },
+}
+
+#[derive(Debug)]
+pub struct Tag {
+    selected: Option<File>,
+    capability_container: [u8; 15],
+}
+
+impl Tag {
+    /// Clears the file selection, e.g., if the NDEF application is selected.
+    pub fn reset(&mut self) {
+        self.selected = None;
+    }
+
+    /// Grants or denies write access to the NDEF file.
+    pub fn set_writable(&mut self, writable: bool) {
+        self.capability_container = capability_container(writable);
+    }
+
+    /// Handles a command, `ndef` being the content of the NDEF file.
+    pub fn call(
+        &mut self,
+        apdu: CommandView<'_>,
+        ndef: &mut Vec<u8, NDEF_FILE_SIZE>,
+        reply: &mut VecView<u8>,
+    ) -> Result<Option<Event>, Status> {
+        match apdu.instruction() {
+            Instruction::Select => self.select(apdu).map(|file| Some(Event::Selected(file))),
+            Instruction::ReadBinary => self.read_binary(apdu, ndef, reply).map(|_| None),
+            _ if u8::from(apdu.instruction()) == INS_UPDATE_BINARY => self
+                .update_binary(apdu, ndef)
+                .map(|complete| Some(Event::Updated { complete })),
+            _ => Err(Status::InstructionNotSupportedOrInvalid),
+        }
+    }
+
+    fn read_access(&self, file: File) -> u8 {
+        match file {
+            File::CapabilityContainer => ACCESS_GRANTED,
+            File::Ndef => self.capability_container[13],
+        }
+    }
+
+    fn write_access(&self, file: File) -> u8 {
+        match file {
+            File::CapabilityContainer => ACCESS_DENIED,
+            File::Ndef => self.capability_container[14],
+        }
+    }
+
+    fn select(&mut self, apdu: CommandView<'_>) -> Result<File, Status> {
+        if apdu.p1 != SELECT_P1_FILE_ID
+            || (apdu.p2 != SELECT_P2_NO_RESPONSE && apdu.p2 != SELECT_P2_FIRST)
+        {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        let file_id = <[u8; 2]>::try_from(apdu.data()).map_err(|_| Status::WrongLength)?;
+        // the selection is unchanged if the file does not exist
+        let file = match file_id {
+            FILE_ID_CC => File::CapabilityContainer,
+            FILE_ID_NDEF => File::Ndef,
+            _ => return Err(Status::NotFound),
+        };
+        self.selected = Some(file);
+        Ok(file)
+    }
+
+    fn read_binary(
+        &self,
+        apdu: CommandView<'_>,
+        ndef: &[u8],
+        reply: &mut VecView<u8>,
+    ) -> Result<(), Status> {
+        let file = self.selected.ok_or(Status::ConditionsOfUseNotSatisfied)?;
+        if self.read_access(file) != ACCESS_GRANTED {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        let content = match file {
+            File::CapabilityContainer => &self.capability_container,
+            File::Ndef => ndef,
+        };
+        let offset = offset(apdu)?;
+        let remaining = content
+            .get(offset..)
+            .ok_or(Status::IncorrectP1OrP2Parameter)?;
+        let len = match apdu.expected() {
+            0 => remaining.len(),
+            expected => expected.min(remaining.len()),
+        };
+        reply
+            .extend_from_slice(&remaining[..len])
+            .map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// Returns true if the NDEF message is complete after the update.
+    fn update_binary(
+        &self,
+        apdu: CommandView<'_>,
+        ndef: &mut Vec<u8, NDEF_FILE_SIZE>,
+    ) -> Result<bool, Status> {
+        let file = self.selected.ok_or(Status::ConditionsOfUseNotSatisfied)?;
+        if self.write_access(file) != ACCESS_GRANTED {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        let offset = offset(apdu)?;
+        let data = apdu.data();
+        if data.is_empty() {
+            return Err(Status::WrongLength);
+        }
+        // the written data must be contiguous
+        if offset > ndef.len() {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        let end = offset + data.len();
+        if end > ndef.len() {
+            ndef.resize(end, 0).map_err(|_| Status::NotEnoughMemory)?;
+        }
+        ndef[offset..end].copy_from_slice(data);
+
+        // The reader first sets the length to zero, then writes the message and finally writes
+        // the actual length.
+        if offset >= NLEN_LEN || ndef.len() < NLEN_LEN {
+            return Ok(false);
+        }
+        let nlen = usize::from(u16::from_be_bytes([ndef[0], ndef[1]]));
+        if nlen == 0 || NLEN_LEN + nlen > ndef.len() {
+            return Ok(false);
+        }
+        ndef.truncate(NLEN_LEN + nlen);
+        Ok(true)
+    }
+}
+
+fn offset(apdu: CommandView<'_>) -> Result<usize, Status> {
+    if apdu.p1 & P1_SHORT_FILE_ID != 0 {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    Ok(usize::from(u16::from_be_bytes([apdu.p1, apdu.p2])))
+}
+
+impl Default for Tag {
+    fn default() -> Self {
+        Self {
+            selected: None,
+            capability_container: capability_container(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+
+    const SELECT_CC: &[u8] = &[0x00, 0xa4, 0x00, 0x0c, 0x02, 0xe1, 0x03];
+    const SELECT_NDEF: &[u8] = &[0x00, 0xa4, 0x00, 0x0c, 0x02, 0xe1, 0x04];
+    const READ_CC: &[u8] = &[0x00, 0xb0, 0x00, 0x00, 0x0f];
+    const OK: &[u8] = &[0x90, 0x00];
+
+    fn nitrokey() -> Vec<u8, NDEF_FILE_SIZE> {
+        Vec::from_slice(Message::nitrokey().as_bytes()).unwrap()
+    }
+
+    // Replays the commands and compares the responses including the status words.
+    //
+    // The command sequences follow the NDEF detection, read and update procedures of the T4T
+    // specification.  They are written for these tests and are not the official NFC Forum test
+    // vectors.
+    //
+    // TODO: replay the test cases of the NFC Forum T4T test specification.  They are only
+    // available to NFC Forum members and have not been added yet.
+    fn replay(tag: &mut Tag, ndef: &mut Vec<u8, NDEF_FILE_SIZE>, vectors: &[(&[u8], &[u8])]) {
+        for (i, (command, expected)) in vectors.iter().enumerate() {
+            let command = CommandView::try_from(*command).unwrap();
+            let mut reply = Vec::<u8, 258>::new();
+            let status = match tag.call(command, ndef, reply.as_mut_view()) {
+                Ok(_) => Status::Success,
+                Err(status) => {
+                    assert!(reply.is_empty(), "vector {}", i);
+                    status
+                }
+            };
+            reply
+                .extend_from_slice(&u16::from(status).to_be_bytes())
+                .unwrap();
+            assert_eq!(&reply, expected, "vector {}", i);
+        }
+    }
+
+    fn response(data: &[u8]) -> Vec<u8, 258> {
+        let mut response = Vec::from_slice(data).unwrap();
+        response.extend_from_slice(OK).unwrap();
+        response
+    }
+
+    fn call(tag: &mut Tag, ndef: &mut Vec<u8, NDEF_FILE_SIZE>, command: &[u8]) -> Option<Event> {
+        let command = CommandView::try_from(command).unwrap();
+        let mut reply = Vec::<u8, 258>::new();
+        tag.call(command, ndef, reply.as_mut_view()).unwrap()
+    }
+
+    #[test]
+    fn capability_container() {
+        for writable in [false, true] {
+            let cc = super::capability_container(writable);
+            assert_eq!(usize::from(u16::from_be_bytes([cc[0], cc[1]])), cc.len());
+            assert_eq!(cc[7..9], [0x04, 0x06]);
+            assert_eq!(cc[9..11], FILE_ID_NDEF);
+            assert_eq!(
+                usize::from(u16::from_be_bytes([cc[11], cc[12]])),
+                NDEF_FILE_SIZE
+            );
+            assert_eq!(cc[13], ACCESS_GRANTED);
+            let write_access = if writable {
+                ACCESS_GRANTED
+            } else {
+                ACCESS_DENIED
+            };
+            assert_eq!(cc[14], write_access);
+        }
+    }
+
+    #[test]
+    fn ndef_detection_and_read() {
+        let mut ndef = nitrokey();
+        let file = ndef.clone();
+        let cc = super::capability_container(false);
+        let mut tag = Tag::default();
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                (SELECT_CC, OK),
+                (READ_CC, &response(&cc)),
+                (SELECT_NDEF, OK),
+                (&[0x00, 0xb0, 0x00, 0x00, 0x02], &response(&file[..2])),
+                (&[0x00, 0xb0, 0x00, 0x02, 0x12], &response(&file[2..])),
+                // chunked read
+                (&[0x00, 0xb0, 0x00, 0x02, 0x08], &response(&file[2..10])),
+                (&[0x00, 0xb0, 0x00, 0x0a, 0x08], &response(&file[10..18])),
+                (&[0x00, 0xb0, 0x00, 0x12, 0x08], &response(&file[18..])),
+                // Le exceeds the file
+                (&[0x00, 0xb0, 0x00, 0x00, 0xff], &response(&file)),
+                (&[0x00, 0xb0, 0x00, 0x14, 0x01], OK),
+            ],
+        );
+    }
+
+    #[test]
+    fn select_errors() {
+        let mut ndef = nitrokey();
+        let file = ndef.clone();
+        let cc = super::capability_container(false);
+        let mut tag = Tag::default();
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                // no file selected
+                (&[0x00, 0xb0, 0x00, 0x00, 0x02], &[0x69, 0x85]),
+                (SELECT_NDEF, OK),
+                // unknown file, the selection is unchanged
+                (&[0x00, 0xa4, 0x00, 0x0c, 0x02, 0xe1, 0x05], &[0x6a, 0x82]),
+                (&[0x00, 0xb0, 0x00, 0x00, 0x02], &response(&file[..2])),
+                // selection by name
+                (&[0x00, 0xa4, 0x04, 0x00, 0x02, 0xe1, 0x03], &[0x6a, 0x86]),
+                (&[0x00, 0xa4, 0x00, 0x0c, 0x01, 0xe1], &[0x67, 0x00]),
+                (
+                    &[0x00, 0xa4, 0x00, 0x0c, 0x03, 0xe1, 0x03, 0x00],
+                    &[0x67, 0x00],
+                ),
+                // the first version of the mapping uses P2 = 0x00
+                (&[0x00, 0xa4, 0x00, 0x00, 0x02, 0xe1, 0x03, 0x00], OK),
+                (READ_CC, &response(&cc)),
+            ],
+        );
+    }
+
+    #[test]
+    fn read_errors() {
+        let mut ndef = nitrokey();
+        let mut tag = Tag::default();
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                (SELECT_NDEF, OK),
+                // offset beyond the file
+                (&[0x00, 0xb0, 0x00, 0x15, 0x01], &[0x6a, 0x86]),
+                (&[0x00, 0xb0, 0x7f, 0xff, 0x01], &[0x6a, 0x86]),
+                // short file identifier
+                (&[0x00, 0xb0, 0x81, 0x00, 0x01], &[0x6a, 0x86]),
+                (SELECT_CC, OK),
+                (&[0x00, 0xb0, 0x00, 0x10, 0x01], &[0x6a, 0x86]),
+            ],
+        );
+    }
+
+    #[test]
+    fn write_locked() {
+        let mut ndef = nitrokey();
+        let mut tag = Tag::default();
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                (&[0x00, 0xd6, 0x00, 0x00, 0x02, 0x00, 0x00], &[0x69, 0x85]),
+                (SELECT_NDEF, OK),
+                (&[0x00, 0xd6, 0x00, 0x00, 0x02, 0x00, 0x00], &[0x69, 0x82]),
+                (SELECT_CC, OK),
+                (&[0x00, 0xd6, 0x00, 0x0e, 0x01, 0x00], &[0x69, 0x82]),
+                // other instructions
+                (&[0x00, 0xca, 0x00, 0x00, 0x00], &[0x6d, 0x00]),
+            ],
+        );
+        assert_eq!(ndef, nitrokey());
+    }
+
+    #[test]
+    fn write_procedure() {
+        let mut ndef = nitrokey();
+        let mut tag = Tag::default();
+        tag.set_writable(true);
+        let cc = super::capability_container(true);
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                (SELECT_CC, OK),
+                (READ_CC, &response(&cc)),
+                (SELECT_CC, OK),
+                // the CC is never writable
+                (&[0x00, 0xd6, 0x00, 0x0e, 0x01, 0x00], &[0x69, 0x82]),
+            ],
+        );
+
+        // URI record "urn:nk" with NLEN = 0, written in two chunks
+        let message = [
+            0x00, 0x00, 0xd1, 0x01, 0x07, 0x55, 0x00, b'u', b'r', b'n', b':', b'n', b'k',
+        ];
+        assert_eq!(
+            call(&mut tag, &mut ndef, SELECT_NDEF),
+            Some(Event::Selected(File::Ndef))
+        );
+        let mut command = Vec::<u8, 32>::from_slice(&[0x00, 0xd6, 0x00, 0x00, 0x07]).unwrap();
+        command.extend_from_slice(&message[..7]).unwrap();
+        assert_eq!(
+            call(&mut tag, &mut ndef, &command),
+            Some(Event::Updated { complete: false })
+        );
+        let mut command = Vec::<u8, 32>::from_slice(&[0x00, 0xd6, 0x00, 0x07, 0x06]).unwrap();
+        command.extend_from_slice(&message[7..]).unwrap();
+        assert_eq!(
+            call(&mut tag, &mut ndef, &command),
+            Some(Event::Updated { complete: false })
+        );
+        // the old message is longer
+        assert_eq!(ndef.len(), 0x14);
+        assert_eq!(
+            call(
+                &mut tag,
+                &mut ndef,
+                &[0x00, 0xd6, 0x00, 0x00, 0x02, 0x00, 0x0b]
+            ),
+            Some(Event::Updated { complete: true })
+        );
+        assert_eq!(ndef[..2], [0x00, 0x0b]);
+        assert_eq!(ndef[2..], message[2..]);
+    }
+
+    #[test]
+    fn write_errors() {
+        let mut ndef = nitrokey();
+        let mut tag = Tag::default();
+        tag.set_writable(true);
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[
+                (SELECT_NDEF, OK),
+                // no data
+                (&[0x00, 0xd6, 0x00, 0x00], &[0x67, 0x00]),
+                // gap after the end of the file
+                (&[0x00, 0xd6, 0x00, 0x15, 0x01, 0x00], &[0x6a, 0x86]),
+                // short file identifier
+                (&[0x00, 0xd6, 0x81, 0x00, 0x01, 0x00], &[0x6a, 0x86]),
+                // the length is not consistent
+                (&[0x00, 0xd6, 0x00, 0x00, 0x02, 0x00, 0x13], OK),
+            ],
+        );
+        assert_eq!(ndef.len(), 0x14);
+
+        // exceeds NDEF_FILE_SIZE
+        ndef.resize(NDEF_FILE_SIZE - 1, 0).unwrap();
+        replay(
+            &mut tag,
+            &mut ndef,
+            &[(&[0x00, 0xd6, 0x00, 0xff, 0x02, 0x00, 0x00], &[0x6a, 0x84])],
+        );
+        assert_eq!(ndef.len(), NDEF_FILE_SIZE - 1);
+    }
+}
@@ -0,0 +1,85 @@
+//! Storage of the template that the NDEF message is rendered from, see `message`.
+//!
+//! The template can only be set over the contact interface, i.e., USB, so that a reader cannot
+//! redirect the tag.  If the PIN is required, it has to be verified first, see `pin`.  The
+//! template is stored as the URI, followed by a newline and the texts, one per line.  Without a
+//! stored template, the default message is served.
+//!
+//! - PUT DATA: `00 DA 00 01 <template>`, without data it removes the stored template
+
+use apdu_app::{CommandView, Interface};
+use iso7816::Status;
+use littlefs2_core::{path, Path};
+use trussed_core::{
+    try_syscall,
+    types::{Location, Message as Data},
+};
+
+use crate::message::Template;
+use crate::ndef::{App, Client};
+
+pub const INS_PUT_DATA: u8 = 0xda;
+
+const P1_P2_TEMPLATE: [u8; 2] = [0x00, 0x01];
+
+const TEMPLATE_FILE: &Path = path!("template");
+
+impl<T: Client> App<T> {
+    /// Reads the stored template, falling back to the default message if it is missing or
+    /// invalid.
+    pub(crate) fn load_template(&mut self) -> Template {
+        let stored = try_syscall!(self
+            .trussed
+            .read_file(Location::Internal, TEMPLATE_FILE.into()));
+        stored
+            .ok()
+            .and_then(|reply| {
+                let template = core::str::from_utf8(&reply.data).ok()?;
+                let template = Template::parse(template).ok()?;
+                template.check(self.options.uuid).ok()?;
+                Some(template)
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn put_template(
+        &mut self,
+        interface: Interface,
+        apdu: CommandView<'_>,
+    ) -> apdu_app::Result {
+        if !matches!(interface, Interface::Contact) {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        if [apdu.p1, apdu.p2] != P1_P2_TEMPLATE {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        // the same PIN protection as for writing the NDEF file
+        if self.options.writable && self.options.pin_required && !self.verified {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+
+        let data = apdu.data();
+        if data.is_empty() {
+            try_syscall!(self
+                .trussed
+                .remove_file(Location::Internal, TEMPLATE_FILE.into()))
+            .ok();
+        } else {
+            let template =
+                core::str::from_utf8(data).map_err(|_| Status::IncorrectDataParameter)?;
+            Template::parse(template)
+                .and_then(|template| template.check(self.options.uuid))
+                .map_err(|_| Status::WrongLength)?;
+            let data = Data::from_slice(data).map_err(|_| Status::WrongLength)?;
+            try_syscall!(self.trussed.write_file(
+                Location::Internal,
+                TEMPLATE_FILE.into(),
+                data,
+                None
+            ))
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        }
+        self.template = None;
+        Ok(())
+    }
+}
@@ -38,7 +38,7 @@ type Nad = Option<u8>;
 type Cid = Option<u8>;
 
 #[allow(clippy::enum_variant_names)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Block {
     IBlock(BlockNum, Nad, Cid, Chaining, Offset),
     RBlock(BlockNum, Cid, Ack, Offset),
@@ -46,8 +46,9 @@ enum Block {
 }
 
 impl Block {
-    fn new(frame: &[u8]) -> Block {
-        let header = frame[0];
+    /// Parses the prologue of the frame, returns `None` if it is incomplete.
+    fn new(frame: &[u8]) -> Option<Block> {
+        let header = *frame.first()?;
 
         let block_num = (header & 1) != 0;
         let flag = (header & 0x10) != 0;
@@ -56,20 +57,16 @@ impl Block {
         // CID included
         let cid = if (header & 0x08) != 0 {
             offset += 1;
-            Some(frame[1])
+            Some(*frame.get(1)?)
         } else {
             None
         };
 
-        if (header & 0xc2) == 0x02 {
+        let block = if (header & 0xc2) == 0x02 {
             // NAD included
             let nad = if (header & 0x4) != 0 {
                 offset += 1;
-                if cid.is_some() {
-                    Some(frame[2])
-                } else {
-                    Some(frame[1])
-                }
+                Some(*frame.get(offset - 1)?)
             } else {
                 None
             };
@@ -79,10 +76,31 @@ impl Block {
             Block::RBlock(block_num, cid, !flag, offset)
         } else {
             Block::SBlock(cid, (0x30 & header) == 0x30)
+        };
+        Some(block)
+    }
+
+    fn cid(&self) -> Cid {
+        match *self {
+            Block::IBlock(_, _, cid, _, _)
+            | Block::RBlock(_, cid, _, _)
+            | Block::SBlock(cid, _) => cid,
         }
     }
 }
 
+/// Parses a frame received from the reader, used by the fuzz targets in `fuzz`.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub fn fuzz_block(frame: &[u8]) {
+    match Block::new(frame) {
+        Some(Block::IBlock(_, _, _, _, offset)) | Some(Block::RBlock(_, _, _, offset)) => {
+            assert!(offset <= frame.len());
+        }
+        _ => {}
+    }
+}
+
 /// Iso14443 device follows related rules for PICC in iso14443-4.
 /// Rules C - E and rules 9 - 13.
 pub struct Iso14443<DEV: nfc::Device> {
@@ -149,7 +167,12 @@ where
     // RBlock(BlockNum, Cid, Ack, ),
     // SBlock(Cid, WtxGranted, ),
     fn handle_block(&mut self, packet: &[u8]) -> Result<(), SourceError> {
-        let block_header = Block::new(packet);
+        let Some(block_header) = Block::new(packet) else {
+            info!("Incomplete block header");
+            return Err(SourceError::NoActivity);
+        };
+        // The PICC has to answer with the CID used by the PCD.
+        self.cid = block_header.cid();
         match block_header {
             Block::IBlock(_block_num, _nad, _cid, chaining, offset) => {
                 if self.state != Iso14443State::Receiving {
@@ -181,10 +204,10 @@ where
                     match self.state.clone() {
                         Iso14443State::Transmitting(last_frame_range, _remaining_data_range) => {
                             info!("Retransmission requested..");
-                            self.send_frame(
-                                &Vec::from_slice(&self.buffer[last_frame_range]).unwrap(),
-                            )
-                            .ok();
+                            // the block number has not changed since the last frame
+                            let (frame, _) =
+                                self.construct_iblock(&self.buffer[last_frame_range.start..]);
+                            self.send_frame(&frame).ok();
                         }
                         _ => {
                             info!("No recent transmissions! NAK");
@@ -255,7 +278,14 @@ where
                     self.wtx_requested = false;
                 } else {
                     info!("Deselected.");
-                    self.device.send(&[0xc2]).ok();
+                    match self.cid {
+                        Some(cid) => {
+                            self.device.send(&[0xca, cid]).ok();
+                        }
+                        _ => {
+                            self.device.send(&[0xc2]).ok();
+                        }
+                    }
                     self.reset_state();
                 }
                 Err(SourceError::NoActivity)
@@ -328,8 +358,6 @@ where
             }
         };
 
-        assert!(packet_len > 0);
-
         // let packet = &self.packet;
         self.handle_block(&packet[..packet_len as usize])?;
 
@@ -390,10 +418,11 @@ where
                 self.send_frame(&frame).ok();
                 if data_used != msg.len() {
                     info!("chaining response!");
-                    self.buffer = msg;
-                    self.state =
-                        Iso14443State::Transmitting(0..data_used, data_used..self.buffer.len());
                 }
+                // keep the response for retransmissions
+                self.buffer = msg;
+                self.state =
+                    Iso14443State::Transmitting(0..data_used, data_used..self.buffer.len());
                 // } else {
                 // info!("session was dropped! dropping response.");
                 // }
@@ -448,3 +477,388 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use apdu_dispatch::interchanges::{Channel, Data, Responder};
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc, vec::Vec as StdVec};
+
+    #[derive(Default)]
+    struct Script {
+        // frames sent by the reader, and if they start a new session
+        incoming: VecDeque<(bool, StdVec<u8>)>,
+        // frames sent by the device
+        sent: StdVec<StdVec<u8>>,
+    }
+
+    /// Replays the frames sent by the reader and records the replies.
+    #[derive(Clone)]
+    struct MockDevice {
+        script: Rc<RefCell<Script>>,
+        frame_size: usize,
+    }
+
+    impl MockDevice {
+        fn push(&self, new_session: bool, frame: &[u8]) {
+            let mut script = self.script.borrow_mut();
+            script.incoming.push_back((new_session, frame.into()));
+        }
+
+        fn take_sent(&self) -> StdVec<StdVec<u8>> {
+            core::mem::take(&mut self.script.borrow_mut().sent)
+        }
+    }
+
+    impl nfc::Device for MockDevice {
+        fn read(&mut self, buf: &mut [u8]) -> Result<nfc::State, nfc::Error> {
+            let Some((new_session, frame)) = self.script.borrow_mut().incoming.pop_front() else {
+                return Err(nfc::Error::NoActivity);
+            };
+            buf[..frame.len()].copy_from_slice(&frame);
+            let len = frame.len() as u8;
+            if new_session {
+                Ok(nfc::State::NewSession(len))
+            } else {
+                Ok(nfc::State::Continue(len))
+            }
+        }
+
+        fn send(&mut self, buf: &[u8]) -> Result<(), nfc::Error> {
+            assert!(buf.len() + 2 <= self.frame_size);
+            self.script.borrow_mut().sent.push(buf.into());
+            Ok(())
+        }
+
+        fn frame_size(&self) -> usize {
+            self.frame_size
+        }
+    }
+
+    struct Harness {
+        iso: Iso14443<MockDevice>,
+        responder: Responder<'static>,
+        device: MockDevice,
+    }
+
+    impl Harness {
+        fn new(channel: &'static Channel, frame_size: usize) -> Self {
+            let (requester, responder) = channel.split().unwrap();
+            let device = MockDevice {
+                script: Default::default(),
+                frame_size,
+            };
+            Self {
+                iso: Iso14443::new(device.clone(), requester),
+                responder,
+                device,
+            }
+        }
+
+        /// Sends a frame from the reader and polls the device.
+        fn receive(&mut self, frame: &[u8]) -> Iso14443Status {
+            self.device.push(false, frame);
+            self.iso.poll()
+        }
+
+        /// Checks the forwarded APDU and sends the response to the reader.
+        fn respond(&mut self, request: &[u8], response: &[u8]) {
+            assert_eq!(self.responder.take_request().unwrap().as_slice(), request);
+            self.responder
+                .respond(Data::from_slice(response).unwrap())
+                .unwrap();
+            assert!(matches!(self.iso.poll(), Iso14443Status::Idle));
+        }
+
+        /// Runs a command that fits into a single frame.
+        fn exchange(&mut self, frame: &[u8], request: &[u8], response: &[u8]) {
+            assert!(matches!(
+                self.receive(frame),
+                Iso14443Status::ReceivedData(_)
+            ));
+            self.respond(request, response);
+        }
+
+        fn sent(&self) -> StdVec<StdVec<u8>> {
+            self.device.take_sent()
+        }
+    }
+
+    #[test]
+    fn block_parsing() {
+        assert_eq!(Block::new(&[]), None);
+        // missing CID
+        assert_eq!(Block::new(&[0x0a]), None);
+        assert_eq!(Block::new(&[0xaa]), None);
+        assert_eq!(Block::new(&[0xca]), None);
+        // missing NAD
+        assert_eq!(Block::new(&[0x06]), None);
+        assert_eq!(Block::new(&[0x0e, 0x05]), None);
+
+        assert_eq!(
+            Block::new(&[0x02]),
+            Some(Block::IBlock(false, None, None, false, 1))
+        );
+        assert_eq!(
+            Block::new(&[0x13, 0xaa]),
+            Some(Block::IBlock(true, None, None, true, 1))
+        );
+        assert_eq!(
+            Block::new(&[0x0e, 0x05, 0x01, 0xaa]),
+            Some(Block::IBlock(false, Some(0x01), Some(0x05), false, 3))
+        );
+        assert_eq!(
+            Block::new(&[0xa3]),
+            Some(Block::RBlock(true, None, true, 1))
+        );
+        assert_eq!(
+            Block::new(&[0xba, 0x05]),
+            Some(Block::RBlock(false, Some(0x05), false, 2))
+        );
+        assert_eq!(Block::new(&[0xf2, 0x01]), Some(Block::SBlock(None, true)));
+        assert_eq!(
+            Block::new(&[0xca, 0x05]),
+            Some(Block::SBlock(Some(0x05), false))
+        );
+    }
+
+    #[test]
+    fn incomplete_frames() {
+        static CHANNEL: Channel = Channel::new();
+        let mut harness = Harness::new(&CHANNEL, 32);
+
+        let frames: [&[u8]; 4] = [&[], &[0x0a], &[0x0e, 0x05], &[0xaa]];
+        for frame in frames {
+            assert!(matches!(harness.receive(frame), Iso14443Status::Idle));
+        }
+        assert!(harness.sent().is_empty());
+
+        harness.exchange(&[0x02, 0xaa], &[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+    }
+
+    #[test]
+    fn block_numbers() {
+        static CHANNEL: Channel = Channel::new();
+        let mut harness = Harness::new(&CHANNEL, 32);
+
+        harness.exchange(&[0x02, 0xaa], &[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+        harness.exchange(&[0x03, 0xbb], &[0xbb], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+        harness.exchange(&[0x02, 0xcc], &[0xcc], &[0x6a, 0x82]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x6a, 0x82]]);
+
+        // the block number is reset to 1 on DESELECT
+        assert!(matches!(harness.receive(&[0xc2]), Iso14443Status::Idle));
+        assert_eq!(harness.sent(), [vec![0xc2]]);
+        harness.exchange(&[0x02, 0xaa], &[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+
+        // and for a new session
+        harness.device.push(true, &[0x02, 0xbb]);
+        assert!(matches!(
+            harness.iso.poll(),
+            Iso14443Status::ReceivedData(_)
+        ));
+        harness.respond(&[0xbb], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+    }
+
+    #[test]
+    fn command_chaining() {
+        static CHANNEL: Channel = Channel::new();
+        let mut harness = Harness::new(&CHANNEL, 32);
+
+        assert!(matches!(
+            harness.receive(&[0x12, 0x00, 0x01, 0x02]),
+            Iso14443Status::Idle
+        ));
+        assert_eq!(harness.sent(), [vec![0xa2]]);
+        assert!(matches!(
+            harness.receive(&[0x13, 0x03, 0x04]),
+            Iso14443Status::Idle
+        ));
+        assert_eq!(harness.sent(), [vec![0xa3]]);
+        harness.exchange(
+            &[0x02, 0x05],
+            &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            &[0x90, 0x00],
+        );
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+
+        // a new command discards the previous response
+        harness.exchange(&[0x03, 0xaa], &[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+    }
+
+    #[test]
+    fn response_chaining() {
+        static CHANNEL: Channel = Channel::new();
+        // five bytes per frame
+        let mut harness = Harness::new(&CHANNEL, 8);
+        let response: StdVec<u8> = (0..12).collect();
+
+        harness.exchange(&[0x02, 0xaa], &[0xaa], &response);
+        assert_eq!(harness.sent(), [vec![0x12, 0x00, 0x01, 0x02, 0x03, 0x04]]);
+        assert!(matches!(harness.receive(&[0xa3]), Iso14443Status::Idle));
+        assert_eq!(harness.sent(), [vec![0x13, 0x05, 0x06, 0x07, 0x08, 0x09]]);
+        assert!(matches!(harness.receive(&[0xa2]), Iso14443Status::Idle));
+        assert_eq!(harness.sent(), [vec![0x02, 0x0a, 0x0b]]);
+
+        harness.exchange(&[0x03, 0xbb], &[0xbb], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+    }
+
+    #[test]
+    fn retransmission() {
+        static CHANNEL: Channel = Channel::new();
+        let mut harness = Harness::new(&CHANNEL, 8);
+        let response: StdVec<u8> = (0..12).collect();
+
+        // R(NAK) with the current block number
+        harness.exchange(&[0x02, 0xaa], &[0xaa], &response);
+        let first: &[u8] = &[0x12, 0x00, 0x01, 0x02, 0x03, 0x04];
+        assert_eq!(harness.sent(), [first]);
+        harness.receive(&[0xb2]);
+        assert_eq!(harness.sent(), [first]);
+
+        // R(ACK) with the current block number
+        harness.receive(&[0xa3]);
+        let second: &[u8] = &[0x13, 0x05, 0x06, 0x07, 0x08, 0x09];
+        assert_eq!(harness.sent(), [second]);
+        harness.receive(&[0xa3]);
+        assert_eq!(harness.sent(), [second]);
+
+        harness.receive(&[0xa2]);
+        let last: &[u8] = &[0x02, 0x0a, 0x0b];
+        assert_eq!(harness.sent(), [last]);
+        harness.receive(&[0xb2]);
+        assert_eq!(harness.sent(), [last]);
+
+        // R(NAK) with another block number, e.g. a presence check
+        harness.receive(&[0xb3]);
+        assert_eq!(harness.sent(), [vec![0xa2]]);
+
+        // responses without chaining
+        harness.exchange(&[0x03, 0xbb], &[0xbb], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+        harness.receive(&[0xb3]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+        harness.receive(&[0xb2]);
+        assert_eq!(harness.sent(), [vec![0xa3]]);
+    }
+
+    #[test]
+    fn waiting_time_extension() {
+        static CHANNEL: Channel = Channel::new();
+        let mut harness = Harness::new(&CHANNEL, 32);
+
+        assert!(matches!(
+            harness.receive(&[0x02, 0xaa]),
+            Iso14443Status::ReceivedData(_)
+        ));
+        assert!(matches!(
+            harness.iso.poll_wait_extensions(),
+            Iso14443Status::ReceivedData(_)
+        ));
+        assert_eq!(harness.sent(), [vec![0xf2, 0x01]]);
+        // no second request before the reply
+        assert!(matches!(
+            harness.iso.poll_wait_extensions(),
+            Iso14443Status::ReceivedData(_)
+        ));
+        assert!(harness.sent().is_empty());
+
+        // the response is sent after the reply
+        harness.device.push(false, &[0xf2, 0x01]);
+        harness.respond(&[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+        assert!(matches!(
+            harness.iso.poll_wait_extensions(),
+            Iso14443Status::Idle
+        ));
+
+        // the reply is received while the request is processed
+        assert!(matches!(
+            harness.receive(&[0x03, 0xbb]),
+            Iso14443Status::ReceivedData(_)
+        ));
+        harness.iso.poll_wait_extensions();
+        assert_eq!(harness.sent(), [vec![0xf2, 0x01]]);
+        assert!(matches!(
+            harness.receive(&[0xf2, 0x01]),
+            Iso14443Status::Idle
+        ));
+        harness.iso.poll_wait_extensions();
+        assert_eq!(harness.sent(), [vec![0xf2, 0x01]]);
+        harness.device.push(false, &[0xf2, 0x01]);
+        harness.respond(&[0xbb], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+
+        // without a reply, the response is dropped
+        assert!(matches!(
+            harness.receive(&[0x02, 0xcc]),
+            Iso14443Status::ReceivedData(_)
+        ));
+        harness.iso.poll_wait_extensions();
+        assert_eq!(harness.sent(), [vec![0xf2, 0x01]]);
+        harness.respond(&[0xcc], &[0x90, 0x00]);
+        assert!(harness.sent().is_empty());
+
+        harness.exchange(&[0x03, 0xdd], &[0xdd], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x03, 0x90, 0x00]]);
+    }
+
+    #[test]
+    fn cid() {
+        static CHANNEL: Channel = Channel::new();
+        // four bytes per frame with CID
+        let mut harness = Harness::new(&CHANNEL, 8);
+
+        harness.exchange(&[0x0a, 0x05, 0xaa], &[0xaa], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x0a, 0x05, 0x90, 0x00]]);
+
+        // command chaining
+        harness.receive(&[0x1b, 0x05, 0x00, 0x01]);
+        assert_eq!(harness.sent(), [vec![0xab, 0x05]]);
+        harness.exchange(&[0x0a, 0x05, 0x02], &[0x00, 0x01, 0x02], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x0a, 0x05, 0x90, 0x00]]);
+
+        // response chaining and retransmission
+        let response: StdVec<u8> = (0..6).collect();
+        harness.exchange(&[0x0b, 0x05, 0xbb], &[0xbb], &response);
+        assert_eq!(harness.sent(), [vec![0x1b, 0x05, 0x00, 0x01, 0x02, 0x03]]);
+        harness.receive(&[0xaa, 0x05]);
+        assert_eq!(harness.sent(), [vec![0x0a, 0x05, 0x04, 0x05]]);
+        harness.receive(&[0xba, 0x05]);
+        assert_eq!(harness.sent(), [vec![0x0a, 0x05, 0x04, 0x05]]);
+        harness.receive(&[0xbb, 0x05]);
+        assert_eq!(harness.sent(), [vec![0xaa, 0x05]]);
+
+        // waiting time extension
+        assert!(matches!(
+            harness.receive(&[0x0b, 0x05, 0xcc]),
+            Iso14443Status::ReceivedData(_)
+        ));
+        harness.iso.poll_wait_extensions();
+        assert_eq!(harness.sent(), [vec![0xfa, 0x05, 0x01]]);
+        harness.device.push(false, &[0xfa, 0x05, 0x01]);
+        harness.respond(&[0xcc], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x0b, 0x05, 0x90, 0x00]]);
+
+        // NAD
+        harness.exchange(&[0x0e, 0x05, 0x01, 0xdd], &[0xdd], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x0a, 0x05, 0x90, 0x00]]);
+
+        assert!(matches!(
+            harness.receive(&[0xca, 0x05]),
+            Iso14443Status::Idle
+        ));
+        assert_eq!(harness.sent(), [vec![0xca, 0x05]]);
+
+        harness.exchange(&[0x02, 0xee], &[0xee], &[0x90, 0x00]);
+        assert_eq!(harness.sent(), [vec![0x02, 0x90, 0x00]]);
+    }
+}
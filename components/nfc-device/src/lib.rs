@@ -9,3 +9,7 @@ pub mod types;
 
 pub mod iso14443;
 pub use iso14443::*;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
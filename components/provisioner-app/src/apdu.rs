@@ -4,17 +4,28 @@ use core::convert::{TryFrom, TryInto};
 use heapless::VecView;
 use iso7816::{Aid, Instruction, Status};
 use trussed::store::Store;
-use trussed_core::CryptoClient;
+use trussed_core::{CryptoClient, UiClient};
 
 const SOLO_PROVISIONER_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
 
-impl TryFrom<Instruction> for super::Instruction {
+// ISO 7816-4: P1-P2 is the offset of WRITE BINARY unless this bit selects a short EF identifier
+const P1_SHORT_FILE_ID: u8 = 0x80;
+
+impl TryFrom<CommandView<'_>> for super::Instruction {
     type Error = Error;
 
-    fn try_from(instruction: Instruction) -> core::result::Result<Self, Self::Error> {
-        match instruction {
+    fn try_from(apdu: CommandView<'_>) -> core::result::Result<Self, Self::Error> {
+        match apdu.instruction() {
             Instruction::Select => Ok(Self::Select),
-            Instruction::WriteBinary => Ok(Self::WriteBinary),
+            Instruction::WriteBinary => {
+                if apdu.p1 & P1_SHORT_FILE_ID != 0 {
+                    return Err(Error::IncorrectDataParameter);
+                }
+                let offset = u16::from_be_bytes([apdu.p1, apdu.p2]);
+                Ok(Self::WriteBinary {
+                    offset: Some(offset),
+                })
+            }
             Instruction::Unknown(instruction) => instruction.try_into(),
             _ => Err(Error::FunctionNotSupported),
         }
@@ -28,6 +39,9 @@ impl From<Error> for Status {
             Error::IncorrectDataParameter => Status::IncorrectDataParameter,
             Error::NotEnoughMemory => Status::NotEnoughMemory,
             Error::NotFound => Status::NotFound,
+            Error::InvalidCertificate => Status::VerificationFailed,
+            Error::Locked => Status::ConditionsOfUseNotSatisfied,
+            Error::AccessDenied => Status::SecurityStatusNotSatisfied,
         }
     }
 }
@@ -35,7 +49,7 @@ impl From<Error> for Status {
 impl<S, T> iso7816::App for Provisioner<S, T>
 where
     S: Store,
-    T: CryptoClient,
+    T: CryptoClient + UiClient,
 {
     fn aid(&self) -> Aid {
         Aid::new(SOLO_PROVISIONER_AID)
@@ -45,7 +59,7 @@ where
 impl<S, T> App for Provisioner<S, T>
 where
     S: Store,
-    T: CryptoClient,
+    T: CryptoClient + UiClient,
 {
     fn select(
         &mut self,
@@ -53,8 +67,7 @@ where
         _apdu: CommandView<'_>,
         reply: &mut VecView<u8>,
     ) -> Result {
-        self.buffer_file_contents.clear();
-        self.buffer_filename.clear();
+        self.clear_upload();
         // For manufacture speed, return uuid on select
         reply.extend_from_slice(&self.uuid).unwrap();
         Ok(())
@@ -68,8 +81,7 @@ where
         apdu: CommandView<'_>,
         reply: &mut VecView<u8>,
     ) -> Result {
-        apdu.instruction()
-            .try_into()
+        super::Instruction::try_from(apdu)
             .and_then(|instruction| self.handle(instruction, apdu.data(), reply))
             .map_err(From::from)
     }
@@ -0,0 +1,173 @@
+//! Minimal DER encoding of PKCS#10 certificate signing requests (RFC 2986) for the attestation
+//! keys, so that the CA gets a proof of possession of the private key.
+
+use heapless::{Vec, VecView};
+
+use crate::{
+    der::{
+        OID_COMMON_NAME, OID_ECDSA_WITH_SHA256, OID_EC_PUBLIC_KEY, OID_ED25519, OID_PRIME256V1,
+        TAG_ATTRIBUTES, TAG_BIT_STRING, TAG_INTEGER, TAG_OID, TAG_SEQUENCE, TAG_SET,
+        TAG_UTF8_STRING,
+    },
+    Error, Uuid,
+};
+
+const SUBJECT_PREFIX: &[u8] = b"Nitrokey ";
+
+pub type Der = Vec<u8, 512>;
+
+#[derive(Copy, Clone)]
+pub enum PublicKey<'a> {
+    /// untagged `x || y`
+    P256(&'a [u8; 64]),
+    Ed255(&'a [u8; 32]),
+}
+
+/// Encodes the `CertificationRequestInfo`, i.e., the data signed by the key.
+///
+/// The subject is a single common name containing the device UUID.
+pub fn request_info(uuid: &Uuid, public_key: PublicKey<'_>) -> Result<Der, Error> {
+    let mut common_name = Der::new();
+    common_name
+        .extend_from_slice(SUBJECT_PREFIX)
+        .map_err(|_| Error::NotEnoughMemory)?;
+    for byte in uuid {
+        for nibble in [byte >> 4, byte & 0xf] {
+            common_name
+                .push(b"0123456789ABCDEF"[nibble as usize])
+                .map_err(|_| Error::NotEnoughMemory)?;
+        }
+    }
+
+    let mut attribute = Der::new();
+    tlv(&mut attribute, TAG_OID, OID_COMMON_NAME)?;
+    tlv(&mut attribute, TAG_UTF8_STRING, &common_name)?;
+    let mut rdn = Der::new();
+    tlv(&mut rdn, TAG_SEQUENCE, &attribute)?;
+    let mut name = Der::new();
+    tlv(&mut name, TAG_SET, &rdn)?;
+
+    let mut info = Der::new();
+    tlv(&mut info, TAG_INTEGER, &[0])?;
+    tlv(&mut info, TAG_SEQUENCE, &name)?;
+    subject_public_key_info(&mut info, public_key)?;
+    tlv(&mut info, TAG_ATTRIBUTES, &[])?;
+
+    let mut request_info = Der::new();
+    tlv(&mut request_info, TAG_SEQUENCE, &info)?;
+    Ok(request_info)
+}
+
+/// Encodes the `CertificationRequest` for the encoded `request_info` and its signature.
+///
+/// A P-256 `signature` is the untagged `r || s`, an Ed25519 signature is used as is.
+pub fn request(
+    request_info: &[u8],
+    public_key: PublicKey<'_>,
+    signature: &[u8],
+    reply: &mut VecView<u8>,
+) -> Result<(), Error> {
+    let mut request = Der::new();
+    request
+        .extend_from_slice(request_info)
+        .map_err(|_| Error::NotEnoughMemory)?;
+
+    let mut bit_string = Der::new();
+    bit_string.push(0).map_err(|_| Error::NotEnoughMemory)?;
+    match public_key {
+        PublicKey::P256(_) => {
+            if signature.len() != 64 {
+                return Err(Error::IncorrectDataParameter);
+            }
+            algorithm_identifier(&mut request, OID_ECDSA_WITH_SHA256, None)?;
+            let mut integers = Der::new();
+            unsigned_integer(&mut integers, &signature[..32])?;
+            unsigned_integer(&mut integers, &signature[32..])?;
+            tlv(&mut bit_string, TAG_SEQUENCE, &integers)?;
+        }
+        PublicKey::Ed255(_) => {
+            algorithm_identifier(&mut request, OID_ED25519, None)?;
+            bit_string
+                .extend_from_slice(signature)
+                .map_err(|_| Error::NotEnoughMemory)?;
+        }
+    }
+    tlv(&mut request, TAG_BIT_STRING, &bit_string)?;
+
+    tlv(reply, TAG_SEQUENCE, &request)
+}
+
+fn subject_public_key_info(out: &mut VecView<u8>, public_key: PublicKey<'_>) -> Result<(), Error> {
+    let mut info = Der::new();
+    // no unused bits
+    let mut bit_string = Der::new();
+    bit_string.push(0).map_err(|_| Error::NotEnoughMemory)?;
+    match public_key {
+        PublicKey::P256(public_key) => {
+            algorithm_identifier(&mut info, OID_EC_PUBLIC_KEY, Some(OID_PRIME256V1))?;
+            // uncompressed point
+            bit_string.push(0x04).map_err(|_| Error::NotEnoughMemory)?;
+            bit_string
+                .extend_from_slice(public_key)
+                .map_err(|_| Error::NotEnoughMemory)?;
+        }
+        PublicKey::Ed255(public_key) => {
+            algorithm_identifier(&mut info, OID_ED25519, None)?;
+            bit_string
+                .extend_from_slice(public_key)
+                .map_err(|_| Error::NotEnoughMemory)?;
+        }
+    }
+    tlv(&mut info, TAG_BIT_STRING, &bit_string)?;
+    tlv(out, TAG_SEQUENCE, &info)
+}
+
+fn algorithm_identifier(
+    out: &mut VecView<u8>,
+    algorithm: &[u8],
+    parameters: Option<&[u8]>,
+) -> Result<(), Error> {
+    let mut identifier = Der::new();
+    tlv(&mut identifier, TAG_OID, algorithm)?;
+    if let Some(parameters) = parameters {
+        tlv(&mut identifier, TAG_OID, parameters)?;
+    }
+    tlv(out, TAG_SEQUENCE, &identifier)
+}
+
+fn unsigned_integer(out: &mut VecView<u8>, bytes: &[u8]) -> Result<(), Error> {
+    // minimal encoding, but at least one byte and never negative
+    let start = bytes
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(bytes.len() - 1);
+    let bytes = &bytes[start..];
+    let mut integer = Der::new();
+    if bytes[0] & 0x80 != 0 {
+        integer.push(0).map_err(|_| Error::NotEnoughMemory)?;
+    }
+    integer
+        .extend_from_slice(bytes)
+        .map_err(|_| Error::NotEnoughMemory)?;
+    tlv(out, TAG_INTEGER, &integer)
+}
+
+fn tlv(out: &mut VecView<u8>, tag: u8, content: &[u8]) -> Result<(), Error> {
+    let len = content.len();
+    let mut header = Vec::<u8, 4>::new();
+    header.push(tag).unwrap();
+    if len < 0x80 {
+        header.push(len as u8).unwrap();
+    } else if len <= 0xff {
+        header.extend_from_slice(&[0x81, len as u8]).unwrap();
+    } else if len <= 0xffff {
+        header
+            .extend_from_slice(&[0x82, (len >> 8) as u8, len as u8])
+            .unwrap();
+    } else {
+        return Err(Error::NotEnoughMemory);
+    }
+    out.extend_from_slice(&header)
+        .and_then(|_| out.extend_from_slice(content))
+        .map_err(|_| Error::NotEnoughMemory)
+}
@@ -3,14 +3,14 @@ use core::convert::TryFrom;
 use ctaphid_app::{App, Command, Error, VendorCommand};
 use heapless_bytes::BytesView;
 use trussed::store::Store;
-use trussed_core::CryptoClient;
+use trussed_core::{CryptoClient, UiClient};
 
 const COMMAND_PROVISIONER: VendorCommand = VendorCommand::H71;
 
 impl<S, T> App<'_> for Provisioner<S, T>
 where
     S: Store,
-    T: CryptoClient,
+    T: CryptoClient + UiClient,
 {
     fn commands(&self) -> &'static [Command] {
         &[Command::Vendor(COMMAND_PROVISIONER)]
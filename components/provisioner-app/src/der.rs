@@ -0,0 +1,101 @@
+//! The subset of DER needed for attestation certificates and certificate signing requests.
+
+use crate::Error;
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0c;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+pub const TAG_ATTRIBUTES: u8 = 0xa0;
+pub const TAG_VERSION: u8 = 0xa0;
+
+// 2.5.4.3
+pub const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+// 1.2.840.10045.2.1
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+// 1.2.840.10045.3.1.7
+pub const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+// 1.3.132.0.34
+pub const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+// 1.3.132.0.10
+pub const OID_SECP256K1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x0a];
+// 1.2.840.10045.4.3.2
+pub const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+// 1.3.101.110
+pub const OID_X25519: &[u8] = &[0x2b, 0x65, 0x6e];
+// 1.3.101.112
+pub const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// Reads consecutive TLVs, every malformed encoding is an `InvalidCertificate`.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Returns the content and the complete encoding of the next TLV.
+    pub fn read(&mut self, tag: u8) -> Result<(&'a [u8], &'a [u8]), Error> {
+        let (&actual, rest) = self.data.split_first().ok_or(Error::InvalidCertificate)?;
+        let (&first, rest) = rest.split_first().ok_or(Error::InvalidCertificate)?;
+        let (len, rest) = match first {
+            0..=0x7f => (first.into(), rest),
+            0x81 => {
+                let (&len, rest) = rest.split_first().ok_or(Error::InvalidCertificate)?;
+                (len.into(), rest)
+            }
+            0x82 => {
+                if rest.len() < 2 {
+                    return Err(Error::InvalidCertificate);
+                }
+                (u16::from_be_bytes([rest[0], rest[1]]).into(), &rest[2..])
+            }
+            _ => return Err(Error::InvalidCertificate),
+        };
+        if actual != tag || rest.len() < len {
+            return Err(Error::InvalidCertificate);
+        }
+        let header = self.data.len() - rest.len();
+        let encoding = &self.data[..header + len];
+        self.data = &rest[len..];
+        Ok((&encoding[header..], encoding))
+    }
+
+    /// Returns the content of the next TLV.
+    pub fn content(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        self.read(tag).map(|(content, _)| content)
+    }
+
+    /// Returns the content of a bit string without unused bits.
+    pub fn bit_string(&mut self) -> Result<&'a [u8], Error> {
+        match self.content(TAG_BIT_STRING)?.split_first() {
+            Some((0, bits)) => Ok(bits),
+            _ => Err(Error::InvalidCertificate),
+        }
+    }
+
+    /// Returns a reader for the content of the next TLV.
+    pub fn nested(&mut self, tag: u8) -> Result<Reader<'a>, Error> {
+        self.content(tag).map(Reader::new)
+    }
+
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidCertificate)
+        }
+    }
+}
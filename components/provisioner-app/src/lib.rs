@@ -3,28 +3,61 @@
 //! This is a highly *non-portable* Trussed app.
 //!
 //! It allows injecting arbitrary binary files at arbitrary paths, e.g., to inject FIDO batch
-//! attestation keys.
+//! attestation keys.  Files are uploaded in chunks, see the `upload` module.
 //! It allows generating Trussed device attestation keys and obtaining their public keys,
 //! to then generate and inject attn certs from a given root or intermedidate CA.
+//! Alternatively, it returns a certificate signing request for a newly generated key, containing
+//! the device UUID and signed with the new key.
+//! Attn certs are only saved if they are DER X.509 certificates for the respective key, and, once
+//! the T1 intermediate public key is saved, signed by it.
+//!
+//! Files can also be staged and then committed to their destination at once, see the `staging`
+//! module.
+//!
+//! The status report tells a station which provisioning steps are already done, see the
+//! `status` module.
+//!
+//! Factory stations can test the hardware with the self test instructions, see the `self_test`
+//! module.
+//!
+//! Once the provisioning is finalized, all instructions modifying the device are refused.
+//! Injected files can be listed, read back, hashed and deleted again, so that a provisioning
+//! station can check its own writes.
 //!
 //! See `solo2-cli` for usage.
 #![no_std]
 
 mod apdu;
+mod csr;
 mod ctaphid;
+mod der;
+mod self_test;
+mod staging;
+mod status;
+mod upload;
+mod x509;
 
 #[macro_use]
 extern crate delog;
 generate_macros!();
 
+pub use self_test::{HardwareStatus, SelfTestOptions, TestResult};
+pub use status::DeviceInfo;
+
 use core::convert::{TryFrom, TryInto};
 use heapless::{Vec, VecView};
-use littlefs2_core::{path, Path, PathBuf};
+use littlefs2_core::{path, DynFile, DynFilesystem, Path, PathBuf, Read as _, Seek as _, SeekFrom};
+use p384::elliptic_curve::{
+    sec1::{EncodedPoint, FromEncodedPoint, ModulusSize, ToEncodedPoint},
+    AffinePoint, CurveArithmetic, FieldBytes, FieldBytesSize, SecretKey,
+};
+use rand_core::{CryptoRng, RngCore};
+use sha2::{Digest as _, Sha256};
 use trussed::{
     key::{Flags, Key, Kind as KeyKind},
     store::{self, Store},
 };
-use trussed_core::{syscall, types::Location, CryptoClient};
+use trussed_core::{syscall, types::Location, CryptoClient, UiClient};
 
 const TESTER_FILENAME_ID: [u8; 2] = [0xe1, 0x01];
 const TESTER_FILE_ID: [u8; 2] = [0xe1, 0x02];
@@ -32,22 +65,73 @@ const TESTER_FILE_ID: [u8; 2] = [0xe1, 0x02];
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instruction {
     Select,
-    WriteBinary,
+    /// `offset` is P1-P2 of the APDU, without it (CTAPHID) the data is appended.
+    WriteBinary {
+        offset: Option<u16>,
+    },
+    WriteChunk,
 
     WriteFile,
 
     BootToBootrom,
     GetUuid,
+    GetStatus,
 
     GenerateP256Key,
     GenerateEd255Key,
     GenerateX255Key,
+    GenerateP384Key,
+    GenerateSecp256k1Key,
+
+    GenerateP256Csr,
+    GenerateEd255Csr,
 
     SaveP256AttestationCertificate,
     SaveEd255AttestationCertificate,
     SaveX255AttestationCertificate,
+    SaveP384AttestationCertificate,
+    SaveSecp256k1AttestationCertificate,
 
     SaveT1IntermediatePublicKey,
+
+    ListDirectory,
+    ReadFile,
+    HashFile,
+    DeleteFile,
+
+    FinalizeProvisioning,
+
+    StageFile,
+    WriteManifest,
+    CommitStaged,
+    DiscardStaged,
+
+    TestLed,
+    TestButton,
+    TestInitStatus,
+    TestExtFlash,
+    TestNfc,
+}
+
+impl Instruction {
+    /// Whether the instruction is still available once the provisioning is finalized.
+    ///
+    /// `ReadFile` is not, a finalized device does not return the content of any file.
+    fn is_read_only(self) -> bool {
+        matches!(
+            self,
+            Self::Select
+                | Self::GetUuid
+                | Self::GetStatus
+                | Self::ListDirectory
+                | Self::HashFile
+                | Self::TestLed
+                | Self::TestButton
+                | Self::TestInitStatus
+                | Self::TestExtFlash
+                | Self::TestNfc
+        )
+    }
 }
 
 impl TryFrom<u8> for Instruction {
@@ -56,23 +140,51 @@ impl TryFrom<u8> for Instruction {
     fn try_from(ins: u8) -> Result<Self, Self::Error> {
         Ok(match ins {
             0xa4 => Self::Select,
-            0xd0 => Self::WriteBinary,
+            // CTAPHID has no P1-P2, thus `WriteBinary` appends to the selected buffer
+            0xd0 => Self::WriteBinary { offset: None },
+            0xd1 => Self::WriteChunk,
 
             0xbf => Self::WriteFile,
 
             0x51 => Self::BootToBootrom,
             0x62 => Self::GetUuid,
+            0x63 => Self::GetStatus,
 
             0xbc => Self::GenerateP256Key,
             0xbb => Self::GenerateEd255Key,
             0xb7 => Self::GenerateX255Key,
+            0xb4 => Self::GenerateP384Key,
+            0xb3 => Self::GenerateSecp256k1Key,
+
+            0xac => Self::GenerateP256Csr,
+            0xab => Self::GenerateEd255Csr,
 
             0xba => Self::SaveP256AttestationCertificate,
             0xb9 => Self::SaveEd255AttestationCertificate,
             0xb6 => Self::SaveX255AttestationCertificate,
+            0xb2 => Self::SaveP384AttestationCertificate,
+            0xb1 => Self::SaveSecp256k1AttestationCertificate,
 
             0xb5 => Self::SaveT1IntermediatePublicKey,
 
+            0xa0 => Self::ListDirectory,
+            0xa1 => Self::ReadFile,
+            0xa2 => Self::HashFile,
+            0xa3 => Self::DeleteFile,
+
+            0xa8 => Self::FinalizeProvisioning,
+
+            0xa5 => Self::StageFile,
+            0xa6 => Self::WriteManifest,
+            0xa7 => Self::CommitStaged,
+            0xa9 => Self::DiscardStaged,
+
+            0x52 => Self::TestLed,
+            0x53 => Self::TestButton,
+            0x54 => Self::TestInitStatus,
+            0x55 => Self::TestExtFlash,
+            0x56 => Self::TestNfc,
+
             _ => return Err(Error::FunctionNotSupported),
         })
     }
@@ -83,19 +195,105 @@ pub enum Error {
     IncorrectDataParameter,
     NotEnoughMemory,
     NotFound,
+    InvalidCertificate,
+    Locked,
+    AccessDenied,
+}
+
+impl From<littlefs2_core::Error> for Error {
+    fn from(error: littlefs2_core::Error) -> Self {
+        match error {
+            littlefs2_core::Error::NO_SUCH_ENTRY => Self::NotFound,
+            littlefs2_core::Error::NO_SPACE => Self::NotEnoughMemory,
+            _ => Self::IncorrectDataParameter,
+        }
+    }
 }
 
 type Uuid = [u8; 16];
 
+/// Marks the provisioning as finalized, see `Instruction::FinalizeProvisioning`.
+///
+/// The marker is kept on factory resets, except for test devices.
+pub const LOCK_FILE: &Path = path!("/attn/lock");
+
 const FILENAME_T1_PUBLIC: &Path = path!("/attn/pub/00");
 
 const FILENAME_P256_SECRET: &Path = path!("/attn/sec/01");
 const FILENAME_ED255_SECRET: &Path = path!("/attn/sec/02");
 const FILENAME_X255_SECRET: &Path = path!("/attn/sec/03");
+const FILENAME_P384_SECRET: &Path = path!("/attn/sec/04");
+const FILENAME_SECP256K1_SECRET: &Path = path!("/attn/sec/05");
 
 const FILENAME_P256_CERT: &Path = path!("/attn/x5c/01");
 const FILENAME_ED255_CERT: &Path = path!("/attn/x5c/02");
 const FILENAME_X255_CERT: &Path = path!("/attn/x5c/03");
+const FILENAME_P384_CERT: &Path = path!("/attn/x5c/04");
+const FILENAME_SECP256K1_CERT: &Path = path!("/attn/x5c/05");
+
+#[derive(Copy, Clone, Debug)]
+enum AttestationKey {
+    P256,
+    Ed255,
+    X255,
+    P384,
+    Secp256k1,
+}
+
+impl AttestationKey {
+    const ALL: [Self; 5] = [
+        Self::P256,
+        Self::Ed255,
+        Self::X255,
+        Self::P384,
+        Self::Secp256k1,
+    ];
+
+    /// number of the key in the `/attn/sec` and `/attn/x5c` filenames
+    fn slot(self) -> u8 {
+        match self {
+            Self::P256 => 1,
+            Self::Ed255 => 2,
+            Self::X255 => 3,
+            Self::P384 => 4,
+            Self::Secp256k1 => 5,
+        }
+    }
+
+    fn secret(self) -> &'static Path {
+        match self {
+            Self::P256 => FILENAME_P256_SECRET,
+            Self::Ed255 => FILENAME_ED255_SECRET,
+            Self::X255 => FILENAME_X255_SECRET,
+            Self::P384 => FILENAME_P384_SECRET,
+            Self::Secp256k1 => FILENAME_SECP256K1_SECRET,
+        }
+    }
+
+    fn certificate(self) -> &'static Path {
+        match self {
+            Self::P256 => FILENAME_P256_CERT,
+            Self::Ed255 => FILENAME_ED255_CERT,
+            Self::X255 => FILENAME_X255_CERT,
+            Self::P384 => FILENAME_P384_CERT,
+            Self::Secp256k1 => FILENAME_SECP256K1_CERT,
+        }
+    }
+
+    /// algorithm and named curve of the `SubjectPublicKeyInfo`
+    fn algorithm(self) -> (&'static [u8], Option<&'static [u8]>) {
+        match self {
+            Self::P256 => (der::OID_EC_PUBLIC_KEY, Some(der::OID_PRIME256V1)),
+            Self::Ed255 => (der::OID_ED25519, None),
+            Self::X255 => (der::OID_X25519, None),
+            Self::P384 => (der::OID_EC_PUBLIC_KEY, Some(der::OID_SECP384R1)),
+            Self::Secp256k1 => (der::OID_EC_PUBLIC_KEY, Some(der::OID_SECP256K1)),
+        }
+    }
+}
+
+// maximum number of file bytes returned by a single `ReadFile`
+const READ_CHUNK_SIZE: usize = 1024;
 
 enum SelectedBuffer {
     Filename,
@@ -105,34 +303,61 @@ enum SelectedBuffer {
 pub struct Provisioner<S, T>
 where
     S: Store,
-    T: CryptoClient,
+    T: CryptoClient + UiClient,
 {
     trussed: T,
 
     selected_buffer: SelectedBuffer,
     buffer_filename: Vec<u8, 128>,
     buffer_file_contents: Vec<u8, 8192>,
+    // number of bytes of the file contents already streamed to flash, see `upload`
+    flushed: usize,
+    manifest: Vec<u8, { staging::MANIFEST_SIZE }>,
 
     store: S,
     uuid: Uuid,
     rebooter: fn() -> !,
+    locked: bool,
+    device: DeviceInfo,
+    self_test: SelfTestOptions,
 }
 
 impl<S, T> Provisioner<S, T>
 where
     S: Store,
-    T: CryptoClient,
+    T: CryptoClient + UiClient,
 {
-    pub fn new(trussed: T, store: S, uuid: Uuid, rebooter: fn() -> !) -> Provisioner<S, T> {
-        Self {
+    pub fn new(
+        trussed: T,
+        store: S,
+        uuid: Uuid,
+        rebooter: fn() -> !,
+        device: DeviceInfo,
+        self_test: SelfTestOptions,
+    ) -> Provisioner<S, T> {
+        let locked = store.ifs().exists(LOCK_FILE);
+        let mut provisioner = Self {
             trussed,
             selected_buffer: SelectedBuffer::Filename,
             buffer_filename: Vec::new(),
             buffer_file_contents: Vec::new(),
+            flushed: 0,
+            manifest: Vec::new(),
             store,
             uuid,
             rebooter,
-        }
+            locked,
+            device,
+            self_test,
+        };
+        provisioner.recover_staged();
+        provisioner
+    }
+
+    /// Whether the provisioning has been finalized, i.e., only read-only instructions are
+    /// available.
+    pub fn is_locked(&self) -> bool {
+        self.locked
     }
 
     fn handle(
@@ -141,86 +366,51 @@ where
         data: &[u8],
         reply: &mut VecView<u8>,
     ) -> Result<(), Error> {
+        if self.locked && !instruction.is_read_only() {
+            info!("provisioning finalized, refusing {:?}", instruction);
+            return Err(Error::Locked);
+        }
+
         match instruction {
             Instruction::Select => self.select(data),
-            Instruction::WriteBinary => {
-                match self.selected_buffer {
-                    SelectedBuffer::Filename => self.buffer_filename.extend_from_slice(data),
-                    SelectedBuffer::File => self.buffer_file_contents.extend_from_slice(data),
+            Instruction::WriteBinary { offset } => {
+                let offset = offset.map_or_else(|| self.upload_len(), usize::from);
+                self.write_chunk(offset, data)
+            }
+            Instruction::WriteChunk => {
+                // <offset: u32><chunk>
+                if data.len() < 4 {
+                    return Err(Error::IncorrectDataParameter);
                 }
-                .unwrap();
-                Ok(())
+                let offset = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                self.write_chunk(offset as usize, &data[4..])
             }
             Instruction::WriteFile => {
-                if self.buffer_file_contents.is_empty() || self.buffer_filename.is_empty() {
+                if self.file_len() == 0 || self.buffer_filename.is_empty() {
                     Err(Error::IncorrectDataParameter)
                 } else if let Ok(buffer_path) = PathBuf::try_from(self.buffer_filename.as_slice()) {
-                    // self.buffer_filename.push(0);
-                    let _filename =
-                        unsafe { core::str::from_utf8_unchecked(self.buffer_filename.as_slice()) };
                     info!(
                         "writing file {} {} bytes",
-                        _filename,
-                        self.buffer_file_contents.len()
+                        buffer_path.as_str(),
+                        self.file_len()
                     );
                     // logging::dump_hex(&self.buffer_file_contents, self.buffer_file_contents.len());
 
-                    let res = store::store(
-                        &self.store,
-                        Location::Internal,
-                        &buffer_path,
-                        &self.buffer_file_contents,
-                    );
-                    self.buffer_file_contents.clear();
-                    self.buffer_filename.clear();
+                    let res = self.store_upload(&buffer_path);
+                    self.clear_upload();
                     if res.is_err() {
                         info!("failed writing file!");
-                        Err(Error::NotEnoughMemory)
                     } else {
                         info!("wrote file");
-                        Ok(())
                     }
+                    res
                 } else {
                     Err(Error::IncorrectDataParameter)
                 }
             }
             Instruction::GenerateP256Key => {
-                use p256_cortex_m4::{Keypair, SecretKey};
                 info!("GenerateP256Key");
-                let mut seed = [0u8; 32];
-
-                // Generate a keypair with rejection sampling.
-                // This should use the proper `random` method but is not possible without a `CryptoRng` implementation, which trussed is not
-                let keypair = loop {
-                    seed.copy_from_slice(syscall!(self.trussed.random_bytes(32)).bytes.as_slice());
-                    match SecretKey::from_bytes(seed) {
-                        Ok(secret) => {
-                            break Keypair {
-                                public: secret.public_key(),
-                                secret,
-                            }
-                        }
-                        Err(_) => continue,
-                    }
-                };
-
-                let serialized_key = Key {
-                    flags: Flags::LOCAL | Flags::SENSITIVE,
-                    kind: KeyKind::P256,
-                    material: seed.as_slice().try_into().unwrap(),
-                };
-
-                let serialized_bytes = serialized_key.serialize();
-
-                store::store(
-                    &self.store,
-                    Location::Internal,
-                    FILENAME_P256_SECRET,
-                    &serialized_bytes,
-                )
-                .map_err(|_| Error::NotEnoughMemory)?;
-                info!("stored to {}", FILENAME_P256_SECRET.as_str());
-
+                let keypair = self.generate_p256_key()?;
                 reply
                     .extend_from_slice(&keypair.public.to_untagged_bytes())
                     .unwrap();
@@ -228,33 +418,34 @@ where
             }
             Instruction::GenerateEd255Key => {
                 info!("GenerateEd255Key");
-                let mut seed = [0u8; 32];
-                seed.copy_from_slice(syscall!(self.trussed.random_bytes(32)).bytes.as_slice());
-
-                let serialized_key = Key {
-                    flags: Flags::LOCAL | Flags::SENSITIVE,
-                    kind: KeyKind::Ed255,
-                    material: seed.as_slice().try_into().unwrap(),
-                };
-
-                // let serialized_key = Key::try_deserialize(&seed[..])
-                // .map_err(|_| Error::WrongLength)?;
-
-                let serialized_bytes = serialized_key.serialize();
-
-                store::store(
-                    &self.store,
-                    Location::Internal,
-                    FILENAME_ED255_SECRET,
-                    &serialized_bytes,
-                )
-                .map_err(|_| Error::NotEnoughMemory)?;
-
-                let keypair = salty::Keypair::from(&seed);
-
+                let keypair = self.generate_ed255_key()?;
                 reply.extend_from_slice(keypair.public.as_bytes()).unwrap();
                 Ok(())
             }
+            Instruction::GenerateP256Csr => {
+                info!("GenerateP256Csr");
+                let keypair = self.generate_p256_key()?;
+                let public_key = keypair.public.to_untagged_bytes();
+                let public_key = csr::PublicKey::P256(&public_key);
+                let request_info = csr::request_info(&self.uuid, public_key)?;
+                let signature = keypair
+                    .secret
+                    .sign(&request_info, TrussedRng(&mut self.trussed));
+                csr::request(
+                    &request_info,
+                    public_key,
+                    &signature.to_untagged_bytes(),
+                    reply,
+                )
+            }
+            Instruction::GenerateEd255Csr => {
+                info!("GenerateEd255Csr");
+                let keypair = self.generate_ed255_key()?;
+                let public_key = csr::PublicKey::Ed255(keypair.public.as_bytes());
+                let request_info = csr::request_info(&self.uuid, public_key)?;
+                let signature = keypair.sign(&request_info);
+                csr::request(&request_info, public_key, &signature.to_bytes(), reply)
+            }
             Instruction::GenerateX255Key => {
                 info_now!("GenerateX255Key");
                 let mut seed = [0u8; 32];
@@ -285,38 +476,42 @@ where
                 reply.extend_from_slice(&public_key.to_bytes()).unwrap();
                 Ok(())
             }
+            Instruction::GenerateP384Key => {
+                info!("GenerateP384Key");
+                let public_key = self.generate_weierstrass_key::<p384::NistP384>(
+                    AttestationKey::P384,
+                    KeyKind::P384,
+                )?;
+                reply
+                    .extend_from_slice(&public_key.as_bytes()[1..])
+                    .unwrap();
+                Ok(())
+            }
+            Instruction::GenerateSecp256k1Key => {
+                info!("GenerateSecp256k1Key");
+                let public_key = self.generate_weierstrass_key::<k256::Secp256k1>(
+                    AttestationKey::Secp256k1,
+                    KeyKind::Secp256k1,
+                )?;
+                reply
+                    .extend_from_slice(&public_key.as_bytes()[1..])
+                    .unwrap();
+                Ok(())
+            }
             Instruction::SaveP256AttestationCertificate => {
-                if !self.store.ifs().exists(FILENAME_P256_SECRET) || data.len() < 100 {
-                    // Assuming certs will always be >100 bytes
-                    Err(Error::IncorrectDataParameter)
-                } else {
-                    info!("saving P256 CERT, {} bytes", data.len());
-                    store::store(&self.store, Location::Internal, FILENAME_P256_CERT, data)
-                        .map_err(|_| Error::NotEnoughMemory)?;
-                    Ok(())
-                }
+                self.save_certificate(AttestationKey::P256, data)
             }
             Instruction::SaveEd255AttestationCertificate => {
-                if !self.store.ifs().exists(FILENAME_ED255_SECRET) || data.len() < 100 {
-                    // Assuming certs will always be >100 bytes
-                    Err(Error::IncorrectDataParameter)
-                } else {
-                    info!("saving ED25519 CERT, {} bytes", data.len());
-                    store::store(&self.store, Location::Internal, FILENAME_ED255_CERT, data)
-                        .map_err(|_| Error::NotEnoughMemory)?;
-                    Ok(())
-                }
+                self.save_certificate(AttestationKey::Ed255, data)
             }
             Instruction::SaveX255AttestationCertificate => {
-                if !self.store.ifs().exists(FILENAME_X255_SECRET) || data.len() < 100 {
-                    // Assuming certs will always be >100 bytes
-                    Err(Error::IncorrectDataParameter)
-                } else {
-                    info!("saving X25519 CERT, {} bytes", data.len());
-                    store::store(&self.store, Location::Internal, FILENAME_X255_CERT, data)
-                        .map_err(|_| Error::NotEnoughMemory)?;
-                    Ok(())
-                }
+                self.save_certificate(AttestationKey::X255, data)
+            }
+            Instruction::SaveP384AttestationCertificate => {
+                self.save_certificate(AttestationKey::P384, data)
+            }
+            Instruction::SaveSecp256k1AttestationCertificate => {
+                self.save_certificate(AttestationKey::Secp256k1, data)
             }
             Instruction::SaveT1IntermediatePublicKey => {
                 info!("saving T1 INTERMEDIATE PUBLIC KEY, {} bytes", data.len());
@@ -347,10 +542,348 @@ where
                     .expect("failed copying UUID");
                 Ok(())
             }
+            Instruction::GetStatus => self.status_report(reply),
             Instruction::BootToBootrom => {
                 (self.rebooter)();
             }
+            Instruction::ListDirectory => {
+                // <index of the first entry: u16><path>
+                if data.len() < 2 {
+                    return Err(Error::IncorrectDataParameter);
+                }
+                let start = u16::from_be_bytes([data[0], data[1]]).into();
+                let path = parse_path(&data[2..])?;
+                info!("listing {} from entry {}", path.as_str(), start);
+                if attn_entry(&path).is_none() {
+                    return Err(Error::AccessDenied);
+                }
+                self.list_directory(&path, start, reply)
+            }
+            Instruction::ReadFile => {
+                // <offset: u32><path>
+                if data.len() < 4 {
+                    return Err(Error::IncorrectDataParameter);
+                }
+                let offset = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                let path = parse_path(&data[4..])?;
+                info!("reading {} from offset {}", path.as_str(), offset);
+                self.read_file(&path, offset, reply)
+            }
+            Instruction::HashFile => {
+                let path = parse_path(data)?;
+                info!("hashing {}", path.as_str());
+                if !is_hashable(&path, self.locked) {
+                    return Err(Error::AccessDenied);
+                }
+                let digest = hash_file(self.store.ifs(), &path)?;
+                reply
+                    .extend_from_slice(&digest)
+                    .map_err(|_| Error::NotEnoughMemory)
+            }
+            Instruction::StageFile => self.stage_file(),
+            Instruction::WriteManifest => self.write_manifest(data),
+            Instruction::CommitStaged => self.commit_staged(),
+            Instruction::DiscardStaged => {
+                self.discard_staged();
+                Ok(())
+            }
+            Instruction::FinalizeProvisioning => {
+                info!("finalizing provisioning");
+                store::store(&self.store, Location::Internal, LOCK_FILE, &[])
+                    .map_err(|_| Error::NotEnoughMemory)?;
+                self.locked = true;
+                Ok(())
+            }
+            Instruction::DeleteFile => {
+                let path = parse_path(data)?;
+                info!("deleting {}", path.as_str());
+                if !matches!(attn_entry(&path), Some(Some(_))) {
+                    return Err(Error::AccessDenied);
+                }
+                if !self.store.ifs().metadata(&path)?.is_file() {
+                    return Err(Error::IncorrectDataParameter);
+                }
+                self.store.ifs().remove(&path)?;
+                Ok(())
+            }
+            Instruction::TestLed => self.test_led(reply),
+            Instruction::TestButton => self.test_button(data, reply),
+            Instruction::TestInitStatus => self.test_init_status(reply),
+            Instruction::TestExtFlash => self.test_ext_flash(reply),
+            Instruction::TestNfc => self.test_nfc(reply),
+        }
+    }
+
+    /// Saves the certificate for an attestation key after checking that it is a DER X.509
+    /// certificate for that key.  If the T1 intermediate public key has been saved, the
+    /// certificate also has to be signed by it.
+    fn save_certificate(&mut self, key: AttestationKey, data: &[u8]) -> Result<(), Error> {
+        if !self.store.ifs().exists(key.secret()) {
+            return Err(Error::IncorrectDataParameter);
+        }
+        info!("saving {:?} CERT, {} bytes", key, data.len());
+
+        let certificate = x509::Certificate::parse(data)?;
+        let public_key = self.attestation_public_key(key)?;
+        let (algorithm, parameters) = key.algorithm();
+        let expected = x509::PublicKeyInfo {
+            algorithm,
+            parameters,
+            key: &public_key,
+        };
+        if certificate.public_key != expected {
+            info!("certificate does not match the key");
+            return Err(Error::InvalidCertificate);
+        }
+
+        if self.store.ifs().exists(FILENAME_T1_PUBLIC) {
+            self.verify_t1_signature(&certificate)?;
+        }
+
+        store::store(&self.store, Location::Internal, key.certificate(), data)
+            .map_err(|_| Error::NotEnoughMemory)
+    }
+
+    /// Encodes the public key of a saved attestation key like in a `SubjectPublicKeyInfo`.
+    fn attestation_public_key(&mut self, key: AttestationKey) -> Result<Vec<u8, 97>, Error> {
+        let serialized_key: trussed::types::Bytes<128> =
+            store::read(&self.store, Location::Internal, key.secret())
+                .map_err(|_| Error::NotFound)?;
+        let secret = Key::try_deserialize(&serialized_key)
+            .map_err(|_| Error::IncorrectDataParameter)?
+            .material;
+        let seed = || -> Result<[u8; 32], Error> {
+            secret
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::IncorrectDataParameter)
+        };
+
+        let mut public_key = Vec::new();
+        match key {
+            AttestationKey::P256 => {
+                let secret = p256_cortex_m4::SecretKey::from_bytes(seed()?)
+                    .map_err(|_| Error::IncorrectDataParameter)?;
+                public_key.push(0x04).unwrap();
+                public_key
+                    .extend_from_slice(&secret.public_key().to_untagged_bytes())
+                    .unwrap();
+            }
+            AttestationKey::Ed255 => {
+                let keypair = salty::Keypair::from(&seed()?);
+                public_key
+                    .extend_from_slice(keypair.public.as_bytes())
+                    .unwrap();
+            }
+            AttestationKey::X255 => {
+                let secret_key = salty::agreement::SecretKey::from_seed(&seed()?);
+                let public = salty::agreement::PublicKey::from(&secret_key);
+                public_key.extend_from_slice(&public.to_bytes()).unwrap();
+            }
+            AttestationKey::P384 => {
+                let secret = p384::SecretKey::from_slice(&secret)
+                    .map_err(|_| Error::IncorrectDataParameter)?;
+                let public = secret.public_key().to_encoded_point(false);
+                public_key.extend_from_slice(public.as_bytes()).unwrap();
+            }
+            AttestationKey::Secp256k1 => {
+                let secret = k256::SecretKey::from_slice(&secret)
+                    .map_err(|_| Error::IncorrectDataParameter)?;
+                let public = secret.public_key().to_encoded_point(false);
+                public_key.extend_from_slice(public.as_bytes()).unwrap();
+            }
+        }
+        Ok(public_key)
+    }
+
+    fn verify_t1_signature(&mut self, certificate: &x509::Certificate<'_>) -> Result<(), Error> {
+        let serialized_key: trussed::types::Bytes<128> =
+            store::read(&self.store, Location::Internal, FILENAME_T1_PUBLIC)
+                .map_err(|_| Error::NotFound)?;
+        let material = Key::try_deserialize(&serialized_key)
+            .map_err(|_| Error::IncorrectDataParameter)?
+            .material;
+        let public_key = p256_cortex_m4::PublicKey::from_untagged_bytes(&material)
+            .map_err(|_| Error::IncorrectDataParameter)?;
+
+        if certificate.signature_algorithm != der::OID_ECDSA_WITH_SHA256 {
+            info!("certificate is not signed with ECDSA P-256");
+            return Err(Error::InvalidCertificate);
+        }
+        let signature = x509::ecdsa_p256_signature(certificate.signature)?;
+        let signature = p256_cortex_m4::Signature::from_untagged_bytes(&signature)
+            .map_err(|_| Error::InvalidCertificate)?;
+        if !public_key.verify(certificate.tbs_certificate, &signature) {
+            info!("certificate is not signed by the T1 intermediate key");
+            return Err(Error::InvalidCertificate);
+        }
+        Ok(())
+    }
+
+    fn generate_p256_key(&mut self) -> Result<p256_cortex_m4::Keypair, Error> {
+        use p256_cortex_m4::{Keypair, SecretKey};
+        let mut seed = [0u8; 32];
+
+        // Generate a keypair with rejection sampling.
+        // This should use the proper `random` method but is not possible without a `CryptoRng` implementation, which trussed is not
+        let keypair = loop {
+            seed.copy_from_slice(syscall!(self.trussed.random_bytes(32)).bytes.as_slice());
+            match SecretKey::from_bytes(seed) {
+                Ok(secret) => {
+                    break Keypair {
+                        public: secret.public_key(),
+                        secret,
+                    }
+                }
+                Err(_) => continue,
+            }
+        };
+
+        let serialized_key = Key {
+            flags: Flags::LOCAL | Flags::SENSITIVE,
+            kind: KeyKind::P256,
+            material: seed.as_slice().try_into().unwrap(),
+        };
+
+        let serialized_bytes = serialized_key.serialize();
+
+        store::store(
+            &self.store,
+            Location::Internal,
+            FILENAME_P256_SECRET,
+            &serialized_bytes,
+        )
+        .map_err(|_| Error::NotEnoughMemory)?;
+        info!("stored to {}", FILENAME_P256_SECRET.as_str());
+
+        Ok(keypair)
+    }
+
+    fn generate_ed255_key(&mut self) -> Result<salty::Keypair, Error> {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(syscall!(self.trussed.random_bytes(32)).bytes.as_slice());
+
+        let serialized_key = Key {
+            flags: Flags::LOCAL | Flags::SENSITIVE,
+            kind: KeyKind::Ed255,
+            material: seed.as_slice().try_into().unwrap(),
+        };
+
+        // let serialized_key = Key::try_deserialize(&seed[..])
+        // .map_err(|_| Error::WrongLength)?;
+
+        let serialized_bytes = serialized_key.serialize();
+
+        store::store(
+            &self.store,
+            Location::Internal,
+            FILENAME_ED255_SECRET,
+            &serialized_bytes,
+        )
+        .map_err(|_| Error::NotEnoughMemory)?;
+
+        Ok(salty::Keypair::from(&seed))
+    }
+
+    /// Generates a secret key for a short Weierstrass curve with rejection sampling (see
+    /// `generate_p256_key`), stores it and returns the uncompressed public key.
+    fn generate_weierstrass_key<C>(
+        &mut self,
+        key: AttestationKey,
+        kind: KeyKind,
+    ) -> Result<EncodedPoint<C>, Error>
+    where
+        C: CurveArithmetic,
+        AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+        FieldBytesSize<C>: ModulusSize,
+    {
+        let mut seed = FieldBytes::<C>::default();
+        let len = seed.len();
+        let secret = loop {
+            seed.copy_from_slice(syscall!(self.trussed.random_bytes(len)).bytes.as_slice());
+            if let Ok(secret) = SecretKey::<C>::from_slice(&seed) {
+                break secret;
+            }
+        };
+
+        let serialized_key = Key {
+            flags: Flags::LOCAL | Flags::SENSITIVE,
+            kind,
+            material: seed.as_slice().try_into().unwrap(),
+        };
+
+        let serialized_bytes = serialized_key.serialize();
+
+        store::store(
+            &self.store,
+            Location::Internal,
+            key.secret(),
+            &serialized_bytes,
+        )
+        .map_err(|_| Error::NotEnoughMemory)?;
+        info!("stored to {}", key.secret().as_str());
+
+        Ok(secret.public_key().to_encoded_point(false))
+    }
+
+    /// Replies with the entries of a directory, starting at entry `start`, as far as they fit
+    /// into `reply`.
+    ///
+    /// Each entry is encoded as `<is dir: u8><length: u32><name length: u8><name>`.  If the
+    /// reply does not contain all remaining entries, the host continues with the next index.
+    /// Only `/attn` and the directories inside of it can be listed.
+    fn list_directory(
+        &mut self,
+        path: &Path,
+        start: usize,
+        reply: &mut VecView<u8>,
+    ) -> Result<(), Error> {
+        self.store.ifs().read_dir_and_then(path, &mut |dir| {
+            // skip `.` and `..`
+            for entry in dir.skip(2).skip(start) {
+                let entry = entry?;
+                let name = entry.file_name().as_str().as_bytes();
+                let metadata = entry.metadata();
+                if reply.capacity() - reply.len() < 6 + name.len() {
+                    break;
+                }
+                reply.push(metadata.is_dir().into()).unwrap();
+                reply
+                    .extend_from_slice(&(metadata.len() as u32).to_be_bytes())
+                    .unwrap();
+                reply.push(name.len() as u8).unwrap();
+                reply.extend_from_slice(name).unwrap();
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Replies with up to `READ_CHUNK_SIZE` bytes of a file, starting at `offset`.  A reply
+    /// shorter than that marks the end of the file.
+    ///
+    /// Only the files in `/attn` can be read, except for the attestation secrets, see
+    /// `is_readable`.
+    fn read_file(
+        &mut self,
+        path: &Path,
+        offset: u32,
+        reply: &mut VecView<u8>,
+    ) -> Result<(), Error> {
+        if !is_readable(path) {
+            return Err(Error::AccessDenied);
         }
+        let mut buffer = [0; READ_CHUNK_SIZE];
+        let len = READ_CHUNK_SIZE.min(reply.capacity() - reply.len());
+        let read = self.store.ifs().open_file_and_then(path, &mut |file| {
+            if offset as usize > file.len()? {
+                return Err(littlefs2_core::Error::INVALID);
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            read_all(file, &mut buffer[..len])
+        })?;
+        reply.extend_from_slice(&buffer[..read]).unwrap();
+        Ok(())
     }
 
     fn select(&mut self, data: &[u8]) -> Result<(), Error> {
@@ -368,3 +901,93 @@ where
         }
     }
 }
+
+/// `CryptoRng` backed by the Trussed RNG, e.g., for ECDSA signatures.
+struct TrussedRng<'a, T>(&'a mut T);
+
+impl<T: CryptoClient> RngCore for TrussedRng<'_, T> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(64) {
+            let bytes = syscall!(self.0.random_bytes(chunk.len())).bytes;
+            chunk.copy_from_slice(&bytes);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<T: CryptoClient> CryptoRng for TrussedRng<'_, T> {}
+
+fn parse_path(data: &[u8]) -> Result<PathBuf, Error> {
+    if data.is_empty() {
+        return Err(Error::IncorrectDataParameter);
+    }
+    PathBuf::try_from(data).map_err(|_| Error::IncorrectDataParameter)
+}
+
+/// The first component of `path` below `/attn`, `Some(None)` for `/attn` itself.  `None` for
+/// paths outside of `/attn` and paths with `.` or `..` components.
+fn attn_entry(path: &Path) -> Option<Option<&str>> {
+    let mut components = path.as_str().split('/').filter(|c| !c.is_empty());
+    if components.clone().any(|c| c == "." || c == "..") {
+        return None;
+    }
+    if components.next() != Some("attn") {
+        return None;
+    }
+    Some(components.next())
+}
+
+/// Whether `ReadFile` may return the content of `path`, i.e., it is inside of `/attn` but not
+/// inside of `/attn/sec`.  Uploaded and staged files might be secrets, too.  Paths with `.` or
+/// `..` components are refused.
+fn is_readable(path: &Path) -> bool {
+    matches!(
+        attn_entry(path),
+        Some(Some(entry)) if !matches!(entry, "sec" | "stage" | "upload")
+    )
+}
+
+/// Whether `HashFile` may return the digest of `path`, i.e., it is readable (see `is_readable`)
+/// or it is an attestation secret and the provisioning is not finalized yet.
+fn is_hashable(path: &Path, locked: bool) -> bool {
+    is_readable(path) || (!locked && attn_entry(path) == Some(Some("sec")))
+}
+
+fn hash_file(fs: &dyn DynFilesystem, path: &Path) -> Result<[u8; 32], Error> {
+    let mut hasher = Sha256::new();
+    fs.open_file_and_then(path, &mut |file| {
+        let mut buffer = [0; 256];
+        loop {
+            let read = read_all(file, &mut buffer)?;
+            hasher.update(&buffer[..read]);
+            if read < buffer.len() {
+                return Ok(());
+            }
+        }
+    })?;
+    Ok(hasher.finalize().into())
+}
+
+/// Reads until `buffer` is full or the end of the file is reached.
+fn read_all(file: &dyn DynFile, buffer: &mut [u8]) -> littlefs2_core::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.read(&mut buffer[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
@@ -0,0 +1,132 @@
+//! Factory self tests of the hardware.
+//!
+//! The LED and the buttons are tested through the Trussed UI.  All other peripherals are owned
+//! by the runner, so it records their state during the initialization, see `HardwareStatus`.
+//!
+//! Every test replies `<result: u8><details>`, see `TestResult` for the results.
+
+use core::convert::TryInto;
+use heapless::VecView;
+use trussed::store::Store;
+use trussed_core::{syscall, try_syscall, CryptoClient, UiClient};
+
+use crate::{Error, Provisioner};
+
+// used if the `TestButton` command does not contain a timeout
+const DEFAULT_BUTTON_TIMEOUT_MS: u32 = 10_000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum TestResult {
+    Pass = 0,
+    Fail = 1,
+    /// the device does not have the tested hardware
+    NotAvailable = 2,
+}
+
+/// State of the hardware after the initialization, recorded by the runner.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HardwareStatus {
+    /// bits of the runner's `InitStatus`, zero if the initialization was successful
+    pub init_status: u8,
+    /// JEDEC ID reported by the external flash, `None` if it was not queried
+    pub ext_flash_jedec_id: Option<[u8; 3]>,
+    /// whether the external flash was detected and mounted without errors
+    pub ext_flash_ok: bool,
+    pub has_nfc: bool,
+    /// FM11NC08 registers 0x02 to 0x0e, `None` if no NFC chip was detected
+    pub nfc_registers: Option<[u8; 13]>,
+}
+
+pub struct SelfTestOptions {
+    pub hardware: HardwareStatus,
+    /// custom UI status that cycles the LED through its colors
+    pub led_status: u8,
+    /// custom UI status that makes the next user presence check use the buttons, even if they
+    /// are disabled for the provisioner
+    pub button_status: u8,
+}
+
+impl<S, T> Provisioner<S, T>
+where
+    S: Store,
+    T: CryptoClient + UiClient,
+{
+    /// Cycles the LED through its colors.  The station has to check the colors itself.
+    pub(crate) fn test_led(&mut self, reply: &mut VecView<u8>) -> Result<(), Error> {
+        info!("testing LED");
+        syscall!(self.trussed.set_custom_status(self.self_test.led_status));
+        reply_result(reply, TestResult::Pass, &[])
+    }
+
+    /// Waits for a button press, data: `[<timeout in ms: u32>]`
+    pub(crate) fn test_button(
+        &mut self,
+        data: &[u8],
+        reply: &mut VecView<u8>,
+    ) -> Result<(), Error> {
+        let timeout = match data.len() {
+            0 => DEFAULT_BUTTON_TIMEOUT_MS,
+            4 => u32::from_be_bytes(data.try_into().unwrap()),
+            _ => return Err(Error::IncorrectDataParameter),
+        };
+        info!("testing buttons, timeout {} ms", timeout);
+        syscall!(self.trussed.set_custom_status(self.self_test.button_status));
+        let pressed = try_syscall!(self.trussed.confirm_user_present(timeout))
+            .map(|reply| reply.result.is_ok())
+            .unwrap_or_default();
+        let result = if pressed {
+            TestResult::Pass
+        } else {
+            TestResult::Fail
+        };
+        reply_result(reply, result, &[])
+    }
+
+    /// Replies the `InitStatus` bits, which are all zero on success.
+    pub(crate) fn test_init_status(&mut self, reply: &mut VecView<u8>) -> Result<(), Error> {
+        let init_status = self.self_test.hardware.init_status;
+        let result = if init_status == 0 {
+            TestResult::Pass
+        } else {
+            TestResult::Fail
+        };
+        reply_result(reply, result, &[init_status])
+    }
+
+    /// Replies the JEDEC ID of the external flash.
+    pub(crate) fn test_ext_flash(&mut self, reply: &mut VecView<u8>) -> Result<(), Error> {
+        let hardware = &self.self_test.hardware;
+        let Some(jedec_id) = hardware.ext_flash_jedec_id else {
+            return reply_result(reply, TestResult::NotAvailable, &[]);
+        };
+        let result = if hardware.ext_flash_ok {
+            TestResult::Pass
+        } else {
+            TestResult::Fail
+        };
+        reply_result(reply, result, &jedec_id)
+    }
+
+    /// Replies the registers of the NFC chip.
+    pub(crate) fn test_nfc(&mut self, reply: &mut VecView<u8>) -> Result<(), Error> {
+        let hardware = &self.self_test.hardware;
+        if !hardware.has_nfc {
+            return reply_result(reply, TestResult::NotAvailable, &[]);
+        }
+        match hardware.nfc_registers {
+            Some(registers) => reply_result(reply, TestResult::Pass, &registers),
+            None => reply_result(reply, TestResult::Fail, &[]),
+        }
+    }
+}
+
+fn reply_result(reply: &mut VecView<u8>, result: TestResult, details: &[u8]) -> Result<(), Error> {
+    info!("self test result: {:?}", result);
+    reply
+        .push(result as u8)
+        .map_err(|_| Error::NotEnoughMemory)?;
+    reply
+        .extend_from_slice(details)
+        .map_err(|_| Error::NotEnoughMemory)
+}
@@ -0,0 +1,287 @@
+//! Staged provisioning: files are uploaded into a staging area in the internal filesystem first
+//! and only moved to their destination by a single commit, once they match the manifest.
+//!
+//! The manifest is a sequence of `<path length: u8><path><SHA-256 digest>` entries.  The commit
+//! moves the previous versions of the files into a backup directory and then the staged files to
+//! their destination.  Files are only renamed, never copied, thus they can be as large as any
+//! upload.  If moving one of the files fails, the files moved so far are rolled back.
+//!
+//! The commit keeps a journal in the staging area, i.e., the manifest and its current `Phase`.
+//! A commit interrupted by a power loss is finished or rolled back on the next start, see
+//! `recover_staged`.  The backups are only removed once the commit or its rollback is done.
+
+use core::convert::TryFrom;
+use heapless::Vec;
+use littlefs2_core::{path, DynFilesystem, Path, PathBuf};
+use trussed::store::Store;
+use trussed_core::{CryptoClient, UiClient};
+
+use crate::{hash_file, parse_path, read_all, Error, Provisioner};
+
+pub const MANIFEST_SIZE: usize = 1024;
+
+const STAGING_DIR: &Path = path!("/attn/stage");
+const BACKUP_DIR: &Path = path!("/attn/stage/old");
+// both prefixes have the same length, see `write_manifest`
+const STAGED_FILES: &str = "/attn/stage/new";
+const BACKUP_FILES: &str = "/attn/stage/old";
+
+const JOURNAL_MANIFEST: &Path = path!("/attn/stage/manifest");
+const JOURNAL_PHASE: &Path = path!("/attn/stage/phase");
+
+/// Phase of a commit, recorded in the journal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    /// The current versions of the files are moved into the backup directory.  An interrupted
+    /// backup is rolled back.
+    Backup = 1,
+    /// The staged files are moved to their destination.  An interrupted commit is finished.
+    Commit = 2,
+    /// The previous versions of the files are moved back.  An interrupted rollback is finished.
+    Rollback = 3,
+}
+
+impl TryFrom<u8> for Phase {
+    type Error = Error;
+
+    fn try_from(phase: u8) -> Result<Self, Error> {
+        match phase {
+            1 => Ok(Self::Backup),
+            2 => Ok(Self::Commit),
+            3 => Ok(Self::Rollback),
+            _ => Err(Error::IncorrectDataParameter),
+        }
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    digest: [u8; 32],
+}
+
+/// Parses the next manifest entry, if any.
+fn next_entry(manifest: &mut &[u8]) -> Result<Option<Entry>, Error> {
+    let Some((&len, rest)) = manifest.split_first() else {
+        return Ok(None);
+    };
+    let len = usize::from(len);
+    if rest.len() < len + 32 || rest.first() != Some(&b'/') {
+        return Err(Error::IncorrectDataParameter);
+    }
+    let path = parse_path(&rest[..len])?;
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&rest[len..len + 32]);
+    *manifest = &rest[len + 32..];
+    Ok(Some(Entry { path, digest }))
+}
+
+/// `prefix` followed by the absolute `path`
+fn prefixed(prefix: &str, path: &Path) -> Result<PathBuf, Error> {
+    let mut buffer = Vec::<u8, 256>::new();
+    buffer
+        .extend_from_slice(prefix.as_bytes())
+        .and_then(|_| buffer.extend_from_slice(path.as_str().as_bytes()))
+        .map_err(|_| Error::IncorrectDataParameter)?;
+    PathBuf::try_from(buffer.as_slice()).map_err(|_| Error::IncorrectDataParameter)
+}
+
+/// Renames `from` to `to`, creating the parent directories of `to`.
+fn move_file(fs: &dyn DynFilesystem, from: &Path, to: &Path) -> Result<(), Error> {
+    if let Some(parent) = to.parent() {
+        fs.create_dir_all(&parent)?;
+    }
+    fs.rename(from, to)?;
+    Ok(())
+}
+
+impl<S, T> Provisioner<S, T>
+where
+    S: Store,
+    T: CryptoClient + UiClient,
+{
+    /// Writes the selected file into the staging area instead of its destination.
+    pub(crate) fn stage_file(&mut self) -> Result<(), Error> {
+        if self.file_len() == 0 || self.buffer_filename.is_empty() {
+            return Err(Error::IncorrectDataParameter);
+        }
+        let result = parse_path(&self.buffer_filename)
+            .and_then(|path| prefixed(STAGED_FILES, &path))
+            .and_then(|staged| {
+                info!("staging file {} {} bytes", staged.as_str(), self.file_len());
+                self.store_upload(&staged)
+            });
+        self.clear_upload();
+        result
+    }
+
+    /// Checks and stores the manifest.  Every path may only occur once, as its previous version
+    /// is moved into the backup directory.
+    pub(crate) fn write_manifest(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut entries = data;
+        while let Some(entry) = next_entry(&mut entries)? {
+            prefixed(STAGED_FILES, &entry.path)?;
+            let mut others = entries;
+            while let Some(other) = next_entry(&mut others)? {
+                if other.path == entry.path {
+                    return Err(Error::IncorrectDataParameter);
+                }
+            }
+        }
+        self.manifest.clear();
+        self.manifest
+            .extend_from_slice(data)
+            .map_err(|_| Error::NotEnoughMemory)
+    }
+
+    /// Moves all files of the manifest from the staging area to their destination, if all of
+    /// them are staged and match their digest.
+    pub(crate) fn commit_staged(&mut self) -> Result<(), Error> {
+        let manifest = core::mem::take(&mut self.manifest);
+        let result = self.commit_manifest(&manifest);
+        self.discard_staged();
+        result
+    }
+
+    /// Removes the staging area.  The journal of an unfinished commit is recovered first and
+    /// kept (together with the backups) if that fails, see `recover_staged`.
+    pub(crate) fn discard_staged(&mut self) {
+        self.manifest.clear();
+        let ifs = self.store.ifs();
+        if ifs.exists(JOURNAL_PHASE) {
+            self.recover_staged();
+            if self.store.ifs().exists(JOURNAL_PHASE) {
+                return;
+            }
+        }
+        self.store.ifs().remove_dir_all(STAGING_DIR).ok();
+    }
+
+    /// Finishes or rolls back a commit interrupted by a power loss, depending on its `Phase`.
+    /// Called on every start.
+    pub(crate) fn recover_staged(&mut self) {
+        let ifs = self.store.ifs();
+        let mut phase = [0; 1];
+        let mut manifest = [0; MANIFEST_SIZE];
+        let journal = ifs
+            .open_file_and_then(JOURNAL_PHASE, &mut |file| read_all(file, &mut phase))
+            .and_then(|_| {
+                ifs.open_file_and_then(JOURNAL_MANIFEST, &mut |file| read_all(file, &mut manifest))
+            });
+        let Ok(len) = journal else {
+            return;
+        };
+        let manifest = &manifest[..len];
+        let result = Phase::try_from(phase[0]).and_then(|phase| {
+            info!("recovering commit interrupted in phase {:?}", phase);
+            match phase {
+                Phase::Backup => self.rollback(manifest, phase),
+                Phase::Commit => self.commit_files(manifest).or_else(|_| {
+                    self.write_phase(Phase::Rollback)?;
+                    self.rollback(manifest, Phase::Rollback)
+                }),
+                Phase::Rollback => self.rollback(manifest, phase),
+            }
+        });
+        match result {
+            Ok(()) => {
+                self.store.ifs().remove(JOURNAL_PHASE).ok();
+            }
+            Err(_err) => info!("recovery failed, keeping the journal: {:?}", _err),
+        }
+    }
+
+    /// Commits the files of `manifest`.  If that fails, the journal is left for the rollback by
+    /// `recover_staged`.
+    fn commit_manifest(&mut self, manifest: &[u8]) -> Result<(), Error> {
+        if manifest.is_empty() {
+            return Err(Error::IncorrectDataParameter);
+        }
+        if self.store.ifs().exists(JOURNAL_PHASE) {
+            info!("unfinished commit, refusing a new one");
+            return Err(Error::AccessDenied);
+        }
+
+        let mut entries = manifest;
+        while let Some(entry) = next_entry(&mut entries)? {
+            let staged = prefixed(STAGED_FILES, &entry.path)?;
+            if hash_file(self.store.ifs(), &staged)? != entry.digest {
+                info!("digest mismatch for {}", entry.path.as_str());
+                return Err(Error::IncorrectDataParameter);
+            }
+        }
+
+        // leftovers of an earlier commit must not be mistaken for backups
+        let ifs = self.store.ifs();
+        ifs.remove_dir_all(BACKUP_DIR).ok();
+        ifs.write(JOURNAL_MANIFEST, manifest)?;
+        self.write_phase(Phase::Backup)?;
+
+        // keep the current versions for a rollback
+        let mut entries = manifest;
+        while let Some(entry) = next_entry(&mut entries)? {
+            if let Err(err) = self.backup(&entry.path) {
+                info!("backup failed, rolling back");
+                return Err(err);
+            }
+        }
+
+        self.write_phase(Phase::Commit)?;
+        if let Err(err) = self.commit_files(manifest) {
+            info!("commit failed, rolling back");
+            // the previous versions of all files are in the backup directory
+            self.write_phase(Phase::Rollback)?;
+            return Err(err);
+        }
+        self.store.ifs().remove(JOURNAL_PHASE)?;
+        Ok(())
+    }
+
+    fn write_phase(&mut self, phase: Phase) -> Result<(), Error> {
+        self.store.ifs().write(JOURNAL_PHASE, &[phase as u8])?;
+        Ok(())
+    }
+
+    /// Moves the current version of `path`, if any, into the backup directory.
+    fn backup(&mut self, path: &Path) -> Result<(), Error> {
+        let ifs = self.store.ifs();
+        if !ifs.exists(path) {
+            return Ok(());
+        }
+        move_file(ifs, path, &prefixed(BACKUP_FILES, path)?)
+    }
+
+    /// Moves the staged files of `manifest` which are not yet moved to their destination.
+    fn commit_files(&mut self, mut manifest: &[u8]) -> Result<(), Error> {
+        let ifs = self.store.ifs();
+        while let Some(entry) = next_entry(&mut manifest)? {
+            let staged = prefixed(STAGED_FILES, &entry.path)?;
+            if ifs.exists(&staged) {
+                info!("committing {}", entry.path.as_str());
+                move_file(ifs, &staged, &entry.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the previous versions of the files of `manifest` from the backup directory.
+    ///
+    /// Once all backups are done (`Phase::Rollback`), a file without a backup did not exist
+    /// before and is removed.  A file whose backup cannot be restored is never removed, the
+    /// rollback fails and the journal is kept instead.
+    fn rollback(&mut self, mut manifest: &[u8], phase: Phase) -> Result<(), Error> {
+        let ifs = self.store.ifs();
+        let mut result = Ok(());
+        while let Some(entry) = next_entry(&mut manifest)? {
+            let backup = prefixed(BACKUP_FILES, &entry.path)?;
+            if ifs.exists(&backup) {
+                if let Err(err) = move_file(ifs, &backup, &entry.path) {
+                    info!("failed to restore {}", entry.path.as_str());
+                    result = Err(err);
+                }
+            } else if phase == Phase::Rollback && ifs.exists(&entry.path) {
+                ifs.remove(&entry.path)?;
+            }
+        }
+        result
+    }
+}
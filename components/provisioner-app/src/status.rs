@@ -0,0 +1,109 @@
+//! Report of the provisioning state, so that a station can skip the steps that are already done.
+//!
+//! The report is a CBOR map with these entries:
+//! - `protocol`: the version of the provisioning protocol, see `PROTOCOL_VERSION`
+//! - `uuid`: the device UUID as a byte string
+//! - `version`: the firmware version as `[major, minor, patch]`
+//! - `variant`: the runner's `Variant`
+//! - `sec`, `x5c`: bit masks of the existing `/attn/sec` and `/attn/x5c` slots, bit `n` being
+//!   set if the file `/attn/sec/0n` or `/attn/x5c/0n` exists
+//! - `t1`: whether the T1 intermediate public key has been saved
+//! - `ifs`, `efs`: the free blocks of the internal and external filesystem, `efs` is missing if
+//!   the external flash is not available
+//! - `locked`: whether the provisioning has been finalized
+
+use heapless::VecView;
+use serde::{Serialize, Serializer};
+use trussed::store::Store;
+use trussed_core::{CryptoClient, UiClient};
+
+use crate::{AttestationKey, Error, Provisioner, FILENAME_T1_PUBLIC};
+
+const REPORT_SIZE: usize = 128;
+
+/// Version of the provisioning protocol, reports without it are version 1.
+///
+/// - 2: `WriteBinary` via APDU writes at the offset from P1-P2 instead of appending to the
+///   selected buffer (`WriteBinary` via CTAPHID still appends), `WriteChunk` writes at an
+///   explicit offset.
+const PROTOCOL_VERSION: u8 = 2;
+
+/// Information about the firmware and the runner that is not available to the app otherwise.
+#[derive(Copy, Clone, Debug)]
+pub struct DeviceInfo {
+    /// `[major, minor, patch]`
+    pub version: [u8; 3],
+    pub variant: u8,
+    /// the external flash is not available if the device is powered by NFC
+    pub is_efs_available: bool,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    protocol: u8,
+    uuid: Bytes<'a>,
+    version: [u8; 3],
+    variant: u8,
+    sec: u32,
+    x5c: u32,
+    t1: bool,
+    ifs: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    efs: Option<usize>,
+    locked: bool,
+}
+
+/// Serializes as a CBOR byte string instead of an array.
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<S, T> Provisioner<S, T>
+where
+    S: Store,
+    T: CryptoClient + UiClient,
+{
+    pub(crate) fn status_report(&self, reply: &mut VecView<u8>) -> Result<(), Error> {
+        let ifs = self.store.ifs();
+        let mut sec = 0;
+        let mut x5c = 0;
+        for key in AttestationKey::ALL {
+            if ifs.exists(key.secret()) {
+                sec |= 1 << key.slot();
+            }
+            if ifs.exists(key.certificate()) {
+                x5c |= 1 << key.slot();
+            }
+        }
+        let efs = if self.device.is_efs_available {
+            Some(self.store.efs().available_blocks()?)
+        } else {
+            None
+        };
+        let report = Report {
+            protocol: PROTOCOL_VERSION,
+            uuid: Bytes(&self.uuid),
+            version: self.device.version,
+            variant: self.device.variant,
+            sec,
+            x5c,
+            t1: ifs.exists(FILENAME_T1_PUBLIC),
+            ifs: ifs.available_blocks()?,
+            efs,
+            locked: self.locked,
+        };
+
+        let mut buffer = [0; REPORT_SIZE];
+        let report = cbor_smol::cbor_serialize(&report, &mut buffer).map_err(|_| {
+            info!("status report too large");
+            Error::NotEnoughMemory
+        })?;
+        reply
+            .extend_from_slice(report)
+            .map_err(|_| Error::NotEnoughMemory)
+    }
+}
@@ -0,0 +1,154 @@
+//! Uploads of the selected filename and file contents in offset-addressed chunks.
+//!
+//! `WriteChunk` prefixes the chunk with a 32-bit offset.  Via APDU, `WriteBinary` takes the
+//! offset from P1-P2 as in ISO 7816-4, thus it can only be used for the first 32 KiB.  Via
+//! CTAPHID, `WriteBinary` appends the chunk to the selected buffer, as before protocol version 2
+//! (see `status`).
+//!
+//! A chunk has to start within or directly after the data written so far.  If it overlaps that
+//! data, e.g., because the host retransmitted a command, the overlapping part has to match.
+//! File contents that do not fit into the buffer are streamed into `UPLOAD_FILE`, which is moved
+//! to its destination by `WriteFile`.
+
+use heapless::Vec;
+use littlefs2_core::{path, OpenSeekFrom, Path};
+use trussed::store::{self, Store};
+use trussed_core::{types::Location, CryptoClient, UiClient};
+
+use crate::{Error, Provisioner, SelectedBuffer};
+
+const UPLOAD_FILE: &Path = path!("/attn/upload");
+
+/// Returns the length of the part of `chunk` that is already contained in `buffer`.
+fn overlap(buffer: &[u8], offset: usize, chunk: &[u8]) -> Result<usize, Error> {
+    if offset > buffer.len() {
+        info!(
+            "chunk at {} leaves a gap after {} bytes",
+            offset,
+            buffer.len()
+        );
+        return Err(Error::IncorrectDataParameter);
+    }
+    let overlap = chunk.len().min(buffer.len() - offset);
+    if buffer[offset..offset + overlap] != chunk[..overlap] {
+        info!("chunk at {} does not match the existing data", offset);
+        return Err(Error::IncorrectDataParameter);
+    }
+    Ok(overlap)
+}
+
+fn write_to_buffer<const N: usize>(
+    buffer: &mut Vec<u8, N>,
+    offset: usize,
+    chunk: &[u8],
+) -> Result<(), Error> {
+    let overlap = overlap(buffer, offset, chunk)?;
+    buffer
+        .extend_from_slice(&chunk[overlap..])
+        .map_err(|_| Error::NotEnoughMemory)
+}
+
+impl<S, T> Provisioner<S, T>
+where
+    S: Store,
+    T: CryptoClient + UiClient,
+{
+    /// Length of the uploaded file contents.
+    pub(crate) fn file_len(&self) -> usize {
+        self.flushed + self.buffer_file_contents.len()
+    }
+
+    /// Length of the selected buffer, i.e., the offset of the next chunk.
+    pub(crate) fn upload_len(&self) -> usize {
+        match self.selected_buffer {
+            SelectedBuffer::Filename => self.buffer_filename.len(),
+            SelectedBuffer::File => self.file_len(),
+        }
+    }
+
+    pub(crate) fn write_chunk(&mut self, offset: usize, chunk: &[u8]) -> Result<(), Error> {
+        match self.selected_buffer {
+            SelectedBuffer::Filename => write_to_buffer(&mut self.buffer_filename, offset, chunk),
+            SelectedBuffer::File => self.write_file_chunk(offset, chunk),
+        }
+    }
+
+    fn write_file_chunk(&mut self, offset: usize, chunk: &[u8]) -> Result<(), Error> {
+        // the flushed data cannot be compared
+        let offset = offset.checked_sub(self.flushed).ok_or_else(|| {
+            info!("chunk at {} overlaps the flushed data", offset);
+            Error::IncorrectDataParameter
+        })?;
+        let overlap = overlap(&self.buffer_file_contents, offset, chunk)?;
+        let chunk = &chunk[overlap..];
+
+        let buffer = &self.buffer_file_contents;
+        if buffer.len() + chunk.len() > buffer.capacity() {
+            self.flush_upload()?;
+        }
+        self.buffer_file_contents
+            .extend_from_slice(chunk)
+            .map_err(|_| Error::NotEnoughMemory)
+    }
+
+    fn flush_upload(&mut self) -> Result<(), Error> {
+        info!(
+            "flushing {} bytes to the upload file",
+            self.buffer_file_contents.len()
+        );
+        if self.flushed == 0 {
+            store::store(
+                &self.store,
+                Location::Internal,
+                UPLOAD_FILE,
+                &self.buffer_file_contents,
+            )
+            .map_err(|_| Error::NotEnoughMemory)?;
+        } else {
+            let position = OpenSeekFrom::Start(self.flushed as u32);
+            self.store
+                .ifs()
+                .write_chunk(UPLOAD_FILE, &self.buffer_file_contents, position)?;
+        }
+        self.flushed += self.buffer_file_contents.len();
+        self.buffer_file_contents.clear();
+        Ok(())
+    }
+
+    /// Whether the file contents have been streamed into the upload file, i.e., are not
+    /// completely contained in the buffer.
+    pub(crate) fn is_streamed(&self) -> bool {
+        self.flushed > 0
+    }
+
+    /// Writes the uploaded file contents to `path`.
+    pub(crate) fn store_upload(&mut self, path: &Path) -> Result<(), Error> {
+        if !self.is_streamed() {
+            return store::store(
+                &self.store,
+                Location::Internal,
+                path,
+                &self.buffer_file_contents,
+            )
+            .map_err(|_| Error::NotEnoughMemory);
+        }
+
+        self.flush_upload()?;
+        if let Some(parent) = path.parent() {
+            self.store.ifs().create_dir_all(&parent)?;
+        }
+        self.store.ifs().rename(UPLOAD_FILE, path)?;
+        self.flushed = 0;
+        Ok(())
+    }
+
+    /// Discards the uploaded filename and file contents.
+    pub(crate) fn clear_upload(&mut self) {
+        self.buffer_filename.clear();
+        self.buffer_file_contents.clear();
+        if self.is_streamed() {
+            self.store.ifs().remove(UPLOAD_FILE).ok();
+            self.flushed = 0;
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Parsing of the DER X.509 attestation certificates (RFC 5280), as far as needed to check them
+//! against the attestation keys.
+
+use crate::{
+    der::{Reader, TAG_INTEGER, TAG_OID, TAG_SEQUENCE, TAG_VERSION},
+    Error,
+};
+
+#[derive(Debug, PartialEq)]
+pub struct PublicKeyInfo<'a> {
+    pub algorithm: &'a [u8],
+    /// the named curve, if any
+    pub parameters: Option<&'a [u8]>,
+    pub key: &'a [u8],
+}
+
+pub struct Certificate<'a> {
+    /// complete encoding of the `TBSCertificate`, i.e., the signed data
+    pub tbs_certificate: &'a [u8],
+    pub public_key: PublicKeyInfo<'a>,
+    pub signature_algorithm: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let mut outer = Reader::new(data);
+        let mut certificate = outer.nested(TAG_SEQUENCE)?;
+        outer.finish()?;
+
+        let (tbs, tbs_certificate) = certificate.read(TAG_SEQUENCE)?;
+        let mut signature_algorithm = certificate.nested(TAG_SEQUENCE)?;
+        let signature = certificate.bit_string()?;
+        certificate.finish()?;
+
+        let mut tbs = Reader::new(tbs);
+        if tbs.peek_tag() == Some(TAG_VERSION) {
+            tbs.content(TAG_VERSION)?;
+        }
+        // serial number, signature, issuer, validity, subject
+        tbs.content(TAG_INTEGER)?;
+        for _ in 0..4 {
+            tbs.content(TAG_SEQUENCE)?;
+        }
+        let public_key = PublicKeyInfo::parse(tbs.nested(TAG_SEQUENCE)?)?;
+
+        Ok(Self {
+            tbs_certificate,
+            public_key,
+            signature_algorithm: signature_algorithm.content(TAG_OID)?,
+            signature,
+        })
+    }
+}
+
+impl<'a> PublicKeyInfo<'a> {
+    fn parse(mut info: Reader<'a>) -> Result<Self, Error> {
+        let mut algorithm = info.nested(TAG_SEQUENCE)?;
+        let key = info.bit_string()?;
+        info.finish()?;
+
+        let oid = algorithm.content(TAG_OID)?;
+        // Ed25519 and X25519 have no parameters
+        let parameters = match algorithm.peek_tag() {
+            Some(TAG_OID) => Some(algorithm.content(TAG_OID)?),
+            _ => None,
+        };
+        Ok(Self {
+            algorithm: oid,
+            parameters,
+            key,
+        })
+    }
+}
+
+/// Converts a DER `ECDSA-Sig-Value` into the untagged `r || s` of a P-256 signature.
+pub fn ecdsa_p256_signature(signature: &[u8]) -> Result<[u8; 64], Error> {
+    let mut outer = Reader::new(signature);
+    let mut integers = outer.nested(TAG_SEQUENCE)?;
+    outer.finish()?;
+
+    let mut untagged = [0; 64];
+    for half in untagged.chunks_mut(32) {
+        let integer = integers.content(TAG_INTEGER)?;
+        // strip the sign byte
+        let start = integer
+            .iter()
+            .position(|&byte| byte != 0)
+            .unwrap_or(integer.len());
+        let integer = &integer[start..];
+        if integer.len() > half.len() {
+            return Err(Error::InvalidCertificate);
+        }
+        let padding = half.len() - integer.len();
+        half[padding..].copy_from_slice(integer);
+    }
+    integers.finish()?;
+    Ok(untagged)
+}
@@ -292,7 +292,10 @@ mod app {
     fn update_ui(mut c: update_ui::Context) {
         // debug_now!("update UI: remaining stack size: {} bytes", super::msp() - 0x2000_0000);
 
-        c.shared.trussed.lock(|trussed| trussed.update_ui());
+        c.shared.trussed.lock(|trussed| {
+            trussed.update_ui();
+            runtime::update_ifs_snapshot(trussed, monotonics::now());
+        });
         update_ui::spawn_after(REFRESH_MILLISECS).ok();
     }
 
@@ -84,6 +84,9 @@ mod app {
             ctx.device.USBD,
         );
 
+        let mut dev_rng = Rng::new(ctx.device.RNG);
+        nk3am::init_store_keys(&ctx.device.FICR, &mut dev_rng);
+
         let internal_flash = InternalFlashStorage::new(ctx.device.NVMC);
         let external_flash = nk3am::init_external_flash(
             ctx.device.SPIM3,
@@ -124,7 +127,6 @@ mod app {
             board_gpio.touch,
         );
 
-        let mut dev_rng = Rng::new(ctx.device.RNG);
         let hw_key = nk3am::hw_key(&ctx.device.FICR);
         let mut trussed = boards::init::init_trussed(
             &mut dev_rng,
@@ -272,6 +274,7 @@ mod app {
         //trace!("update ui");
         trussed.lock(|trussed| {
             trussed.update_ui();
+            runtime::update_ifs_snapshot(trussed, monotonics::now().into());
         });
         ui::spawn_after(RtcDuration::from_ms(125)).ok();
     }
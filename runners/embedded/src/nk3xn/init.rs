@@ -607,7 +607,13 @@ impl Stage4 {
             #[cfg(feature = "write-undefined-flash")]
             initialize_fs_flash(&mut self.flash.flash_gordon, &mut self.flash.prince);
 
-            InternalFlashStorage::new(self.flash.flash_gordon, self.flash.prince)
+            let mut internal =
+                InternalFlashStorage::new(self.flash.flash_gordon, self.flash.prince);
+            // the IFS snapshots are only written if the device is powered via USB
+            if !external.is_ram() {
+                internal.init_snapshot_secret(&mut self.flash.rng);
+            }
+            internal
         };
 
         #[cfg(feature = "no-encrypted-storage")]
@@ -256,6 +256,7 @@ mod app {
         //trace!("update ui");
         trussed.lock(|trussed| {
             trussed.update_ui();
+            runtime::update_ifs_snapshot(trussed, monotonics::now().into());
         });
         ui::spawn_after(RtcDuration::from_ms(125)).ok();
     }
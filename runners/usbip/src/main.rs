@@ -195,6 +195,12 @@ fn exec(
         provisioner: apps::ProvisionerData {
             store,
             rebooter: || unimplemented!(),
+            variant: Variant::Usbip,
+            version: VERSION,
+            init_status: Default::default(),
+            ext_flash_jedec_id: None,
+            has_nfc: false,
+            nfc_registers: None,
         },
         _marker: Default::default(),
     };
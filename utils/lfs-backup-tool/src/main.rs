@@ -0,0 +1,468 @@
+//! Host-side tool to inspect, extract and create `lfs-backup` backups, e.g., from
+//! external flash dumps or storage files of the usbip runner.
+//!
+//! User attributes are stored next to the extracted entry in a file with the
+//! suffix `.user-attr`, `create` reads them back from there.
+
+mod memory;
+
+use std::{
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Component, Path as HostPath, PathBuf as HostPathBuf},
+    process::ExitCode,
+};
+
+use gumdrop::Options;
+use lfs_backup::{
+    BackupBackend, BackupHeader, BackupKey, FSBackupError, FSEntryBlob, FSRecord, CHUNK_SIZE,
+    SALT_SIZE,
+};
+use littlefs2::{
+    fs::Filesystem,
+    path::{Path, PathBuf},
+};
+use trussed_core::{config::USER_ATTRIBUTE_NUMBER, types::UserAttribute};
+
+use memory::{MemoryBackend, RamStorage};
+
+const ATTR_SUFFIX: &str = ".user-attr";
+
+/// Inspects, extracts and creates lfs-backup backups.
+#[derive(Debug, Options)]
+struct Args {
+    /// Show this help message.
+    help: bool,
+    #[options(command)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Options)]
+enum Command {
+    /// List the entries of a backup.
+    List(ReadArgs),
+    /// Extract the entries of a backup into a directory.
+    Extract(ExtractArgs),
+    /// Create a backup from a directory.
+    Create(CreateArgs),
+}
+
+#[derive(Debug, Options)]
+struct ReadArgs {
+    /// Show this help message.
+    help: bool,
+    /// Offset of the backup inside the input file (e.g., 0x1e0000 for the spare area of a 2 MiB external flash dump).
+    #[options(meta = "OFFSET", parse(try_from_str = "parse_number"))]
+    offset: Option<usize>,
+    /// Key material for encrypted backups as hex string.
+    #[options(meta = "HEX", parse(try_from_str = "parse_hex"))]
+    key: Option<Vec<u8>>,
+    /// The backup file.
+    #[options(free, required)]
+    backup: HostPathBuf,
+}
+
+#[derive(Debug, Options)]
+struct ExtractArgs {
+    /// Show this help message.
+    help: bool,
+    /// Offset of the backup inside the input file.
+    #[options(meta = "OFFSET", parse(try_from_str = "parse_number"))]
+    offset: Option<usize>,
+    /// Key material for encrypted backups as hex string.
+    #[options(meta = "HEX", parse(try_from_str = "parse_hex"))]
+    key: Option<Vec<u8>>,
+    /// The backup file.
+    #[options(free, required)]
+    backup: HostPathBuf,
+    /// The (new) target directory.
+    #[options(free, required)]
+    target: HostPathBuf,
+}
+
+#[derive(Debug, Options)]
+struct CreateArgs {
+    /// Show this help message.
+    help: bool,
+    /// Maximum size of the backup (default: 128 KiB, the external flash spare area).
+    #[options(meta = "SIZE", parse(try_from_str = "parse_number"))]
+    size: Option<usize>,
+    /// Compress the entries of the backup.
+    compress: bool,
+    /// The source directory.
+    #[options(free, required)]
+    source: HostPathBuf,
+    /// The backup file to write.
+    #[options(free, required)]
+    backup: HostPathBuf,
+}
+
+type Result<T, E = String> = std::result::Result<T, E>;
+
+fn err<E: Debug>(context: impl std::fmt::Display) -> impl FnOnce(E) -> String {
+    move |e| format!("{context}: {e:?}")
+}
+
+fn parse_number(s: &str) -> Result<usize, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".into());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn open_backup(
+    backup: &HostPath,
+    offset: Option<usize>,
+    key: Option<&[u8]>,
+) -> Result<MemoryBackend> {
+    let mut data = fs::read(backup).map_err(err(backup.display()))?;
+    let offset = offset.unwrap_or_default();
+    if offset > data.len() {
+        return Err(format!("offset {offset} is beyond the end of the input"));
+    }
+    data.drain(..offset);
+    // the salt is read from the backup itself
+    let key = key.map(|key| BackupKey::derive(key, [0; SALT_SIZE]));
+    Ok(MemoryBackend::new(data, key))
+}
+
+/// call `f` for all records of the backup, legacy entries are converted into records
+fn for_each_record(
+    backend: &mut MemoryBackend,
+    mut f: impl FnMut(&FSRecord) -> Result<()>,
+) -> Result<Option<BackupHeader>> {
+    let mut state = backend.read_start().map_err(err("invalid backup"))?;
+    let header = state.header().cloned();
+    let streamed = header.as_ref().is_some_and(|h| h.format_version >= 2);
+    loop {
+        let next = if streamed {
+            backend.read_next(&mut state).map(|record| vec![record])
+        } else {
+            backend.read_next_blob(&mut state).map(blob_to_records)
+        };
+        let records = match next {
+            Ok(records) => records,
+            Err(FSBackupError::EndOfBackupBlobs) => break,
+            Err(e) => return Err(format!("failed to read backup: {e:?}")),
+        };
+        for record in &records {
+            f(record)?;
+        }
+    }
+    Ok(header)
+}
+
+fn blob_to_records(blob: FSEntryBlob) -> Vec<FSRecord> {
+    if blob.is_dir {
+        return vec![FSRecord::Dir {
+            path: blob.path,
+            attr: blob.attr,
+        }];
+    }
+    let content = blob.content.unwrap_or_default();
+    let mut records = vec![FSRecord::File {
+        path: blob.path,
+        attr: blob.attr,
+        len: content.len() as u32,
+    }];
+    for chunk in content.chunks(CHUNK_SIZE) {
+        records.push(FSRecord::Chunk(chunk.try_into().unwrap()));
+    }
+    records
+}
+
+fn record_path(path: &[u8]) -> Result<&str> {
+    Path::from_bytes_with_nul(path)
+        .map(|path| path.as_str())
+        .map_err(err("invalid path"))
+}
+
+fn format_attr(attr: Option<&UserAttribute>) -> String {
+    attr.map(|attr| {
+        let hex: String = attr.iter().map(|b| format!("{b:02x}")).collect();
+        format!(" [attr: {hex}]")
+    })
+    .unwrap_or_default()
+}
+
+fn list(args: ReadArgs) -> Result<()> {
+    let mut backend = open_backup(&args.backup, args.offset, args.key.as_deref())?;
+    let header = for_each_record(&mut backend, |record| {
+        match record {
+            FSRecord::Dir { path, attr } => {
+                println!(
+                    "d {:>8} {}/{}",
+                    "-",
+                    record_path(path)?,
+                    format_attr(attr.as_ref())
+                )
+            }
+            FSRecord::File { path, attr, len } => {
+                println!(
+                    "f {len:>8} {}{}",
+                    record_path(path)?,
+                    format_attr(attr.as_ref())
+                )
+            }
+            FSRecord::Chunk(_) => {}
+        }
+        Ok(())
+    })?;
+
+    match header {
+        Some(header) => {
+            let version = header.firmware_version;
+            println!(
+                "format version {}, firmware version {}.{}.{}, {} dirs, {} files, {} bytes{}",
+                header.format_version,
+                version >> 22,
+                (version >> 6) & 0xffff,
+                version & 0x3f,
+                header.dirs,
+                header.files,
+                header.total_len,
+                if header.encrypted { ", encrypted" } else { "" },
+            );
+        }
+        None => println!("legacy backup (no header)"),
+    }
+    Ok(())
+}
+
+fn write_attr(path: &HostPath, attr: Option<&UserAttribute>) -> Result<()> {
+    if let Some(attr) = attr {
+        let mut attr_path = path.as_os_str().to_owned();
+        attr_path.push(ATTR_SUFFIX);
+        fs::write(&attr_path, attr).map_err(err(path.display()))?;
+    }
+    Ok(())
+}
+
+/// map the path of a record into `target`, refusing paths that would leave it
+fn host_path(target: &HostPath, path: &[u8]) -> Result<HostPathBuf> {
+    let path = record_path(path)?;
+    let relative = HostPath::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(format!("invalid path in backup: {path}"));
+    }
+    Ok(target.join(relative))
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let mut backend = open_backup(&args.backup, args.offset, args.key.as_deref())?;
+    if args.target.exists() {
+        return Err(format!("{} already exists", args.target.display()));
+    }
+    fs::create_dir_all(&args.target).map_err(err(args.target.display()))?;
+
+    let mut file: Option<File> = None;
+    for_each_record(&mut backend, |record| {
+        match record {
+            FSRecord::Dir { path, attr } => {
+                let path = host_path(&args.target, path)?;
+                fs::create_dir_all(&path).map_err(err(path.display()))?;
+                // the attribute of the root directory would end up outside of the target and
+                // `create` could not back it up anyway
+                if path != args.target {
+                    write_attr(&path, attr.as_ref())?;
+                }
+            }
+            FSRecord::File { path, attr, .. } => {
+                let path = host_path(&args.target, path)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).map_err(err(parent.display()))?;
+                }
+                file = Some(
+                    OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .map_err(err(path.display()))?,
+                );
+                write_attr(&path, attr.as_ref())?;
+            }
+            FSRecord::Chunk(data) => {
+                let file = file.as_mut().ok_or("chunk without file")?;
+                file.write_all(data).map_err(err("failed to write chunk"))?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// copy the host directory `source` recursively into `fs` at `target`
+fn copy_dir(fs: &Filesystem<RamStorage>, source: &HostPath, target: &Path) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(source)
+        .map_err(err(source.display()))?
+        .collect::<Result<_, _>>()
+        .map_err(err(source.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| format!("invalid file name: {name:?}"))?;
+        if name.ends_with(ATTR_SUFFIX) {
+            continue;
+        }
+        let host_path = entry.path();
+        let path = target.join(&PathBuf::try_from(name).map_err(err(name))?);
+
+        if host_path.is_dir() {
+            fs.create_dir(&path).map_err(err(&path))?;
+            copy_dir(fs, &host_path, &path)?;
+        } else {
+            let data = fs::read(&host_path).map_err(err(host_path.display()))?;
+            fs.write(&path, &data).map_err(err(&path))?;
+        }
+
+        let mut attr_path = host_path.into_os_string();
+        attr_path.push(ATTR_SUFFIX);
+        if let Ok(attr) = fs::read(&attr_path) {
+            fs.set_attribute(&path, USER_ATTRIBUTE_NUMBER, &attr)
+                .map_err(err(&path))?;
+        }
+    }
+    Ok(())
+}
+
+fn create(args: CreateArgs) -> Result<()> {
+    let mut storage = RamStorage::default();
+    Filesystem::format(&mut storage).map_err(err("failed to format filesystem"))?;
+    let mut alloc = Filesystem::allocate();
+    let fs =
+        Filesystem::mount(&mut alloc, &mut storage).map_err(err("failed to mount filesystem"))?;
+    copy_dir(&fs, &args.source, &PathBuf::from(littlefs2::path!("/")))?;
+
+    let mut backend = MemoryBackend::erased(args.size.unwrap_or(128 * 1024), args.compress);
+    let (dirs, files) = backend.backup(&fs).map_err(err("backup failed"))?;
+    fs::write(&args.backup, backend.into_data()).map_err(err(args.backup.display()))?;
+    println!(
+        "wrote {dirs} dirs and {files} files to {}",
+        args.backup.display()
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse_args_default_or_exit();
+    let result = match args.command {
+        Some(Command::List(args)) => list(args),
+        Some(Command::Extract(args)) => extract(args),
+        Some(Command::Create(args)) => create(args),
+        None => Err(format!(
+            "{}\n\nAvailable commands:\n{}",
+            Args::usage(),
+            Args::command_list().unwrap_or_default()
+        )),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a fresh directory below the system temp directory
+    fn temp_dir(name: &str) -> HostPathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("lfs-backup-tool-{}-{name}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn host_paths() {
+        let target = HostPath::new("/tmp/target");
+        assert_eq!(host_path(target, b"/\0").unwrap(), target);
+        assert_eq!(
+            host_path(target, b"/fido/dat/rk\0").unwrap(),
+            target.join("fido/dat/rk")
+        );
+        for path in [
+            &b"/..\0"[..],
+            b"/fido/../../etc\0",
+            b"/./fido\0",
+            b"/fido/..\0",
+        ] {
+            assert!(host_path(target, path).is_err(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn create_and_extract() {
+        let source = temp_dir("source");
+        fs::create_dir_all(source.join("fido/dat")).unwrap();
+        fs::create_dir_all(source.join("empty")).unwrap();
+        fs::write(source.join("fido/dat/rk"), [0x42; 300]).unwrap();
+        fs::write(source.join("fido/dat/rk.user-attr"), [1, 2, 3]).unwrap();
+        fs::write(source.join("version"), b"1").unwrap();
+
+        let backup = temp_dir("backup");
+        create(CreateArgs {
+            help: false,
+            size: Some(16 * 1024),
+            compress: true,
+            source: source.clone(),
+            backup: backup.clone(),
+        })
+        .unwrap();
+
+        let target = temp_dir("target");
+        extract(ExtractArgs {
+            help: false,
+            offset: None,
+            key: None,
+            backup: backup.clone(),
+            target: target.clone(),
+        })
+        .unwrap();
+
+        assert!(target.join("empty").is_dir());
+        assert_eq!(fs::read(target.join("fido/dat/rk")).unwrap(), [0x42; 300]);
+        assert_eq!(
+            fs::read(target.join("fido/dat/rk.user-attr")).unwrap(),
+            [1, 2, 3]
+        );
+        assert_eq!(fs::read(target.join("version")).unwrap(), b"1");
+
+        // the target must not exist yet
+        let args = ExtractArgs {
+            help: false,
+            offset: None,
+            key: None,
+            backup: backup.clone(),
+            target: target.clone(),
+        };
+        assert!(extract(args).is_err());
+
+        for dir in [&source, &target] {
+            fs::remove_dir_all(dir).unwrap();
+        }
+        fs::remove_file(&backup).unwrap();
+    }
+}
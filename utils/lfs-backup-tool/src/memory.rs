@@ -0,0 +1,144 @@
+use lfs_backup::{BackupBackend, BackupKey, FSBackupError, Result};
+use littlefs2::{
+    consts::{U512, U8},
+    driver::Storage,
+};
+use trussed_core::types::Bytes;
+
+const ERASED: u8 = 0xff;
+
+/// `BackupBackend` operating on a backup blob held in memory
+pub struct MemoryBackend {
+    data: Vec<u8>,
+    offset: usize,
+    end: usize,
+    key: Option<BackupKey>,
+    compress: bool,
+}
+
+impl MemoryBackend {
+    /// backend for reading the backup inside `data`
+    pub fn new(data: Vec<u8>, key: Option<BackupKey>) -> Self {
+        let end = data.len();
+        Self {
+            data,
+            offset: 0,
+            end,
+            key,
+            compress: false,
+        }
+    }
+
+    /// empty (erased) backend for writing a backup of at most `len` bytes
+    pub fn erased(len: usize, compress: bool) -> Self {
+        Self {
+            data: vec![ERASED; len],
+            offset: 0,
+            end: 0,
+            key: None,
+            compress,
+        }
+    }
+
+    /// the written backup data
+    pub fn into_data(mut self) -> Vec<u8> {
+        self.data.truncate(self.end);
+        self.data
+    }
+
+    fn padded(len: usize) -> usize {
+        len.div_ceil(Self::RW_SIZE) * Self::RW_SIZE
+    }
+}
+
+impl BackupBackend for MemoryBackend {
+    // same as the external flash backend of the NK3AM migration
+    const RW_SIZE: usize = 256;
+
+    fn read<const N: usize>(&mut self, len: usize) -> Result<Bytes<N>> {
+        let data = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or(FSBackupError::BackendReadErr)?;
+        let output = Bytes::from_slice(data).map_err(|_| FSBackupError::BackendReadErr)?;
+        self.offset += Self::padded(len);
+        Ok(output)
+    }
+
+    fn write(&mut self, content: &[u8]) -> Result<usize> {
+        let target = self
+            .data
+            .get_mut(self.offset..self.offset + content.len())
+            .ok_or(FSBackupError::BackendWriteErr)?;
+        target.copy_from_slice(content);
+        self.offset += Self::padded(content.len());
+        self.end = self.end.max(self.offset.min(self.data.len()));
+        Ok(content.len())
+    }
+
+    fn erase(&mut self) -> Result<usize> {
+        self.data.fill(ERASED);
+        self.offset = 0;
+        self.end = 0;
+        Ok(self.data.len())
+    }
+
+    fn reset(&mut self) {
+        self.offset = 0;
+    }
+
+    fn position(&self) -> usize {
+        self.offset
+    }
+
+    fn seek(&mut self, position: usize) {
+        self.offset = position;
+    }
+
+    fn key(&self) -> Option<&BackupKey> {
+        self.key.as_ref()
+    }
+
+    fn compression(&self) -> bool {
+        self.compress
+    }
+}
+
+/// RAM storage for the littlefs filesystem used to assemble a backup
+pub struct RamStorage {
+    buf: Vec<u8>,
+}
+
+impl Default for RamStorage {
+    fn default() -> Self {
+        Self {
+            buf: vec![ERASED; Self::BLOCK_SIZE * Self::BLOCK_COUNT],
+        }
+    }
+}
+
+impl Storage for RamStorage {
+    const READ_SIZE: usize = 4;
+    const WRITE_SIZE: usize = 4;
+    const BLOCK_SIZE: usize = 512;
+    const BLOCK_COUNT: usize = 4096;
+    const BLOCK_CYCLES: isize = -1;
+
+    type CACHE_SIZE = U512;
+    type LOOKAHEAD_SIZE = U8;
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> littlefs2::io::Result<usize> {
+        buffer.copy_from_slice(&self.buf[offset..offset + buffer.len()]);
+        Ok(buffer.len())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> littlefs2::io::Result<usize> {
+        self.buf[offset..offset + data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, offset: usize, len: usize) -> littlefs2::io::Result<usize> {
+        self.buf[offset..offset + len].fill(ERASED);
+        Ok(len)
+    }
+}